@@ -74,6 +74,9 @@
 #![doc(html_root_url = "https://docs.rs/automaat-core/0.1.0")]
 
 use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use std::{error, fmt, io, path};
 use tempfile::{tempdir, TempDir};
 
@@ -114,10 +117,18 @@ pub trait Processor<'de>: fmt::Debug + Serialize + Deserialize<'de> {
 /// for any required shared state.
 ///
 /// At the moment, it is used to provide a shared location on the local
-/// file system to store and retrieve data from.
+/// file system to store and retrieve data from, to signal processors that
+/// their run has been cancelled, to set a deadline before which their run
+/// has to finish, and to let processors write output while they are running.
+///
+/// Use [`Context::fork`] to run multiple processors in parallel, sharing the
+/// same workspace.
 #[derive(Debug)]
 pub struct Context {
-    workspace: TempDir,
+    workspace: Arc<TempDir>,
+    cancelled: Arc<AtomicBool>,
+    deadline: Option<Instant>,
+    output_sink: Option<Arc<dyn OutputSink>>,
 }
 
 impl Context {
@@ -130,14 +141,174 @@ impl Context {
     /// returned. Specifically the `ContextError::Io` variant.
     pub fn new() -> Result<Self, ContextError> {
         Ok(Self {
-            workspace: tempdir()?,
+            workspace: Arc::new(tempdir()?),
+            cancelled: Arc::new(AtomicBool::new(false)),
+            deadline: None,
+            output_sink: None,
         })
     }
 
+    /// Create a new `Context` object that shares the workspace and
+    /// cancellation state of this context.
+    ///
+    /// The new context has its own deadline and output sink (initially the
+    /// same as those of this context), which allows processors using the
+    /// forked contexts to run in parallel.
+    ///
+    /// The workspace is removed once both contexts are dropped.
+    pub fn fork(&self) -> Self {
+        Self {
+            workspace: Arc::clone(&self.workspace),
+            cancelled: Arc::clone(&self.cancelled),
+            deadline: self.deadline,
+            output_sink: self.output_sink.clone(),
+        }
+    }
+
     /// Returns a [`std::path::Path`] reference to the shared workspace.
     pub fn workspace_path(&self) -> &path::Path {
         self.workspace.path()
     }
+
+    /// Returns `true` if the processor runs using this context have been
+    /// cancelled.
+    ///
+    /// Long-running processors are expected to check this value periodically,
+    /// and stop their work as soon as possible once it returns `true`.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Returns a [`Canceller`] that can be used to cancel any processor runs
+    /// using this context.
+    ///
+    /// The canceller can be moved to a different thread, to cancel a processor
+    /// while it is running.
+    pub fn canceller(&self) -> Canceller {
+        Canceller(Arc::clone(&self.cancelled))
+    }
+
+    /// Set the deadline before which processor runs using this context have
+    /// to finish.
+    ///
+    /// Setting the deadline to `None` removes any existing deadline.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline
+    }
+
+    /// Returns the deadline of the context, if any.
+    pub const fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Returns `true` if the deadline of the context has passed.
+    ///
+    /// Similar to [`Context::is_cancelled`], long-running processors are
+    /// expected to check this value periodically, and stop their work as soon
+    /// as possible once it returns `true`.
+    pub fn is_timed_out(&self) -> bool {
+        self.deadline
+            .map_or(false, |deadline| Instant::now() >= deadline)
+    }
+
+    /// Returns the time left before the deadline of the context passes, or
+    /// `None` if the context has no deadline.
+    ///
+    /// This is useful for processors that rely on libraries supporting their
+    /// own timeout configuration.
+    pub fn time_remaining(&self) -> Option<Duration> {
+        let now = Instant::now();
+
+        self.deadline.map(|deadline| {
+            if deadline > now {
+                deadline - now
+            } else {
+                Duration::from_secs(0)
+            }
+        })
+    }
+
+    /// Set the sink to which processor runs using this context write their
+    /// output while they are running.
+    ///
+    /// Setting the sink to `None` discards any output written afterwards.
+    pub fn set_output_sink(&mut self, sink: Option<Arc<dyn OutputSink>>) {
+        self.output_sink = sink
+    }
+
+    /// Write a single line of output to the output sink of the context.
+    ///
+    /// If the context has no output sink, the line is discarded.
+    ///
+    /// This does not replace the output returned by [`Processor::run`], but
+    /// allows long-running processors to report their progress before they
+    /// finish.
+    pub fn write_output(&self, stream: OutputStream, line: &str) {
+        self.output_writer().write_line(stream, line)
+    }
+
+    /// Returns an [`OutputWriter`] that can be used to write output to the
+    /// output sink of this context.
+    ///
+    /// The writer can be moved to a different thread, to write output while
+    /// the processor is busy with other work.
+    pub fn output_writer(&self) -> OutputWriter {
+        OutputWriter(self.output_sink.clone())
+    }
+}
+
+/// A handle to cancel the processor runs of a [`Context`].
+///
+/// Use [`Context::canceller`] to get a canceller for an existing context.
+#[derive(Clone, Debug)]
+pub struct Canceller(Arc<AtomicBool>);
+
+impl Canceller {
+    /// Mark the context as cancelled.
+    ///
+    /// Once cancelled, a context cannot be "un-cancelled".
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst)
+    }
+}
+
+/// The stream to which a line of output is written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputStream {
+    /// The standard output of a processor.
+    Stdout,
+
+    /// The error output of a processor.
+    Stderr,
+}
+
+/// A destination for the output written by processors while they are running.
+///
+/// Implement this trait to receive the output written to a [`Context`], for
+/// example to store it, or to show it to a user while the processor runs.
+pub trait OutputSink: fmt::Debug + Send + Sync {
+    /// Receive a single line of output, without its trailing newline.
+    ///
+    /// This method is called from the thread writing the output, so it
+    /// should not block for long.
+    fn write_line(&self, stream: OutputStream, line: &str);
+}
+
+/// A handle to write output to the [`OutputSink`] of a [`Context`].
+///
+/// Use [`Context::output_writer`] to get a writer for an existing context.
+#[derive(Clone, Debug)]
+pub struct OutputWriter(Option<Arc<dyn OutputSink>>);
+
+impl OutputWriter {
+    /// Write a single line of output.
+    ///
+    /// If the context has no output sink, the line is discarded.
+    pub fn write_line(&self, stream: OutputStream, line: &str) {
+        if let Some(sink) = &self.0 {
+            sink.write_line(stream, line)
+        }
+    }
 }
 
 /// Represents all the ways that a [`Context`] can fail.
@@ -188,6 +359,91 @@ mod tests {
         assert!(context.workspace_path().exists())
     }
 
+    #[test]
+    fn test_context_fork() {
+        let mut context = Context::new().unwrap();
+        context.set_deadline(Some(Instant::now() + Duration::from_secs(60)));
+
+        let mut fork = context.fork();
+        assert_eq!(fork.workspace_path(), context.workspace_path());
+        assert_eq!(fork.deadline(), context.deadline());
+
+        fork.set_deadline(None);
+        assert!(context.deadline().is_some());
+
+        context.canceller().cancel();
+        assert!(fork.is_cancelled());
+
+        let path = context.workspace_path().to_owned();
+        drop(context);
+        assert!(path.exists());
+
+        drop(fork);
+        assert!(!path.exists())
+    }
+
+    #[test]
+    fn test_context_cancel() {
+        let context = Context::new().unwrap();
+        assert!(!context.is_cancelled());
+
+        let canceller = context.canceller();
+        std::thread::spawn(move || canceller.cancel())
+            .join()
+            .unwrap();
+
+        assert!(context.is_cancelled())
+    }
+
+    #[test]
+    fn test_context_deadline() {
+        let mut context = Context::new().unwrap();
+        assert!(!context.is_timed_out());
+        assert_eq!(context.time_remaining(), None);
+
+        context.set_deadline(Some(Instant::now() + Duration::from_secs(60)));
+        assert!(!context.is_timed_out());
+        assert!(context.time_remaining().unwrap() > Duration::from_secs(0));
+
+        context.set_deadline(Some(Instant::now()));
+        assert!(context.is_timed_out());
+        assert_eq!(context.time_remaining(), Some(Duration::from_secs(0)));
+    }
+
+    #[test]
+    fn test_context_output() {
+        use std::sync::Mutex;
+
+        #[derive(Debug, Default)]
+        struct Sink(Mutex<Vec<(OutputStream, String)>>);
+
+        impl OutputSink for Sink {
+            fn write_line(&self, stream: OutputStream, line: &str) {
+                self.0.lock().unwrap().push((stream, line.to_owned()))
+            }
+        }
+
+        let mut context = Context::new().unwrap();
+        context.write_output(OutputStream::Stdout, "discarded");
+
+        let sink = Arc::new(Sink::default());
+        context.set_output_sink(Some(sink.clone()));
+
+        let writer = context.output_writer();
+        std::thread::spawn(move || writer.write_line(OutputStream::Stderr, "world"))
+            .join()
+            .unwrap();
+        context.write_output(OutputStream::Stdout, "hello");
+
+        assert_eq!(
+            *sink.0.lock().unwrap(),
+            vec![
+                (OutputStream::Stderr, "world".to_owned()),
+                (OutputStream::Stdout, "hello".to_owned()),
+            ]
+        );
+    }
+
     #[test]
     fn test_readme_deps() {
         version_sync::assert_markdown_deps_updated!("README.md");
@@ -307,11 +307,23 @@ impl<'a> Processor<'a> for HttpRequest {
     ///
     /// If the response status does not match one of the provided status
     /// assertions, the [`Error::Status`] error variant is returned.
-    fn run(&self, _context: &Context) -> Result<Option<Self::Output>, Self::Error> {
+    ///
+    /// If the [`Context`] has a deadline, the request times out once the
+    /// deadline passes, in which case the [`Error::Response`] error variant is
+    /// returned.
+    fn run(&self, context: &Context) -> Result<Option<Self::Output>, Self::Error> {
         self.validate()?;
 
+        // client
+        let mut client = Client::builder();
+        if let Some(timeout) = context.time_remaining() {
+            client = client.timeout(timeout);
+        }
+
         // request builder
-        let mut request = Client::new().request(self.method.into(), self.url.as_str());
+        let mut request = client
+            .build()?
+            .request(self.method.into(), self.url.as_str());
 
         // headers
         let mut map = header::HeaderMap::new();
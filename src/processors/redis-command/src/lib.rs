@@ -140,6 +140,9 @@ impl<'a> Processor<'a> for RedisCommand {
     /// values are correctly mapped, such as `Nil` becoming `None`, and all
     /// valid UTF-8 data is returned as `Some`, containing the data as a string.
     ///
+    /// Commands returning multiple values (such as `SMEMBERS`) return each
+    /// value on its own line.
+    ///
     /// Any value that cannot be coerced into a valid UTF-8 string, is
     /// represented in the best possible way as a valid UTF-8 string, but won't
     /// completely match the original output of Redis.
@@ -149,8 +152,6 @@ impl<'a> Processor<'a> for RedisCommand {
     /// See the [`Error`] enum for all possible error values that can be
     /// returned. These values wrap the [`redis::ErrorKind`] values.
     fn run(&self, _context: &Context) -> Result<Option<Self::Output>, Self::Error> {
-        use redis::Value;
-
         let url = Url::from_str(&self.url)?;
         let client = redis::Client::open(url.as_str())?;
         let conn = client.get_connection()?;
@@ -160,15 +161,30 @@ impl<'a> Processor<'a> for RedisCommand {
             .arg(args)
             .query(&conn)
             .map_err(Into::into)
-            .map(|v| match v {
-                Value::Nil => None,
-                Value::Status(string) => Some(string),
-                Value::Data(ref val) => match from_utf8(val) {
-                    Ok(string) => Some(string.to_owned()),
-                    Err(_) => Some(format!("{:?}", val)),
-                },
-                other => Some(format!("{:?}", other)),
-            })
+            .map(output)
+    }
+}
+
+/// Convert the value returned by Redis into the output of the processor.
+fn output(value: redis::Value) -> Option<String> {
+    use redis::Value;
+
+    match value {
+        Value::Nil => None,
+        Value::Status(string) => Some(string),
+        Value::Int(int) => Some(int.to_string()),
+        Value::Data(ref val) => match from_utf8(val) {
+            Ok(string) => Some(string.to_owned()),
+            Err(_) => Some(format!("{:?}", val)),
+        },
+        Value::Bulk(values) => Some(
+            values
+                .into_iter()
+                .filter_map(output)
+                .collect::<Vec<_>>()
+                .join("\n"),
+        ),
+        other => Some(format!("{:?}", other)),
     }
 }
 
@@ -324,6 +340,20 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_output() {
+        use redis::Value;
+
+        let value = Value::Bulk(vec![
+            Value::Data(b"hello".to_vec()),
+            Value::Nil,
+            Value::Int(42),
+        ]);
+
+        assert_eq!(output(value), Some("hello\n42".to_owned()));
+        assert_eq!(output(Value::Nil), None);
+    }
+
     #[test]
     fn test_readme_deps() {
         version_sync::assert_markdown_deps_updated!("README.md");
@@ -9,6 +9,14 @@
 //! If the shell command returns a non-zero exit code, the processor returns the
 //! _stderr_ output as its error value.
 //!
+//! If the [`Context`] is cancelled, or its deadline passes while the command is
+//! running, the command is killed, and an error is returned. On Unix, the
+//! command runs in its own process group, and any process it started is
+//! killed along with it.
+//!
+//! While the command is running, each line it writes to _stdout_ or _stderr_
+//! is written to the output sink of the [`Context`].
+//!
 //! All commands are executed within the [`Context`] workspace.
 //!
 //! [Automaat]: automaat_core
@@ -71,11 +79,15 @@
 #![allow(clippy::multiple_crate_versions, missing_doc_code_examples)]
 #![doc(html_root_url = "https://docs.rs/automaat-processor-shell-command/0.1.0")]
 
-use automaat_core::{Context, Processor};
+use automaat_core::{Context, OutputStream, OutputWriter, Processor};
 use serde::{Deserialize, Serialize};
-use std::io::Write;
-use std::process::{Command, Stdio};
-use std::{env, error, fmt, io, path};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::{env, error, fmt, io, path, thread, time};
+
+/// The interval at which a running command is checked for completion or
+/// cancellation.
+const POLL_INTERVAL: time::Duration = time::Duration::from_millis(10);
 
 /// The processor configuration.
 #[cfg_attr(feature = "juniper", derive(juniper::GraphQLObject))]
@@ -210,6 +222,12 @@ impl<'a> Processor<'a> for ShellCommand {
     /// If the run fails, an [`Error`] result value is returned. The variant can
     /// differ, depending on if the command itself failed, some IO error
     /// happened, or the configuration is invalid.
+    ///
+    /// If the [`Context`] is cancelled while the command is running, the command
+    /// is killed, and [`Error::Cancelled`] is returned.
+    ///
+    /// If the deadline of the [`Context`] passes while the command is running,
+    /// the command is killed, and [`Error::Timeout`] is returned.
     fn run(&self, context: &Context) -> Result<Option<Self::Output>, Self::Error> {
         self.validate()?;
 
@@ -240,18 +258,40 @@ impl<'a> Processor<'a> for ShellCommand {
             .env("PATH", env::join_paths(path)?)
             .args(arguments);
 
-        let output = if let Some(input) = &self.stdin {
-            let mut spawn = command
-                .stdin(Stdio::piped())
-                .stdout(Stdio::piped())
-                .stderr(Stdio::piped())
-                .spawn()?;
+        // Processes started by the command inherit its process group, so they
+        // can be killed together with the command.
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            let _ = command.process_group(0);
+        }
 
-            spawn.stdin.as_mut().unwrap().write_all(input.as_bytes())?;
-            spawn.wait_with_output()
-        } else {
-            command.output()
-        }?;
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        // Dropping the stdin handle closes it, signaling the end of the input
+        // to the command.
+        if let Some(mut stdin) = child.stdin.take() {
+            if let Some(input) = &self.stdin {
+                stdin.write_all(input.as_bytes())?;
+            }
+        }
+
+        // The output is read in separate threads, to prevent the command from
+        // blocking on a full pipe buffer while we wait for it to exit.
+        let writer = context.output_writer();
+        let stdout = read_pipe(child.stdout.take(), writer.clone(), OutputStream::Stdout);
+        let stderr = read_pipe(child.stderr.take(), writer, OutputStream::Stderr);
+
+        let status = wait(&mut child, context)?;
+        let output = Output {
+            status,
+            stdout: join_pipe(stdout)?,
+            stderr: join_pipe(stderr)?,
+        };
 
         if !output.status.success() {
             if output.stderr.is_empty() {
@@ -279,6 +319,108 @@ impl<'a> Processor<'a> for ShellCommand {
     }
 }
 
+/// The collected output of a finished command.
+struct Output {
+    status: ExitStatus,
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+}
+
+/// Wait for the child process to exit.
+///
+/// If the context is cancelled before the process exits, the process is killed
+/// and [`Error::Cancelled`] is returned. Similarly, if the context deadline
+/// passes, the process is killed and [`Error::Timeout`] is returned.
+///
+/// See [`kill`] for details on how the process is killed.
+fn wait(child: &mut Child, context: &Context) -> Result<ExitStatus, Error> {
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+
+        let error = if context.is_cancelled() {
+            Some(Error::Cancelled)
+        } else if context.is_timed_out() {
+            Some(Error::Timeout)
+        } else {
+            None
+        };
+
+        if let Some(error) = error {
+            kill(child)?;
+            let _ = child.wait()?;
+
+            return Err(error);
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Kill the child process, and all processes in its process group.
+///
+/// Killing the whole group makes sure no process started by the command keeps
+/// running, holding on to the output pipes of the command.
+#[cfg(unix)]
+fn kill(child: &mut Child) -> io::Result<()> {
+    use nix::sys::signal::{killpg, Signal};
+    use nix::unistd::Pid;
+    use std::convert::TryFrom;
+
+    // The ID of the process group matches the ID of the child process.
+    match i32::try_from(child.id()) {
+        Ok(id) if killpg(Pid::from_raw(id), Signal::SIGKILL).is_ok() => Ok(()),
+        _ => child.kill(),
+    }
+}
+
+/// Kill the child process.
+#[cfg(not(unix))]
+fn kill(child: &mut Child) -> io::Result<()> {
+    child.kill()
+}
+
+/// Read all data from a (optional) child process pipe in a separate thread.
+///
+/// Each line is written to the provided output writer as soon as it is read.
+fn read_pipe<R>(
+    pipe: Option<R>,
+    writer: OutputWriter,
+    stream: OutputStream,
+) -> thread::JoinHandle<io::Result<Vec<u8>>>
+where
+    R: Read + Send + 'static,
+{
+    thread::spawn(move || {
+        let mut buffer = vec![];
+        let mut pipe = match pipe {
+            None => return Ok(buffer),
+            Some(pipe) => BufReader::new(pipe),
+        };
+
+        loop {
+            let start = buffer.len();
+            if pipe.read_until(b'\n', &mut buffer)? == 0 {
+                return Ok(buffer);
+            }
+
+            let line = buffer.get(start..).unwrap_or_default();
+            let line = strip_ansi_escapes::strip(line).unwrap_or_else(|_| line.to_vec());
+
+            writer.write_line(stream, String::from_utf8_lossy(&line).trim_end());
+        }
+    })
+}
+
+/// Wait for a pipe reading thread to finish, and return the read data.
+fn join_pipe(handle: thread::JoinHandle<io::Result<Vec<u8>>>) -> Result<Vec<u8>, Error> {
+    handle
+        .join()
+        .map_err(|_| io::Error::new(io::ErrorKind::Other, "unable to read command output"))?
+        .map_err(Into::into)
+}
+
 /// Represents all the ways that [`ShellCommand`] can fail.
 ///
 /// This type is not intended to be exhaustively matched, and new variants may
@@ -292,6 +434,18 @@ pub enum Error {
     /// The string value represents the _stderr_ output of the command.
     Command(String),
 
+    /// The command was killed, because the [`Context`] was cancelled while the
+    /// command was running.
+    ///
+    /// [`Context`]: automaat_core::Context
+    Cancelled,
+
+    /// The command was killed, because the deadline of the [`Context`] passed
+    /// while the command was running.
+    ///
+    /// [`Context`]: automaat_core::Context
+    Timeout,
+
     /// An I/O operation failed.
     ///
     /// This is a wrapper around [`std::io::Error`].
@@ -309,6 +463,8 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match *self {
             Error::Command(ref err) => write!(f, "Command error: {}", err),
+            Error::Cancelled => f.write_str("Command cancelled"),
+            Error::Timeout => f.write_str("Command timed out"),
             Error::Io(ref err) => write!(f, "IO error: {}", err),
             Error::Path(ref err) => write!(f, "Path error: {}", err),
             Error::__Unknown => unreachable!(),
@@ -319,7 +475,7 @@ impl fmt::Display for Error {
 impl error::Error for Error {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match *self {
-            Error::Command(_) | Error::Path(_) => None,
+            Error::Command(_) | Error::Cancelled | Error::Timeout | Error::Path(_) => None,
             Error::Io(ref err) => Some(err),
             Error::__Unknown => unreachable!(),
         }
@@ -437,6 +593,122 @@ mod tests {
             )
         }
 
+        #[test]
+        fn test_command_cancelled() {
+            let mut processor = processor_stub();
+            processor.command = "sleep".to_owned();
+            processor.arguments = Some(vec!["10".to_owned()]);
+
+            let context = Context::new().unwrap();
+            let canceller = context.canceller();
+            let _ = thread::spawn(move || {
+                thread::sleep(time::Duration::from_millis(100));
+                canceller.cancel()
+            });
+
+            let start = time::Instant::now();
+            let error = processor.run(&context).unwrap_err();
+
+            assert_eq!(error.to_string(), "Command cancelled".to_owned());
+            assert!(start.elapsed() < time::Duration::from_secs(5));
+        }
+
+        #[test]
+        fn test_command_timed_out() {
+            let mut processor = processor_stub();
+            processor.command = "sleep".to_owned();
+            processor.arguments = Some(vec!["10".to_owned()]);
+
+            let mut context = Context::new().unwrap();
+            let deadline = time::Instant::now() + time::Duration::from_millis(100);
+            context.set_deadline(Some(deadline));
+
+            let start = time::Instant::now();
+            let error = processor.run(&context).unwrap_err();
+
+            assert_eq!(error.to_string(), "Command timed out".to_owned());
+            assert!(start.elapsed() < time::Duration::from_secs(5));
+        }
+
+        #[test]
+        #[cfg(unix)]
+        fn test_command_cancelled_kills_started_processes() {
+            use automaat_core::OutputSink;
+            use std::sync::Arc;
+
+            #[derive(Debug)]
+            struct Sink;
+
+            impl OutputSink for Sink {
+                fn write_line(&self, _: OutputStream, _: &str) {}
+            }
+
+            let mut processor = processor_stub();
+            processor.command = "sh".to_owned();
+            processor.arguments = Some(vec!["-c".to_owned(), "sleep 10 & sleep 10".to_owned()]);
+
+            let sink: Arc<dyn OutputSink> = Arc::new(Sink);
+            let mut context = Context::new().unwrap();
+            context.set_output_sink(Some(sink.clone()));
+
+            let canceller = context.canceller();
+            let _ = thread::spawn(move || {
+                thread::sleep(time::Duration::from_millis(100));
+                canceller.cancel()
+            });
+
+            let error = processor.run(&context).unwrap_err();
+            assert_eq!(error.to_string(), "Command cancelled".to_owned());
+
+            // The output pipes close once the background process is killed,
+            // after which the threads reading them release the sink.
+            let start = time::Instant::now();
+            while Arc::strong_count(&sink) > 2 {
+                assert!(start.elapsed() < time::Duration::from_secs(5));
+                thread::sleep(POLL_INTERVAL);
+            }
+        }
+
+        #[test]
+        fn test_command_streams_output() {
+            use automaat_core::OutputSink;
+            use std::sync::{Arc, Mutex};
+
+            #[derive(Debug, Default)]
+            struct Sink(Mutex<Vec<(OutputStream, String)>>);
+
+            impl OutputSink for Sink {
+                fn write_line(&self, stream: OutputStream, line: &str) {
+                    self.0.lock().unwrap().push((stream, line.to_owned()))
+                }
+            }
+
+            let mut processor = processor_stub();
+            processor.command = "sh".to_owned();
+            processor.arguments = Some(vec![
+                "-c".to_owned(),
+                "echo hello; echo world; echo oops >&2".to_owned(),
+            ]);
+
+            let sink = Arc::new(Sink::default());
+            let mut context = Context::new().unwrap();
+            context.set_output_sink(Some(sink.clone()));
+
+            let output = processor.run(&context).unwrap().expect("Some");
+            let lines = sink.0.lock().unwrap();
+
+            assert_eq!(output, "hello\nworld".to_owned());
+            assert_eq!(
+                lines
+                    .iter()
+                    .filter(|(stream, _)| *stream == OutputStream::Stdout)
+                    .map(|(_, line)| line.as_str())
+                    .collect::<Vec<_>>(),
+                vec!["hello", "world"]
+            );
+            assert!(lines.contains(&(OutputStream::Stderr, "oops".to_owned())));
+        }
+
         #[test]
         fn test_appending_paths() {
             let mut processor = processor_stub();
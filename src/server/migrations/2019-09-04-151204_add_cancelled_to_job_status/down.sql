@@ -0,0 +1,8 @@
+UPDATE jobs SET status = 'failed' WHERE status = 'cancelled';
+
+ALTER TYPE JobStatus RENAME TO JobStatusOld;
+CREATE TYPE JobStatus AS ENUM ('scheduled', 'pending', 'running', 'failed', 'ok');
+
+ALTER TABLE jobs ALTER COLUMN status TYPE JobStatus USING status::Text::JobStatus;
+
+DROP TYPE JobStatusOld;
@@ -0,0 +1,8 @@
+-- Enum values can't be added within a transaction, so the type is replaced
+-- instead.
+ALTER TYPE JobStatus RENAME TO JobStatusOld;
+CREATE TYPE JobStatus AS ENUM ('scheduled', 'pending', 'running', 'failed', 'cancelled', 'ok');
+
+ALTER TABLE jobs ALTER COLUMN status TYPE JobStatus USING status::Text::JobStatus;
+
+DROP TYPE JobStatusOld;
@@ -0,0 +1,3 @@
+ALTER TABLE jobs ADD COLUMN run_at Timestamp NULL;
+
+CREATE INDEX ON jobs (status, run_at);
@@ -0,0 +1,18 @@
+CREATE TABLE schedules (
+    id          Serial    PRIMARY KEY,
+    expression  VarChar   NOT NULL,
+    next_run_at Timestamp NOT NULL,
+    task_id     Integer   NOT NULL REFERENCES tasks ON DELETE CASCADE
+);
+
+CREATE INDEX ON schedules (task_id);
+CREATE INDEX ON schedules (next_run_at);
+
+CREATE TABLE schedule_variables (
+    id          Serial  PRIMARY KEY,
+    key         VarChar NOT NULL,
+    value       Bytea   NOT NULL,
+    schedule_id Integer NOT NULL REFERENCES schedules ON DELETE CASCADE,
+
+    UNIQUE (key, schedule_id)
+);
@@ -0,0 +1,4 @@
+ALTER TABLE job_steps DROP COLUMN timeout_seconds;
+ALTER TABLE jobs      DROP COLUMN timeout_seconds;
+ALTER TABLE steps     DROP COLUMN timeout_seconds;
+ALTER TABLE tasks     DROP COLUMN timeout_seconds;
@@ -0,0 +1,4 @@
+ALTER TABLE tasks     ADD COLUMN timeout_seconds Integer NULL CHECK (timeout_seconds > 0);
+ALTER TABLE steps     ADD COLUMN timeout_seconds Integer NULL CHECK (timeout_seconds > 0);
+ALTER TABLE jobs      ADD COLUMN timeout_seconds Integer NULL;
+ALTER TABLE job_steps ADD COLUMN timeout_seconds Integer NULL;
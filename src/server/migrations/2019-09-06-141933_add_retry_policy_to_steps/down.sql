@@ -0,0 +1,4 @@
+DROP TABLE job_step_attempts;
+
+ALTER TABLE job_steps DROP COLUMN retry_policy;
+ALTER TABLE steps     DROP COLUMN retry_policy;
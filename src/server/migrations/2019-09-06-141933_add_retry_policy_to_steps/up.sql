@@ -0,0 +1,14 @@
+ALTER TABLE steps     ADD COLUMN retry_policy Jsonb NULL;
+ALTER TABLE job_steps ADD COLUMN retry_policy Jsonb NULL;
+
+CREATE TABLE job_step_attempts (
+    id          Serial        PRIMARY KEY,
+    attempt     Integer       NOT NULL,
+    started_at  Timestamp     NOT NULL,
+    finished_at Timestamp     NOT NULL,
+    status      JobStepStatus NOT NULL,
+    output      Text              NULL,
+    job_step_id Integer       NOT NULL REFERENCES job_steps ON DELETE CASCADE,
+
+    UNIQUE (attempt, job_step_id)
+);
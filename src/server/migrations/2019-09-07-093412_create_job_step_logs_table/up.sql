@@ -0,0 +1,12 @@
+CREATE TYPE JobStepLogStream AS ENUM ('stdout', 'stderr');
+
+CREATE TABLE job_step_logs (
+    id          Serial           PRIMARY KEY,
+    position    Integer          NOT NULL,
+    stream      JobStepLogStream NOT NULL,
+    line        Text             NOT NULL,
+    created_at  Timestamp        NOT NULL DEFAULT NOW(),
+    job_step_id Integer          NOT NULL REFERENCES job_steps ON DELETE CASCADE,
+
+    UNIQUE (position, job_step_id)
+);
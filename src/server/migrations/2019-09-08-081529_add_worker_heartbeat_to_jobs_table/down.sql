@@ -0,0 +1,3 @@
+ALTER TABLE jobs DROP COLUMN status_reason;
+ALTER TABLE jobs DROP COLUMN heartbeat_at;
+ALTER TABLE jobs DROP COLUMN worker_id;
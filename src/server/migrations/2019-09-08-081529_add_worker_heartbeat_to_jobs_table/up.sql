@@ -0,0 +1,5 @@
+ALTER TABLE jobs ADD COLUMN worker_id     Text      NULL;
+ALTER TABLE jobs ADD COLUMN heartbeat_at  Timestamp NULL;
+ALTER TABLE jobs ADD COLUMN status_reason Text      NULL;
+
+CREATE INDEX ON jobs (status, heartbeat_at);
@@ -0,0 +1,2 @@
+ALTER TABLE job_steps DROP COLUMN depends_on;
+ALTER TABLE steps     DROP COLUMN depends_on;
@@ -0,0 +1,2 @@
+ALTER TABLE steps     ADD COLUMN depends_on Text[] NULL;
+ALTER TABLE job_steps ADD COLUMN depends_on Text[] NULL;
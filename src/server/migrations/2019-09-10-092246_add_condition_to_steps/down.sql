@@ -0,0 +1,15 @@
+UPDATE job_steps SET status = 'cancelled' WHERE status = 'skipped';
+
+ALTER TYPE JobStepStatus RENAME TO JobStepStatusOld;
+CREATE TYPE JobStepStatus AS ENUM ('initialized', 'pending', 'running', 'failed', 'cancelled', 'ok');
+
+ALTER TABLE job_steps ALTER COLUMN status DROP DEFAULT;
+ALTER TABLE job_steps ALTER COLUMN status TYPE JobStepStatus USING status::Text::JobStepStatus;
+ALTER TABLE job_steps ALTER COLUMN status SET DEFAULT 'pending';
+
+ALTER TABLE job_step_attempts ALTER COLUMN status TYPE JobStepStatus USING status::Text::JobStepStatus;
+
+DROP TYPE JobStepStatusOld;
+
+ALTER TABLE job_steps DROP COLUMN condition;
+ALTER TABLE steps     DROP COLUMN condition;
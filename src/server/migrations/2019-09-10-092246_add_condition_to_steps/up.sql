@@ -0,0 +1,15 @@
+ALTER TABLE steps     ADD COLUMN condition Text NULL;
+ALTER TABLE job_steps ADD COLUMN condition Text NULL;
+
+-- Enum values can't be added within a transaction, so the type is replaced
+-- instead.
+ALTER TYPE JobStepStatus RENAME TO JobStepStatusOld;
+CREATE TYPE JobStepStatus AS ENUM ('initialized', 'pending', 'running', 'failed', 'cancelled', 'skipped', 'ok');
+
+ALTER TABLE job_steps ALTER COLUMN status DROP DEFAULT;
+ALTER TABLE job_steps ALTER COLUMN status TYPE JobStepStatus USING status::Text::JobStepStatus;
+ALTER TABLE job_steps ALTER COLUMN status SET DEFAULT 'pending';
+
+ALTER TABLE job_step_attempts ALTER COLUMN status TYPE JobStepStatus USING status::Text::JobStepStatus;
+
+DROP TYPE JobStepStatusOld;
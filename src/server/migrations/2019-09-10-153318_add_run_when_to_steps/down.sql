@@ -0,0 +1,4 @@
+ALTER TABLE job_steps DROP COLUMN run_when;
+ALTER TABLE steps     DROP COLUMN run_when;
+
+DROP TYPE StepRunWhen;
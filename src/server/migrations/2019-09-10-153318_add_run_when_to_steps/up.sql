@@ -0,0 +1,4 @@
+CREATE TYPE StepRunWhen AS ENUM ('on_success', 'on_failure', 'always');
+
+ALTER TABLE steps     ADD COLUMN run_when StepRunWhen NOT NULL DEFAULT 'on_success';
+ALTER TABLE job_steps ADD COLUMN run_when StepRunWhen NOT NULL DEFAULT 'on_success';
@@ -0,0 +1,4 @@
+ALTER TABLE job_steps DROP COLUMN item;
+ALTER TABLE job_steps DROP COLUMN parent_id;
+ALTER TABLE job_steps DROP COLUMN for_each;
+ALTER TABLE steps     DROP COLUMN for_each;
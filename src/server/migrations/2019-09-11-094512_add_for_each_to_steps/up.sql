@@ -0,0 +1,6 @@
+ALTER TABLE steps     ADD COLUMN for_each  Text    NULL;
+ALTER TABLE job_steps ADD COLUMN for_each  Text    NULL;
+ALTER TABLE job_steps ADD COLUMN parent_id Integer NULL REFERENCES job_steps(id) ON DELETE CASCADE;
+ALTER TABLE job_steps ADD COLUMN item      Jsonb   NULL;
+
+CREATE INDEX ON job_steps (parent_id);
@@ -0,0 +1,3 @@
+ALTER TABLE jobs ADD COLUMN parent_id Integer NULL REFERENCES jobs(id) ON DELETE SET NULL;
+
+CREATE INDEX ON jobs (parent_id);
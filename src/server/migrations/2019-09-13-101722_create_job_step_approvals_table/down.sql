@@ -0,0 +1,12 @@
+DROP TABLE job_step_approvals;
+
+ALTER TABLE jobs DROP COLUMN requested_by;
+
+UPDATE jobs SET status = 'running' WHERE status = 'waiting';
+
+ALTER TYPE JobStatus RENAME TO JobStatusOld;
+CREATE TYPE JobStatus AS ENUM ('scheduled', 'pending', 'running', 'failed', 'cancelled', 'ok');
+
+ALTER TABLE jobs ALTER COLUMN status TYPE JobStatus USING status::Text::JobStatus;
+
+DROP TYPE JobStatusOld;
@@ -0,0 +1,24 @@
+-- Enum values can't be added within a transaction, so the type is replaced
+-- instead.
+ALTER TYPE JobStatus RENAME TO JobStatusOld;
+CREATE TYPE JobStatus AS ENUM ('scheduled', 'pending', 'running', 'waiting', 'failed', 'cancelled', 'ok');
+
+ALTER TABLE jobs ALTER COLUMN status TYPE JobStatus USING status::Text::JobStatus;
+
+DROP TYPE JobStatusOld;
+
+ALTER TABLE jobs ADD COLUMN requested_by Integer NULL REFERENCES sessions(id) ON DELETE SET NULL;
+
+CREATE TABLE job_step_approvals (
+    id           Serial    PRIMARY KEY,
+    job_step_id  Integer   NOT NULL REFERENCES job_steps(id) ON DELETE CASCADE,
+    privilege    Text      NOT NULL,
+    message      Text      NULL,
+    requested_by Integer   NULL REFERENCES sessions(id) ON DELETE SET NULL,
+    requested_at Timestamp NOT NULL,
+    approved     Boolean   NULL,
+    decided_by   Integer   NULL REFERENCES sessions(id) ON DELETE SET NULL,
+    decided_at   Timestamp NULL
+);
+
+CREATE INDEX ON job_step_approvals (job_step_id);
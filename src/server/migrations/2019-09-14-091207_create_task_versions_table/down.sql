@@ -0,0 +1,3 @@
+ALTER TABLE jobs DROP COLUMN task_version_id;
+
+DROP TABLE task_versions;
@@ -0,0 +1,15 @@
+CREATE TABLE task_versions (
+    id         Serial    PRIMARY KEY,
+    version    Integer   NOT NULL,
+    definition Jsonb     NOT NULL,
+    created_at Timestamp NOT NULL,
+    task_id    Integer   NOT NULL REFERENCES tasks ON DELETE CASCADE,
+
+    UNIQUE (version, task_id)
+);
+
+CREATE INDEX ON task_versions (task_id);
+
+ALTER TABLE jobs ADD COLUMN task_version_id Integer NULL REFERENCES task_versions ON DELETE SET NULL;
+
+CREATE INDEX ON jobs (task_version_id);
@@ -0,0 +1,4 @@
+ALTER TABLE variables DROP COLUMN constraints;
+ALTER TABLE variables DROP COLUMN kind;
+
+DROP TYPE VariableKind;
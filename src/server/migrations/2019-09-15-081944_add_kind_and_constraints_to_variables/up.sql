@@ -0,0 +1,4 @@
+CREATE TYPE VariableKind AS ENUM ('text', 'multiline', 'number', 'boolean', 'date', 'enum', 'regex');
+
+ALTER TABLE variables ADD COLUMN kind        VariableKind NOT NULL DEFAULT 'text';
+ALTER TABLE variables ADD COLUMN constraints Jsonb            NULL;
@@ -0,0 +1 @@
+ALTER TABLE variables DROP COLUMN optional;
@@ -0,0 +1 @@
+ALTER TABLE variables ADD COLUMN optional Boolean NOT NULL DEFAULT false;
@@ -0,0 +1,5 @@
+ALTER TABLE job_steps DROP COLUMN formalized_processor;
+
+ALTER TABLE job_variables    DROP COLUMN secret;
+ALTER TABLE global_variables DROP COLUMN secret;
+ALTER TABLE variables        DROP COLUMN secret;
@@ -0,0 +1,5 @@
+ALTER TABLE variables        ADD COLUMN secret Boolean NOT NULL DEFAULT false;
+ALTER TABLE global_variables ADD COLUMN secret Boolean NOT NULL DEFAULT false;
+ALTER TABLE job_variables    ADD COLUMN secret Boolean NOT NULL DEFAULT false;
+
+ALTER TABLE job_steps ADD COLUMN formalized_processor Jsonb NULL;
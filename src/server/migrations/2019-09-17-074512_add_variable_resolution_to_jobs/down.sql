@@ -0,0 +1,2 @@
+ALTER TABLE jobs DROP COLUMN provides_variable;
+ALTER TABLE jobs DROP COLUMN resolve_variables;
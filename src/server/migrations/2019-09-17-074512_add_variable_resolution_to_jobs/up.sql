@@ -0,0 +1,2 @@
+ALTER TABLE jobs ADD COLUMN resolve_variables Boolean NOT NULL DEFAULT false;
+ALTER TABLE jobs ADD COLUMN provides_variable Text NULL;
@@ -0,0 +1,4 @@
+ALTER TABLE global_variables DROP COLUMN updated_at;
+ALTER TABLE global_variables DROP COLUMN created_at;
+ALTER TABLE global_variables DROP COLUMN labels;
+ALTER TABLE global_variables DROP COLUMN description;
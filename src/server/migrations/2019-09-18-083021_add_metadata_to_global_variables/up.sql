@@ -0,0 +1,4 @@
+ALTER TABLE global_variables ADD COLUMN description Text      NULL;
+ALTER TABLE global_variables ADD COLUMN labels      Text[]    NOT NULL DEFAULT '{}';
+ALTER TABLE global_variables ADD COLUMN created_at  Timestamp NOT NULL DEFAULT (now() AT TIME ZONE 'utc');
+ALTER TABLE global_variables ADD COLUMN updated_at  Timestamp NOT NULL DEFAULT (now() AT TIME ZONE 'utc');
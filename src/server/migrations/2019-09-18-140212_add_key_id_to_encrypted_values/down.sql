@@ -0,0 +1,3 @@
+ALTER TABLE schedule_variables DROP COLUMN key_id;
+ALTER TABLE job_variables      DROP COLUMN key_id;
+ALTER TABLE global_variables   DROP COLUMN key_id;
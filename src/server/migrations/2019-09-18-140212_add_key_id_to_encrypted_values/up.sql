@@ -0,0 +1,9 @@
+-- Existing values are encrypted with the secret that was used before key IDs
+-- existed, which is the secret of the `default` key.
+ALTER TABLE global_variables   ADD COLUMN key_id Text NOT NULL DEFAULT 'default';
+ALTER TABLE job_variables      ADD COLUMN key_id Text NOT NULL DEFAULT 'default';
+ALTER TABLE schedule_variables ADD COLUMN key_id Text NOT NULL DEFAULT 'default';
+
+ALTER TABLE global_variables   ALTER COLUMN key_id DROP DEFAULT;
+ALTER TABLE job_variables      ALTER COLUMN key_id DROP DEFAULT;
+ALTER TABLE schedule_variables ALTER COLUMN key_id DROP DEFAULT;
@@ -0,0 +1,277 @@
+//! Encryption of variable values at rest.
+//!
+//! The values of global, job, and schedule variables are encrypted by the
+//! database, using `pgp_sym_encrypt`. Each value is stored with the ID of the
+//! key whose secret encrypted it, so that values encrypted with different
+//! secrets can be decrypted side by side.
+//!
+//! New values are always encrypted with the active key. Its secret is set
+//! using `ENCRYPTION_SECRET`, and its ID using `ENCRYPTION_KEY_ID` (defaults to
+//! `default`). Values encrypted with any other key can still be decrypted, as
+//! long as the secret of that key is set using `ENCRYPTION_SECRET_<ID>`.
+//!
+//! `automaat rotate-secret` re-encrypts all values with the active key, after
+//! which the secrets of the other keys are no longer needed.
+
+use crate::schema::{global_variables, job_variables, schedule_variables};
+use crate::ENCRYPTION_KEYS;
+use diesel::expression::{AsExpression, NonAggregate};
+use diesel::prelude::*;
+use diesel::sql_types::{Bytea, Jsonb, Text};
+use serde_json::{Map, Value};
+use std::{env, error::Error};
+
+/// The ID of the active key, if no other ID is configured.
+///
+/// Values encrypted before key IDs were stored belong to this key.
+const DEFAULT_KEY_ID: &str = "default";
+
+/// The prefix of the environment variables containing the secrets of keys
+/// other than the active key.
+const SECRET_PREFIX: &str = "ENCRYPTION_SECRET_";
+
+sql_function!(fn pgp_sym_encrypt(data: Text, secret: Text) -> Bytea);
+sql_function!(fn pgp_sym_decrypt(data: Bytea, secret: Text) -> Text);
+sql_function!(fn jsonb_extract_path_text(from_json: Jsonb, path: Text) -> Text);
+
+/// An expression that encrypts a value using the secret of the active key.
+pub(crate) type Encrypt<'a> = pgp_sym_encrypt::HelperType<&'a str, &'static str>;
+
+/// An expression that decrypts a value using the secret of the key with which
+/// it was encrypted.
+pub(crate) type Decrypt<V, K> =
+    pgp_sym_decrypt::HelperType<V, jsonb_extract_path_text::HelperType<&'static Value, K>>;
+
+/// The set of keys used to encrypt and decrypt variable values.
+#[derive(Clone, Debug)]
+pub(crate) struct Keys {
+    active_id: String,
+    active_secret: String,
+
+    /// The secrets of all keys (including the active key), by key ID.
+    ///
+    /// The secrets are stored as a JSON object, so that the database can look
+    /// up the secret matching the key ID of each value it decrypts.
+    secrets: Value,
+}
+
+impl Keys {
+    /// Load the keys from the environment.
+    ///
+    /// This returns an error if `ENCRYPTION_SECRET` is not set.
+    pub(crate) fn from_environment() -> Result<Self, String> {
+        let secret = env::var("ENCRYPTION_SECRET")
+            .map_err(|_| "ENCRYPTION_SECRET environment variable not set".to_owned())?;
+
+        let id = env::var("ENCRYPTION_KEY_ID").unwrap_or_else(|_| DEFAULT_KEY_ID.to_owned());
+
+        Self::new(id, secret, env::vars())
+    }
+
+    /// Create a set of keys from the active key, and the environment
+    /// variables containing the secrets of the other keys.
+    ///
+    /// Variables without the `ENCRYPTION_SECRET_` prefix are ignored.
+    fn new(
+        active_id: String,
+        active_secret: String,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self, String> {
+        if active_id.is_empty() || active_secret.is_empty() {
+            return Err("encryption key ID and secret must not be empty".to_owned());
+        }
+
+        let mut secrets = Map::new();
+        for (name, secret) in vars {
+            let id = match name.strip_prefix(SECRET_PREFIX) {
+                None => continue,
+                Some(id) => id,
+            };
+
+            if id.is_empty() || secret.is_empty() {
+                return Err(format!("{} must have a key ID and a secret", name));
+            }
+
+            if id == active_id && secret != active_secret {
+                return Err(format!("{} must match ENCRYPTION_SECRET", name));
+            }
+
+            let _ = secrets.insert(id.to_owned(), Value::String(secret));
+        }
+
+        let _ = secrets.insert(active_id.clone(), Value::String(active_secret.clone()));
+
+        Ok(Self {
+            active_id,
+            active_secret,
+            secrets: Value::Object(secrets),
+        })
+    }
+
+    /// The ID of the key with which new values are encrypted.
+    pub(crate) fn active_id(&self) -> &str {
+        &self.active_id
+    }
+
+    /// Returns `true` if the secret of the key with the provided ID is known.
+    pub(crate) fn contains(&self, id: &str) -> bool {
+        self.secrets.get(id).is_some()
+    }
+}
+
+/// Encrypt the provided value, using the secret of the active key.
+///
+/// The key ID stored with the value has to match [`Keys::active_id`].
+pub(crate) fn encrypt(value: &str) -> Encrypt<'_> {
+    pgp_sym_encrypt(value, ENCRYPTION_KEYS.active_secret.as_str())
+}
+
+/// Decrypt the provided value, using the secret of the key with the provided
+/// ID.
+pub(crate) fn decrypt<V, K>(value: V, key_id: K) -> Decrypt<V, K>
+where
+    V: AsExpression<Bytea>,
+    K: AsExpression<Text>,
+    K::Expression: NonAggregate,
+{
+    pgp_sym_decrypt(
+        value,
+        jsonb_extract_path_text(&ENCRYPTION_KEYS.secrets, key_id),
+    )
+}
+
+/// Re-encrypt all variable values that are not yet encrypted with the active
+/// key.
+///
+/// All values are re-encrypted in a single transaction. If the secret of any
+/// of the stored key IDs is unknown, nothing is re-encrypted.
+pub(crate) fn rotate_secret() -> Result<(), Box<dyn Error>> {
+    let conn = PgConnection::establish(&env::var("DATABASE_URL")?)?;
+    crate::embedded_migrations::run(&conn)?;
+
+    let count = rotate(&conn)?;
+    println!(
+        "re-encrypted {} variable values with key {}",
+        count,
+        ENCRYPTION_KEYS.active_id()
+    );
+
+    Ok(())
+}
+
+/// Re-encrypt all variable values that are not yet encrypted with the active
+/// key, and return the number of re-encrypted values.
+fn rotate(conn: &PgConnection) -> Result<usize, Box<dyn Error>> {
+    let active_id = ENCRYPTION_KEYS.active_id();
+    let active_secret = ENCRYPTION_KEYS.active_secret.as_str();
+
+    conn.transaction::<_, Box<dyn Error>, _>(|| {
+        let mut ids: Vec<String> = global_variables::table
+            .select(global_variables::key_id)
+            .distinct()
+            .load(conn)?;
+
+        ids.extend(
+            job_variables::table
+                .select(job_variables::key_id)
+                .distinct()
+                .load::<String>(conn)?,
+        );
+
+        ids.extend(
+            schedule_variables::table
+                .select(schedule_variables::key_id)
+                .distinct()
+                .load::<String>(conn)?,
+        );
+
+        ids.sort();
+        ids.dedup();
+        ids.retain(|id| !ENCRYPTION_KEYS.contains(id));
+
+        if !ids.is_empty() {
+            return Err(format!("missing secrets of encryption keys: {}", ids.join(", ")).into());
+        }
+
+        let mut count = 0;
+
+        count += {
+            use crate::schema::global_variables::dsl::*;
+
+            diesel::update(global_variables.filter(key_id.ne(active_id)))
+                .set((
+                    value.eq(pgp_sym_encrypt(decrypt(value, key_id), active_secret)),
+                    key_id.eq(active_id),
+                ))
+                .execute(conn)?
+        };
+
+        count += {
+            use crate::schema::job_variables::dsl::*;
+
+            diesel::update(job_variables.filter(key_id.ne(active_id)))
+                .set((
+                    value.eq(pgp_sym_encrypt(decrypt(value, key_id), active_secret)),
+                    key_id.eq(active_id),
+                ))
+                .execute(conn)?
+        };
+
+        count += {
+            use crate::schema::schedule_variables::dsl::*;
+
+            diesel::update(schedule_variables.filter(key_id.ne(active_id)))
+                .set((
+                    value.eq(pgp_sym_encrypt(decrypt(value, key_id), active_secret)),
+                    key_id.eq(active_id),
+                ))
+                .execute(conn)?
+        };
+
+        Ok(count)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(name, value)| ((*name).to_owned(), (*value).to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn test_keys_new() {
+        let keys = Keys::new(
+            "v2".to_owned(),
+            "new".to_owned(),
+            vars(&[("ENCRYPTION_SECRET_default", "old"), ("HOME", "/root")]),
+        )
+        .unwrap();
+
+        assert_eq!(keys.active_id(), "v2");
+        assert!(keys.contains("v2"));
+        assert!(keys.contains("default"));
+        assert!(!keys.contains("HOME"));
+        assert_eq!(keys.secrets["default"], "old");
+    }
+
+    #[test]
+    fn test_keys_new_invalid() {
+        assert!(Keys::new("v1".to_owned(), String::new(), vec![]).is_err());
+        assert!(Keys::new(
+            "v1".to_owned(),
+            "new".to_owned(),
+            vars(&[("ENCRYPTION_SECRET_", "old")])
+        )
+        .is_err());
+        assert!(Keys::new(
+            "v1".to_owned(),
+            "new".to_owned(),
+            vars(&[("ENCRYPTION_SECRET_v1", "old")])
+        )
+        .is_err());
+    }
+}
@@ -1,20 +1,67 @@
-use crate::models::{NewGlobalVariable, NewSession, Session};
+use crate::models::{GlobalVariable, NewGlobalVariable, NewSession, Session};
 use crate::resources::{
-    CreateJobFromTaskInput, CreateSessionInput, CreateTaskInput, GlobalVariableInput, Job, NewJob,
-    NewJobVariable, NewTask, OnConflict, SearchTaskInput, Task, UpdatePrivilegesInput,
+    CreateJobFromTaskInput, CreateScheduleInput, CreateSessionInput, CreateTaskInput,
+    GlobalVariableInput, Job, JobStatus, JobStepApproval, NewJob, NewJobVariable, NewSchedule,
+    NewTask, OnConflict, Schedule, SearchTaskInput, Step, Task, TaskPatch, TaskVersion,
+    UpdatePrivilegesInput, UpdateScheduleInput, UpdateTaskInput,
 };
 use crate::schema::*;
 use crate::server::RequestState;
+use crate::subscription::SubscriptionState;
 use diesel::prelude::*;
-use juniper::{object, Context, FieldResult, RootNode, ID};
+use juniper::meta::MetaType;
+use juniper::{
+    object, Context, DefaultScalarValue, FieldResult, FromContext, GraphQLType, Registry, RootNode,
+    ID,
+};
 use std::convert::TryFrom;
+use std::error::Error;
 
 impl Context for RequestState {}
+impl Context for SubscriptionState {}
+
+impl FromContext<SubscriptionState> for RequestState {
+    fn from(state: &SubscriptionState) -> &Self {
+        &state.request
+    }
+}
 
 pub(crate) type Schema = RootNode<'static, QueryRoot, MutationRoot>;
 pub(crate) struct QueryRoot;
 pub(crate) struct MutationRoot;
 
+/// The schema used to resolve subscriptions.
+///
+/// Juniper has no native support for subscriptions, so the subscription root
+/// is exposed as the query root of a separate schema. Each subscription is
+/// resolved again every time a change is reported by the database.
+///
+/// See [`crate::subscription`] for more details.
+pub(crate) type SubscriptionSchema = RootNode<'static, SubscriptionRoot, SubscriptionMutation>;
+pub(crate) struct SubscriptionRoot;
+
+/// The mutation root of the subscription schema, which has no mutations.
+///
+/// Juniper's `EmptyMutation` can't be used, as it is only shared between
+/// threads if its context is, and the context holds a database connection.
+pub(crate) struct SubscriptionMutation;
+
+impl GraphQLType for SubscriptionMutation {
+    type Context = SubscriptionState;
+    type TypeInfo = ();
+
+    fn name(_: &()) -> Option<&str> {
+        Some("_EmptyMutation")
+    }
+
+    fn meta<'r>(_: &(), registry: &mut Registry<'r>) -> MetaType<'r>
+    where
+        DefaultScalarValue: 'r,
+    {
+        registry.build_object_type::<Self>(&(), &[]).into_meta()
+    }
+}
+
 #[object(Context = RequestState)]
 impl QueryRoot {
     /// Return a list of tasks.
@@ -65,6 +112,49 @@ impl QueryRoot {
             .map_err(Into::into)
     }
 
+    /// Return a list of approval requests of jobs that are waiting for them,
+    /// ordered from oldest to newest.
+    fn pending_approvals(context: &RequestState) -> FieldResult<Vec<JobStepApproval>> {
+        JobStepApproval::pending(&context.conn).map_err(Into::into)
+    }
+
+    /// Return a list of global variables, ordered by their key.
+    ///
+    /// The values of the global variables are only returned if the
+    /// `query_global_variable_values` privilege is set for the provided
+    /// session, and for scoped global variables, one of their labels as well.
+    ///
+    /// # Privileges
+    ///
+    /// This query requires the `query_global_variables` privilege to be set
+    /// for the provided session.
+    fn global_variables(context: &RequestState) -> FieldResult<Vec<GlobalVariable>> {
+        authorization_guard(&["query_global_variables"], &context.session)?;
+
+        GlobalVariable::all()
+            .order(global_variables::key)
+            .load(&context.conn)
+            .map_err(Into::into)
+    }
+
+    /// Return a single global variable, based on its key.
+    ///
+    /// This query can return `null` if no global variable is found matching
+    /// the provided key.
+    ///
+    /// # Privileges
+    ///
+    /// This query requires the `query_global_variables` privilege to be set
+    /// for the provided session.
+    fn global_variable(context: &RequestState, key: String) -> FieldResult<Option<GlobalVariable>> {
+        authorization_guard(&["query_global_variables"], &context.session)?;
+
+        GlobalVariable::by_key(&key)
+            .first(&context.conn)
+            .optional()
+            .map_err(Into::into)
+    }
+
     /// Get details of the current session, if any.
     fn session(context: &RequestState) -> Option<&Session> {
         context.session.as_ref()
@@ -90,9 +180,149 @@ impl MutationRoot {
         .map_err(Into::into)
     }
 
+    /// Update the name, description, labels or timeout of an existing task.
+    ///
+    /// Any field not provided in the patch is left unchanged. A task can't be
+    /// renamed while other tasks run it as a sub-job.
+    ///
+    /// # Privileges
+    ///
+    /// This mutation requires the `mutation_update_task` privilege to be set
+    /// for the provided session.
+    ///
+    /// If the task has one or more labels, at least one privilege must also
+    /// match one of the task labels. The same applies to any new labels.
+    fn updateTask(context: &RequestState, id: ID, patch: UpdateTaskInput) -> FieldResult<Task> {
+        authorization_guard(&["mutation_update_task"], &context.session)?;
+
+        let task = find_task(context, id.parse()?)?;
+        if let Some(labels) = &patch.labels {
+            authorization_guard(
+                &labels.iter().map(String::as_str).collect::<Vec<_>>(),
+                &context.session,
+            )?;
+        }
+
+        TaskPatch::try_from(&patch)?
+            .apply(&context.conn, &task)
+            .map_err(Into::into)
+    }
+
+    /// Delete an existing task, including its steps, variables and
+    /// schedules.
+    ///
+    /// Jobs already created from the task are kept, but are no longer linked
+    /// to the task. A task can't be deleted while other tasks run it as a
+    /// sub-job.
+    ///
+    /// # Privileges
+    ///
+    /// This mutation requires the `mutation_delete_task` privilege to be set
+    /// for the provided session.
+    ///
+    /// If the task has one or more labels, at least one privilege must also
+    /// match one of the task labels.
+    fn deleteTask(context: &RequestState, id: ID) -> FieldResult<bool> {
+        authorization_guard(&["mutation_delete_task"], &context.session)?;
+
+        find_task(context, id.parse()?)?
+            .delete(&context.conn)
+            .map(|_| true)
+            .map_err(Into::into)
+    }
+
+    /// Delete a single step of an existing task.
+    ///
+    /// A step can't be deleted while other steps of the task depend on it.
+    ///
+    /// # Privileges
+    ///
+    /// This mutation requires the `mutation_delete_step` privilege to be set
+    /// for the provided session.
+    ///
+    /// If the task of the step has one or more labels, at least one privilege
+    /// must also match one of the task labels.
+    fn deleteStep(context: &RequestState, id: ID) -> FieldResult<bool> {
+        authorization_guard(&["mutation_delete_step"], &context.session)?;
+
+        let step: Step = steps::table
+            .filter(steps::id.eq(id.parse::<i32>()?))
+            .first(&context.conn)?;
+
+        let task = find_task(context, step.task_id)?;
+
+        task.delete_step(&step, &context.conn)
+            .map(|_| true)
+            .map_err(Into::into)
+    }
+
+    /// Change the order in which the steps of an existing task run.
+    ///
+    /// The `stepIds` argument has to contain the IDs of all steps of the task
+    /// exactly once, in their new order. Steps that don't declare their
+    /// dependencies depend on the step preceding them, so the new order
+    /// can't result in circular dependencies.
+    ///
+    /// # Privileges
+    ///
+    /// This mutation requires the `mutation_update_task` privilege to be set
+    /// for the provided session.
+    ///
+    /// If the task has one or more labels, at least one privilege must also
+    /// match one of the task labels.
+    fn reorderSteps(context: &RequestState, task_id: ID, step_ids: Vec<ID>) -> FieldResult<Task> {
+        authorization_guard(&["mutation_update_task"], &context.session)?;
+
+        let task = find_task(context, task_id.parse()?)?;
+        let step_ids = step_ids
+            .iter()
+            .map(|id| id.parse::<i32>())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        task.reorder_steps(&step_ids, &context.conn)?;
+        Ok(task)
+    }
+
+    /// Restore a task to an earlier version.
+    ///
+    /// The name, description, labels, timeout, variables and steps of the
+    /// task are set to those of the provided version. Restoring a version
+    /// records a new version of the task, so a restore can itself be undone.
+    ///
+    /// # Privileges
+    ///
+    /// This mutation requires the `mutation_update_task` privilege to be set
+    /// for the provided session.
+    ///
+    /// If the task has one or more labels, at least one privilege must also
+    /// match one of the task labels. The same applies to the labels of the
+    /// restored version.
+    fn restoreTaskVersion(context: &RequestState, id: ID) -> FieldResult<Task> {
+        authorization_guard(&["mutation_update_task"], &context.session)?;
+
+        let version: TaskVersion = task_versions::table
+            .filter(task_versions::id.eq(id.parse::<i32>()?))
+            .first(&context.conn)?;
+
+        let _ = find_task(context, version.task_id)?;
+        let definition = version.definition()?;
+        authorization_guard(
+            &definition
+                .labels
+                .iter()
+                .map(String::as_str)
+                .collect::<Vec<_>>(),
+            &context.session,
+        )?;
+
+        version.restore(&context.conn).map_err(Into::into)
+    }
+
     /// Create a job from an existing task ID.
     ///
-    /// Once the job is created, it will be scheduled to run immediately.
+    /// Once the job is created, it will be scheduled to run immediately,
+    /// unless `runAt` is provided, in which case the job is scheduled to run
+    /// at the provided moment.
     ///
     /// # Privileges
     ///
@@ -119,7 +349,168 @@ impl MutationRoot {
             .map(Into::into)
             .collect::<Vec<NewJobVariable<'_>>>();
 
-        NewJob::create_from_task(&context.conn, &task, variables).map_err(Into::into)
+        let run_at = job.run_at.map(|t| t.naive_utc());
+
+        let requested_by = context.session.as_ref().map(|s| s.id);
+        let resolve_variables = job.resolve_variables.unwrap_or(false);
+
+        NewJob::create_from_task(
+            &context.conn,
+            &task,
+            variables,
+            run_at,
+            requested_by,
+            resolve_variables,
+        )
+        .map_err(Into::into)
+    }
+
+    /// Cancel a job that hasn't finished running yet.
+    ///
+    /// A pending job is cancelled before it starts running. If the job is
+    /// already running, any remaining steps are cancelled, and the active step
+    /// is interrupted, if possible.
+    ///
+    /// Returns an error if the job already finished running.
+    ///
+    /// # Privileges
+    ///
+    /// The same privileges apply as when creating a job from a task. If the
+    /// job was created from a task with one or more labels, at least one
+    /// privilege of the session must match one of the task labels.
+    fn cancelJob(context: &RequestState, id: ID) -> FieldResult<Job> {
+        let job: Job = jobs::table
+            .filter(jobs::id.eq(id.parse::<i32>()?))
+            .first(&context.conn)?;
+
+        if let Some(task) = job.task(&context.conn)? {
+            authorization_guard(
+                &task.labels.iter().map(String::as_str).collect::<Vec<_>>(),
+                &context.session,
+            )?;
+        }
+
+        job.cancel(&context.conn).map_err(Into::into)
+    }
+
+    /// Approve the pending approval request of a job that is waiting for it.
+    ///
+    /// Once approved, the job is pending again, and the worker that picks it
+    /// up continues running the job at the step that requested approval.
+    ///
+    /// # Privileges
+    ///
+    /// This mutation requires the privilege configured on the
+    /// `RequireApproval` step to be set for the provided session.
+    ///
+    /// The session that requested the job is not allowed to approve it.
+    fn approveJob(context: &RequestState, id: ID) -> FieldResult<Job> {
+        decide_approval(context, id, true)
+    }
+
+    /// Reject the pending approval request of a job that is waiting for it.
+    ///
+    /// Once rejected, the job is pending again, and the worker that picks it
+    /// up fails the step that requested approval, as well as the job, unless
+    /// the step is retried.
+    ///
+    /// # Privileges
+    ///
+    /// This mutation requires the privilege configured on the
+    /// `RequireApproval` step to be set for the provided session.
+    ///
+    /// The session that requested the job is not allowed to reject it.
+    fn rejectJob(context: &RequestState, id: ID) -> FieldResult<Job> {
+        decide_approval(context, id, false)
+    }
+
+    /// Create a new schedule, to run a task on a recurring basis.
+    ///
+    /// # Privileges
+    ///
+    /// This mutation requires the `mutation_create_schedule` privilege to be
+    /// set for the provided session.
+    ///
+    /// If the task has one or more labels, at least one privilege must also
+    /// match one of the task labels.
+    fn createSchedule(
+        context: &RequestState,
+        schedule: CreateScheduleInput,
+    ) -> FieldResult<Schedule> {
+        authorization_guard(&["mutation_create_schedule"], &context.session)?;
+
+        let task: Task = tasks::table
+            .filter(tasks::id.eq(schedule.task_id.parse::<i32>()?))
+            .first(&context.conn)?;
+
+        authorization_guard(
+            &task.labels.iter().map(String::as_str).collect::<Vec<_>>(),
+            &context.session,
+        )?;
+
+        NewSchedule::from(&schedule)
+            .create(&context.conn, &task)
+            .map_err(Into::into)
+    }
+
+    /// Update the cron expression and variable values of an existing schedule.
+    ///
+    /// # Privileges
+    ///
+    /// This mutation requires the `mutation_update_schedule` privilege to be
+    /// set for the provided session.
+    ///
+    /// If the task of the schedule has one or more labels, at least one
+    /// privilege must also match one of the task labels.
+    fn updateSchedule(
+        context: &RequestState,
+        schedule: UpdateScheduleInput,
+    ) -> FieldResult<Schedule> {
+        authorization_guard(&["mutation_update_schedule"], &context.session)?;
+
+        let existing: Schedule = schedules::table
+            .filter(schedules::id.eq(schedule.id.parse::<i32>()?))
+            .first(&context.conn)?;
+
+        let task = existing.task(&context.conn)?;
+        authorization_guard(
+            &task.labels.iter().map(String::as_str).collect::<Vec<_>>(),
+            &context.session,
+        )?;
+
+        NewSchedule::from(&schedule)
+            .update(&context.conn, &existing)
+            .map_err(Into::into)
+    }
+
+    /// Delete an existing schedule.
+    ///
+    /// Jobs already created by the schedule are not affected.
+    ///
+    /// # Privileges
+    ///
+    /// This mutation requires the `mutation_delete_schedule` privilege to be
+    /// set for the provided session.
+    ///
+    /// If the task of the schedule has one or more labels, at least one
+    /// privilege must also match one of the task labels.
+    fn deleteSchedule(context: &RequestState, id: ID) -> FieldResult<bool> {
+        authorization_guard(&["mutation_delete_schedule"], &context.session)?;
+
+        let schedule: Schedule = schedules::table
+            .filter(schedules::id.eq(id.parse::<i32>()?))
+            .first(&context.conn)?;
+
+        let task = schedule.task(&context.conn)?;
+        authorization_guard(
+            &task.labels.iter().map(String::as_str).collect::<Vec<_>>(),
+            &context.session,
+        )?;
+
+        diesel::delete(&schedule)
+            .execute(&context.conn)
+            .map(|_| true)
+            .map_err(Into::into)
     }
 
     /// Create a new global variable.
@@ -135,6 +526,10 @@ impl MutationRoot {
     ///
     /// This mutation requires the `mutation_create_global_variable` privilege
     /// to be set for the provided session.
+    ///
+    /// If the global variable is scoped to one or more labels, at least one
+    /// privilege must also match one of those labels. The same applies to the
+    /// labels of an existing global variable that is updated.
     fn createGlobalVariable(
         context: &RequestState,
         variable: GlobalVariableInput,
@@ -143,6 +538,28 @@ impl MutationRoot {
 
         authorization_guard(&["mutation_create_global_variable"], &context.session)?;
 
+        if let Some(labels) = &variable.labels {
+            authorization_guard(
+                &labels.iter().map(String::as_str).collect::<Vec<_>>(),
+                &context.session,
+            )?;
+        }
+
+        let existing: Option<GlobalVariable> = GlobalVariable::by_key(&variable.key)
+            .first(&context.conn)
+            .optional()?;
+
+        if let Some(existing) = existing {
+            authorization_guard(
+                &existing
+                    .labels
+                    .iter()
+                    .map(String::as_str)
+                    .collect::<Vec<_>>(),
+                &context.session,
+            )?;
+        }
+
         let global_variable = NewGlobalVariable::from(&variable);
         let global_variable = match &variable.on_conflict.as_ref().unwrap_or(&Abort) {
             Abort => global_variable.create(&context.conn),
@@ -152,6 +569,37 @@ impl MutationRoot {
         global_variable.map(|_| true).map_err(Into::into)
     }
 
+    /// Delete an existing global variable, based on its key.
+    ///
+    /// Tasks referencing the global variable in their templates will fail to
+    /// render them, until a global variable with the same key is created.
+    ///
+    /// # Privileges
+    ///
+    /// This mutation requires the `mutation_delete_global_variable` privilege
+    /// to be set for the provided session.
+    ///
+    /// If the global variable is scoped to one or more labels, at least one
+    /// privilege must also match one of those labels.
+    fn deleteGlobalVariable(context: &RequestState, key: String) -> FieldResult<bool> {
+        authorization_guard(&["mutation_delete_global_variable"], &context.session)?;
+
+        let variable: GlobalVariable = GlobalVariable::by_key(&key).first(&context.conn)?;
+        authorization_guard(
+            &variable
+                .labels
+                .iter()
+                .map(String::as_str)
+                .collect::<Vec<_>>(),
+            &context.session,
+        )?;
+
+        diesel::delete(&variable)
+            .execute(&context.conn)
+            .map(|_| true)
+            .map_err(Into::into)
+    }
+
     /// Create a new session.
     ///
     /// The returned session token can be used to authenticate with the GraphQL
@@ -199,13 +647,86 @@ impl MutationRoot {
     }
 }
 
+#[object(Context = SubscriptionState)]
+impl SubscriptionRoot {
+    /// Subscribe to changes of a single job, based on the job ID.
+    ///
+    /// An update is sent every time the status of the job or one of its
+    /// sub-jobs changes, or one of their steps starts or finishes running.
+    ///
+    /// This subscription can return `null` if no job is found matching the
+    /// provided ID.
+    fn job_updated(context: &SubscriptionState, id: ID) -> FieldResult<Option<Job>> {
+        let conn = &context.request.conn;
+        let id = id.parse::<i32>()?;
+        context.watch_job(id);
+
+        let job: Option<Job> = jobs::table.filter(jobs::id.eq(id)).first(conn).optional()?;
+        if let Some(job) = &job {
+            job.children(conn)?
+                .iter()
+                .for_each(|child| context.watch_job(child.id));
+        }
+
+        Ok(job)
+    }
+}
+
+/// Find the task with the provided ID, and return an error if none of the
+/// task labels are present in the session privileges.
+fn find_task(context: &RequestState, id: i32) -> FieldResult<Task> {
+    let task: Task = tasks::table.filter(tasks::id.eq(id)).first(&context.conn)?;
+
+    authorization_guard(
+        &task.labels.iter().map(String::as_str).collect::<Vec<_>>(),
+        &context.session,
+    )?;
+
+    Ok(task)
+}
+
+/// Approve or reject the pending approval request of the job with the
+/// provided ID, and return the job.
+fn decide_approval(context: &RequestState, id: ID, approved: bool) -> FieldResult<Job> {
+    let job: Job = jobs::table
+        .filter(jobs::id.eq(id.parse::<i32>()?))
+        .first(&context.conn)?;
+
+    let approval = JobStepApproval::pending_for_job(&job, &context.conn)?
+        .ok_or("job is not waiting for approval")?;
+
+    authorization_guard(&[&approval.privilege], &context.session)?;
+
+    let session = context.session.as_ref().ok_or("Unauthorized")?;
+    if approval.requested_by == Some(session.id) {
+        return Err("job cannot be decided by the session that requested it".into());
+    }
+
+    // The job is queued again, so that a worker continues running it.
+    context.conn.transaction::<_, Box<dyn Error>, _>(|| {
+        let _ = approval.decide(&context.conn, session.id, approved)?;
+        Job::transition(
+            job.id,
+            JobStatus::Waiting,
+            JobStatus::Pending,
+            &context.conn,
+        )
+        .map_err(Into::into)
+    })?;
+
+    jobs::table
+        .find(job.id)
+        .first(&context.conn)
+        .map_err(Into::into)
+}
+
 /// A guard function that returns an error if none of the defined labels are
 /// present in the provided session privileges.
 ///
 /// If no labels are provided, the request is considered to be authorized.
 ///
 /// If no session is provided, its privileges are considered to be empty.
-fn authorization_guard(labels: &[&str], session: &Option<Session>) -> FieldResult<()> {
+pub(crate) fn authorization_guard(labels: &[&str], session: &Option<Session>) -> FieldResult<()> {
     if labels.is_empty() {
         return Ok(());
     }
@@ -227,6 +748,11 @@ fn authorization_guard(labels: &[&str], session: &Option<Session>) -> FieldResul
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::resources::NewStep;
+    use crate::test_support::{create_task, request_state};
+    use chrono::Utc;
+    use juniper::Variables;
+    use serde_json::json;
     use uuid::Uuid;
 
     fn stub_session(privileges: &[&str]) -> Option<Session> {
@@ -237,6 +763,227 @@ mod tests {
         })
     }
 
+    /// Execute the GraphQL document, and return the messages of any field
+    /// errors.
+    fn execute(context: &RequestState, document: &str) -> Vec<String> {
+        let schema = Schema::new(QueryRoot, MutationRoot);
+        let (_, errors) =
+            juniper::execute(document, None, &schema, &Variables::new(), context).unwrap();
+
+        errors
+            .iter()
+            .map(|err| err.error().message().to_owned())
+            .collect()
+    }
+
+    /// Create a task with a single step, labeled `ops`.
+    fn labeled_task(conn: &PgConnection) -> Task {
+        let processor = json!({ "PrintOutput": { "output": "hello" } });
+        let processor = serde_json::from_value(processor).unwrap();
+
+        let mut task = NewTask::new("privileged", None, vec!["ops"]);
+        task.with_steps(vec![NewStep::new("print", None, processor, 0, None)]);
+        task.create(conn).unwrap()
+    }
+
+    /// Create a job requested by the provided session, which is waiting for
+    /// a session with the `approver` privilege to approve it.
+    fn waiting_job(conn: &PgConnection, requested_by: &Session) -> Job {
+        let approval = json!({ "RequireApproval": { "privilege": "approver" } });
+        let task = create_task(conn, "approval", &[], &[("approve", approval)]);
+        let job = NewJob::create_from_task(conn, &task, vec![], None, Some(requested_by.id), false)
+            .unwrap();
+
+        Job::transition(job.id, JobStatus::Pending, JobStatus::Waiting, conn).unwrap();
+
+        let step = &job.steps(conn).unwrap()[0];
+        let _ = diesel::insert_into(job_step_approvals::table)
+            .values((
+                job_step_approvals::job_step_id.eq(step.id),
+                job_step_approvals::privilege.eq("approver"),
+                job_step_approvals::requested_by.eq(requested_by.id),
+                job_step_approvals::requested_at.eq(Utc::now().naive_utc()),
+            ))
+            .execute(conn)
+            .unwrap();
+
+        job
+    }
+
+    #[test]
+    fn test_update_task_privileges() {
+        let mut context = request_state();
+        let task = labeled_task(&context.conn);
+        let update = |patch: &str| {
+            format!(
+                r#"mutation {{ updateTask(id: "{}", patch: {{ {} }}) {{ name }} }}"#,
+                task.id, patch
+            )
+        };
+
+        let denied = [
+            (&["ops"][..], r#"name: "renamed""#),
+            (&["mutation_update_task"][..], r#"name: "renamed""#),
+            (
+                &["mutation_update_task", "ops"][..],
+                r#"name: "renamed", labels: ["admin"]"#,
+            ),
+        ];
+
+        for (privileges, patch) in &denied {
+            context.session = stub_session(privileges);
+            assert_eq!(execute(&context, &update(patch)), vec!["Unauthorized"]);
+        }
+
+        let name: String = tasks::table
+            .find(task.id)
+            .select(tasks::name)
+            .first(&context.conn)
+            .unwrap();
+        assert_eq!(name, "privileged");
+
+        context.session = stub_session(&["mutation_update_task", "ops"]);
+        assert!(execute(&context, &update(r#"name: "renamed""#)).is_empty());
+
+        let name: String = tasks::table
+            .find(task.id)
+            .select(tasks::name)
+            .first(&context.conn)
+            .unwrap();
+        assert_eq!(name, "renamed");
+    }
+
+    #[test]
+    fn test_delete_task_privileges() {
+        let mut context = request_state();
+        let task = labeled_task(&context.conn);
+        let delete = format!(r#"mutation {{ deleteTask(id: "{}") }}"#, task.id);
+
+        for privileges in &[
+            &["ops", "mutation_update_task"][..],
+            &["mutation_delete_task"],
+        ] {
+            context.session = stub_session(privileges);
+            assert_eq!(execute(&context, &delete), vec!["Unauthorized"]);
+        }
+
+        context.session = stub_session(&["mutation_delete_task", "ops"]);
+        assert!(execute(&context, &delete).is_empty());
+
+        let count: i64 = tasks::table
+            .filter(tasks::id.eq(task.id))
+            .count()
+            .get_result(&context.conn)
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+
+    #[test]
+    fn test_delete_step_privileges() {
+        let mut context = request_state();
+        let task = labeled_task(&context.conn);
+        let step = &task.steps(&context.conn).unwrap()[0];
+        let delete = format!(r#"mutation {{ deleteStep(id: "{}") }}"#, step.id);
+
+        for privileges in &[
+            &["ops", "mutation_delete_task"][..],
+            &["mutation_delete_step"],
+        ] {
+            context.session = stub_session(privileges);
+            assert_eq!(execute(&context, &delete), vec!["Unauthorized"]);
+        }
+
+        context.session = stub_session(&["mutation_delete_step", "ops"]);
+        assert!(execute(&context, &delete).is_empty());
+        assert!(task.steps(&context.conn).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_global_variable_value_privileges() {
+        let mut context = request_state();
+        let mut variable = NewGlobalVariable::new("test_scoped", "hunter2");
+        variable.with_labels(vec!["ops"]);
+        let _ = variable.create(&context.conn).unwrap();
+
+        let value = |context: &RequestState| {
+            let schema = Schema::new(QueryRoot, MutationRoot);
+            let document = "{ globalVariables { key value } }";
+            let (value, errors) =
+                juniper::execute(document, None, &schema, &Variables::new(), context).unwrap();
+            assert!(errors.is_empty());
+
+            let value = serde_json::to_value(value).unwrap();
+            value["globalVariables"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|v| v["key"] == "test_scoped")
+                .unwrap()["value"]
+                .clone()
+        };
+
+        for privileges in &[
+            &["query_global_variables", "ops"][..],
+            &["query_global_variables", "query_global_variable_values"],
+        ] {
+            context.session = stub_session(privileges);
+            assert_eq!(value(&context), json!(null));
+        }
+
+        context.session = stub_session(&[
+            "query_global_variables",
+            "query_global_variable_values",
+            "ops",
+        ]);
+        assert_eq!(value(&context), json!("hunter2"));
+    }
+
+    #[test]
+    fn test_decide_approval() {
+        let mut context = request_state();
+        let requester = NewSession::new(vec!["approver"])
+            .create(&context.conn)
+            .unwrap();
+        let approver = NewSession::new(vec!["approver"])
+            .create(&context.conn)
+            .unwrap();
+        let job = waiting_job(&context.conn, &requester);
+        let id = ID::from(job.id.to_string());
+
+        // The session that requested the job can't approve it, even though it
+        // has the required privilege.
+        context.session = Some(requester);
+        let err = decide_approval(&context, id.clone(), true).unwrap_err();
+        assert_eq!(
+            err.message(),
+            "job cannot be decided by the session that requested it"
+        );
+
+        context.session = None;
+        let err = decide_approval(&context, id.clone(), true).unwrap_err();
+        assert_eq!(err.message(), "Unauthorized");
+
+        context.session = Some(approver.clone());
+        let _ = decide_approval(&context, id.clone(), false).unwrap();
+
+        let approval: JobStepApproval = job_step_approvals::table
+            .inner_join(job_steps::table)
+            .filter(job_steps::job_id.eq(job.id))
+            .select(job_step_approvals::all_columns)
+            .first(&context.conn)
+            .unwrap();
+
+        assert_eq!(approval.approved, Some(false));
+        assert_eq!(approval.decided_by, Some(approver.id));
+
+        // The job is queued again, for a worker to continue running it.
+        let job: Job = jobs::table.find(job.id).first(&context.conn).unwrap();
+        assert_eq!(job.status, JobStatus::Pending);
+
+        let err = decide_approval(&context, id, true).unwrap_err();
+        assert_eq!(err.message(), "job is not waiting for approval");
+    }
+
     #[test]
     fn test_authorization_guard_empty_labels() {
         let session = stub_session(&[]);
@@ -1,8 +1,10 @@
-use crate::graphql::Schema;
+use crate::graphql::{Schema, SubscriptionSchema};
 use crate::models::Session;
 use crate::server::{RequestState, ServerError, State};
-use actix_web::web::{block, Data, Json};
-use actix_web::{HttpRequest, HttpResponse};
+use crate::subscription::{self, Broker};
+use actix_web::web::{block, Data, Json, Payload};
+use actix_web::{Error, HttpRequest, HttpResponse};
+use actix_web_actors::ws;
 use diesel::pg::PgConnection;
 use futures::future::Future;
 use juniper::http::{graphiql, playground, GraphQLRequest};
@@ -68,6 +70,47 @@ pub(super) fn graphql(
     })
 }
 
+/// Upgrade the request to a WebSocket connection, used to serve GraphQL
+/// subscriptions.
+///
+/// Clients that can't set the authorization header on the WebSocket request
+/// can provide their token in the `connection_init` message instead.
+pub(super) fn subscriptions(
+    request: HttpRequest,
+    stream: Payload,
+    (state, schema): (Data<Arc<State>>, Data<Arc<SubscriptionSchema>>),
+    broker: Data<Broker>,
+) -> Result<HttpResponse, Error> {
+    use actix_web::http::header;
+
+    let token = auth_token(&request).and_then(Result::ok);
+    let session = subscription::Session::new(
+        state.get_ref().clone(),
+        schema.get_ref().clone(),
+        broker.get_ref().clone(),
+        token,
+    );
+
+    let mut response = ws::handshake(&request)?;
+
+    // Only confirm the sub-protocol if the client asked for it, browsers
+    // reject the connection otherwise.
+    let protocols = request
+        .headers()
+        .get(header::SEC_WEBSOCKET_PROTOCOL)
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or_default();
+
+    if protocols
+        .split(',')
+        .any(|p| p.trim() == subscription::PROTOCOL)
+    {
+        let _ = response.header(header::SEC_WEBSOCKET_PROTOCOL, subscription::PROTOCOL);
+    }
+
+    Ok(response.streaming(ws::WebsocketContext::create(session, stream)))
+}
+
 pub(super) fn health() -> HttpResponse {
     let health = Health {
         status: Status::Pass,
@@ -80,7 +123,7 @@ pub(super) fn health() -> HttpResponse {
         .json(health)
 }
 
-fn authenticate(token: &str, conn: &PgConnection) -> Result<Session, ServerError> {
+pub(crate) fn authenticate(token: &str, conn: &PgConnection) -> Result<Session, ServerError> {
     Uuid::from_str(token)
         .ok()
         .and_then(|token| Session::find_by_token(token, conn).ok())
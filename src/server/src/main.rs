@@ -51,36 +51,63 @@ extern crate diesel_migrations;
 #[macro_use]
 extern crate diesel_derive_enum;
 
+mod encryption;
 mod graphql;
 mod handlers;
 mod middleware;
 mod models;
+mod notification;
 mod processor;
 mod resources;
 mod schema;
 mod server;
+mod subscription;
+mod sync;
+#[cfg(test)]
+mod test_support;
 mod worker;
 
+use crate::encryption::Keys;
 use crate::processor::{Input as ProcessorInput, Processor};
 use crate::server::Server;
+use crate::sync::ApplyOptions;
 use crate::worker::Worker;
 use diesel_migrations::embed_migrations;
-use std::env;
+use std::{env, error::Error, path::Path};
+
+const USAGE: &str = "usage: automaat \
+                     [server|worker [--concurrency N]|export DIR|apply DIR [--dry-run] [--prune]\
+                     |rotate-secret]";
 
 lazy_static::lazy_static! {
-    static ref ENCRYPTION_SECRET: String = env::var("ENCRYPTION_SECRET")
-        .expect("ENCRYPTION_SECRET environment variable not set");
+    static ref ENCRYPTION_KEYS: Keys = Keys::from_environment()
+        .unwrap_or_else(|err| panic!("{}", err));
 }
 
 fn main() {
-    // Make sure encryption secret is set by loading it once.
-    let _ = &ENCRYPTION_SECRET.to_string();
+    // Make sure the encryption keys are valid by loading them once.
+    lazy_static::initialize(&ENCRYPTION_KEYS);
 
     let args: Vec<String> = env::args().collect();
     let run = || match args.get(1).map(String::as_str) {
         Some("server") => Server::from_environment()?.run_to_completion(),
-        Some("worker") => Worker::from_environment()?.run_to_completion(),
-        _ => Err("usage: automaat [server|worker]".into()),
+        Some("worker") => {
+            let concurrency = concurrency_flag(args.get(2..).unwrap_or_default())?;
+            Worker::from_environment(concurrency)?.run_to_completion()
+        }
+        Some("export") => match args.get(2..).unwrap_or_default() {
+            [dir] => sync::export(Path::new(dir)),
+            _ => Err("usage: automaat export DIR".into()),
+        },
+        Some("apply") => {
+            let (dir, options) = apply_args(args.get(2..).unwrap_or_default())?;
+            sync::apply(Path::new(dir), options)
+        }
+        Some("rotate-secret") => match args.get(2..).unwrap_or_default() {
+            [] => encryption::rotate_secret(),
+            _ => Err("usage: automaat rotate-secret".into()),
+        },
+        _ => Err(USAGE.into()),
     };
 
     if let Err(err) = run() {
@@ -88,12 +115,78 @@ fn main() {
     }
 }
 
+/// Returns the value of the `--concurrency` flag of the worker command, if
+/// provided.
+fn concurrency_flag(args: &[String]) -> Result<Option<usize>, Box<dyn Error>> {
+    match args {
+        [] => Ok(None),
+        [flag, value] if flag == "--concurrency" => worker::parse_concurrency(value).map(Some),
+        _ => Err("usage: automaat worker [--concurrency N]".into()),
+    }
+}
+
+/// Returns the directory and options of the apply command.
+fn apply_args(args: &[String]) -> Result<(&str, ApplyOptions), Box<dyn Error>> {
+    let usage = "usage: automaat apply DIR [--dry-run] [--prune]";
+    let mut options = ApplyOptions::default();
+    let mut dir = None;
+
+    for arg in args {
+        match arg.as_str() {
+            "--dry-run" => options.dry_run = true,
+            "--prune" => options.prune = true,
+            flag if flag.starts_with("--") => return Err(usage.into()),
+            value if dir.is_none() => dir = Some(value),
+            _ => return Err(usage.into()),
+        }
+    }
+
+    dir.map(|dir| (dir, options)).ok_or_else(|| usage.into())
+}
+
 // Embeds all migrations inside the binary, so that they can be run when needed
 // on startup.
 embed_migrations!();
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+
+    #[test]
+    fn test_concurrency_flag() {
+        let args = vec!["--concurrency".to_owned(), "4".to_owned()];
+
+        assert_eq!(concurrency_flag(&args).unwrap(), Some(4));
+    }
+
+    #[test]
+    fn test_concurrency_flag_missing() {
+        assert_eq!(concurrency_flag(&[]).unwrap(), None);
+    }
+
+    #[test]
+    fn test_concurrency_flag_invalid() {
+        assert!(concurrency_flag(&["--concurrency".to_owned()]).is_err());
+        assert!(concurrency_flag(&["--threads".to_owned(), "4".to_owned()]).is_err());
+    }
+
+    #[test]
+    fn test_apply_args() {
+        let args = vec!["--dry-run".to_owned(), "tasks".to_owned()];
+        let (dir, options) = apply_args(&args).unwrap();
+
+        assert_eq!(dir, "tasks");
+        assert!(options.dry_run);
+        assert!(!options.prune);
+    }
+
+    #[test]
+    fn test_apply_args_invalid() {
+        assert!(apply_args(&[]).is_err());
+        assert!(apply_args(&["--force".to_owned(), "tasks".to_owned()]).is_err());
+        assert!(apply_args(&["one".to_owned(), "two".to_owned()]).is_err());
+    }
+
     #[test]
     fn test_readme_deps() {
         version_sync::assert_markdown_deps_updated!("README.md");
@@ -1,7 +1,8 @@
+use crate::encryption::{decrypt, encrypt, Decrypt, Encrypt};
 use crate::schema::global_variables;
-use crate::ENCRYPTION_SECRET;
+use crate::ENCRYPTION_KEYS;
+use chrono::{NaiveDateTime, Utc};
 use diesel::prelude::*;
-use diesel::sql_types::{Bytea, Text};
 
 /// The model representing a global variable stored in the database.
 #[derive(Debug, Identifiable, Queryable)]
@@ -9,6 +10,21 @@ pub(crate) struct GlobalVariable {
     pub(crate) id: i32,
     pub(crate) key: String,
     pub(crate) value: String,
+
+    /// Whether the value is masked in anything stored about the jobs using
+    /// it.
+    pub(crate) secret: bool,
+
+    /// An optional description of what the global variable is used for.
+    pub(crate) description: Option<String>,
+
+    /// The task labels to which the global variable is scoped.
+    ///
+    /// If empty, the global variable is available to all tasks.
+    pub(crate) labels: Vec<String>,
+
+    pub(crate) created_at: NaiveDateTime,
+    pub(crate) updated_at: NaiveDateTime,
 }
 
 impl GlobalVariable {
@@ -19,7 +35,6 @@ impl GlobalVariable {
 
     /// Build a query that searches for a specific global variable, based on the
     /// provided key.
-    #[allow(dead_code)]
     pub(crate) fn by_key(key: &str) -> ByKey<'_> {
         Self::all().filter(Self::with_key(key))
     }
@@ -29,6 +44,15 @@ impl GlobalVariable {
     pub(crate) fn all() -> All {
         global_variables::table.select(all_columns())
     }
+
+    /// Returns `true` if the global variable is available to a task with the
+    /// provided labels.
+    ///
+    /// A global variable without labels is available to all tasks. Otherwise,
+    /// the task has to carry at least one of the labels of the variable.
+    pub(crate) fn is_available_to(&self, labels: &[String]) -> bool {
+        self.labels.is_empty() || self.labels.iter().any(|label| labels.contains(label))
+    }
 }
 
 /// Use this struct to create a new global variable.
@@ -36,7 +60,12 @@ impl GlobalVariable {
 #[table_name = "global_variables"]
 pub(crate) struct NewGlobalVariable<'a> {
     key: &'a str,
-    value: pgp_sym_encrypt::HelperType<&'a str, &'static str>,
+    value: Encrypt<'a>,
+    key_id: &'static str,
+    secret: bool,
+    description: Option<&'a str>,
+    labels: Option<Vec<&'a str>>,
+    updated_at: NaiveDateTime,
 }
 
 impl<'a> NewGlobalVariable<'a> {
@@ -47,10 +76,35 @@ impl<'a> NewGlobalVariable<'a> {
     pub(crate) fn new(key: &'a str, value: &'a str) -> Self {
         Self {
             key,
-            value: pgp_sym_encrypt(value, ENCRYPTION_SECRET.as_str()),
+            value: encrypt(value),
+            key_id: ENCRYPTION_KEYS.active_id(),
+            secret: false,
+            description: None,
+            labels: None,
+            updated_at: Utc::now().naive_utc(),
         }
     }
 
+    /// Mark the global variable as secret, masking its value in anything
+    /// stored about the jobs using it.
+    pub(crate) fn with_secret(&mut self, secret: bool) {
+        self.secret = secret
+    }
+
+    /// Describe what the global variable is used for.
+    pub(crate) fn with_description(&mut self, description: &'a str) {
+        self.description = Some(description)
+    }
+
+    /// Scope the global variable to tasks carrying at least one of the
+    /// provided labels.
+    ///
+    /// If no labels are set, the labels of an updated variable are left
+    /// unchanged, and a new variable is available to all tasks.
+    pub(crate) fn with_labels(&mut self, labels: Vec<&'a str>) {
+        self.labels = Some(labels)
+    }
+
     /// Save the new global variable in the database.
     ///
     /// If an existing variable exists with the same key, this method will
@@ -83,7 +137,12 @@ impl<'a> NewGlobalVariable<'a> {
 type AllColumns = (
     global_variables::id,
     global_variables::key,
-    pgp_sym_decrypt::HelperType<global_variables::value, &'static str>,
+    Decrypt<global_variables::value, global_variables::key_id>,
+    global_variables::secret,
+    global_variables::description,
+    global_variables::labels,
+    global_variables::created_at,
+    global_variables::updated_at,
 );
 
 type All = diesel::dsl::Select<global_variables::table, AllColumns>;
@@ -94,9 +153,40 @@ fn all_columns() -> AllColumns {
     (
         global_variables::id,
         global_variables::key,
-        pgp_sym_decrypt(global_variables::value, ENCRYPTION_SECRET.as_str()),
+        decrypt(global_variables::value, global_variables::key_id),
+        global_variables::secret,
+        global_variables::description,
+        global_variables::labels,
+        global_variables::created_at,
+        global_variables::updated_at,
     )
 }
 
-sql_function!(fn pgp_sym_encrypt(data: Text, secret: Text) -> Bytea);
-sql_function!(fn pgp_sym_decrypt(data: Bytea, secret: Text) -> Text);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stub_variable(labels: &[&str]) -> GlobalVariable {
+        GlobalVariable {
+            id: 0,
+            key: "foo".to_owned(),
+            value: "bar".to_owned(),
+            secret: false,
+            description: None,
+            labels: labels.iter().map(|l| (*l).to_owned()).collect(),
+            created_at: Utc::now().naive_utc(),
+            updated_at: Utc::now().naive_utc(),
+        }
+    }
+
+    #[test]
+    fn test_is_available_to() {
+        let labels = vec!["deploy".to_owned()];
+
+        assert!(stub_variable(&[]).is_available_to(&labels));
+        assert!(stub_variable(&[]).is_available_to(&[]));
+        assert!(stub_variable(&["deploy", "billing"]).is_available_to(&labels));
+        assert!(!stub_variable(&["billing"]).is_available_to(&labels));
+        assert!(!stub_variable(&["billing"]).is_available_to(&[]));
+    }
+}
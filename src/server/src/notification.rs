@@ -0,0 +1,279 @@
+//! Notifications about changes to jobs, sent using Postgres' `NOTIFY`
+//! mechanism.
+//!
+//! Any connection can send a [`Notification`] using [`notify`]. Notifications
+//! sent within a transaction are only delivered once the transaction commits.
+//!
+//! A [`Listener`] uses its own (non-Diesel) database connection to `LISTEN`
+//! for notifications, which allows workers to wake up as soon as a new job is
+//! pending, instead of polling the database.
+//!
+//! The listener accepts the same database URLs as Diesel, both in URI and in
+//! `key=value` format, and honors their `sslmode`.
+
+use crate::resources::{JobStatus, JobStepStatus};
+use diesel::prelude::*;
+use diesel::sql_types::Text;
+use futures::{stream, Future, Stream};
+use openssl::error::ErrorStack;
+use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
+use postgres_openssl::MakeTlsConnector;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::runtime::current_thread::Runtime;
+use tokio_postgres::{AsyncMessage, Client, Config};
+
+/// The Postgres channel used to send and receive notifications.
+const CHANNEL: &str = "automaat";
+
+/// A notification describing a change to a job.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(tag = "type")]
+pub(crate) enum Notification {
+    /// The status of a job changed.
+    JobStatus { job_id: i32, status: JobStatus },
+
+    /// The status of a job step changed.
+    JobStepStatus {
+        job_id: i32,
+        job_step_id: i32,
+        status: JobStepStatus,
+    },
+
+    /// New lines were added to the log of a job step.
+    JobStepLog { job_id: i32, job_step_id: i32 },
+}
+
+impl Notification {
+    /// The ID of the job to which the notification applies.
+    pub(crate) fn job_id(&self) -> i32 {
+        match *self {
+            Notification::JobStatus { job_id, .. }
+            | Notification::JobStepStatus { job_id, .. }
+            | Notification::JobStepLog { job_id, .. } => job_id,
+        }
+    }
+}
+
+/// Send a notification to all listeners.
+pub(crate) fn notify(conn: &PgConnection, notification: &Notification) -> QueryResult<()> {
+    let payload = serde_json::to_string(notification)
+        .map_err(|err| diesel::result::Error::SerializationError(err.into()))?;
+
+    diesel::sql_query("SELECT pg_notify($1, $2)")
+        .bind::<Text, _>(CHANNEL)
+        .bind::<Text, _>(payload)
+        .execute(conn)
+        .map(|_| ())
+}
+
+/// A notification as received by the connection of a [`Listener`], or the
+/// error that closed the connection.
+type Received = Result<tokio_postgres::Notification, tokio_postgres::Error>;
+
+/// Listens for notifications sent by any connection to the database.
+pub(crate) struct Listener {
+    /// The connection is closed once the client is dropped.
+    _client: Client,
+    notifications: mpsc::Receiver<Received>,
+}
+
+impl Listener {
+    /// Open a new database connection, and start listening for
+    /// notifications.
+    ///
+    /// The connection runs on its own thread, which forwards the
+    /// notifications to the listener.
+    pub(crate) fn new(database_url: &str) -> Result<Self, Box<dyn Error>> {
+        let config = database_url.parse::<Config>()?;
+        let tls = tls_connector()?;
+        let (ready, connected) = mpsc::channel();
+        let (sender, notifications) = mpsc::channel();
+
+        let _ = thread::spawn(move || match listen(&config, tls, sender) {
+            Err(err) => {
+                let _ = ready.send(Err(err.to_string()));
+            }
+            Ok((mut runtime, client)) => {
+                if ready.send(Ok(client)).is_ok() {
+                    let _ = runtime.run();
+                }
+            }
+        });
+
+        Ok(Self {
+            _client: connected.recv()??,
+            notifications,
+        })
+    }
+
+    /// Block until a notification matching the predicate is received, or the
+    /// timeout passes.
+    ///
+    /// Notifications not matching the predicate are discarded.
+    pub(crate) fn wait_for<F>(
+        &self,
+        timeout: Duration,
+        mut predicate: F,
+    ) -> Result<Option<Notification>, Box<dyn Error>>
+    where
+        F: FnMut(&Notification) -> bool,
+    {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+
+            let notification = match self.notifications.recv_timeout(deadline - now) {
+                Ok(received) => received?,
+                Err(RecvTimeoutError::Timeout) => return Ok(None),
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err("notification listener disconnected".into())
+                }
+            };
+
+            if notification.channel() != CHANNEL {
+                continue;
+            }
+
+            match serde_json::from_str(notification.payload()) {
+                Ok(notification) if predicate(&notification) => return Ok(Some(notification)),
+                _ => continue,
+            }
+        }
+    }
+}
+
+/// Connect to the database, and start listening for notifications.
+///
+/// The returned runtime has to run for notifications to be forwarded to the
+/// sender. It keeps running until the returned client is dropped, or the
+/// connection fails, in which case the error is forwarded as well.
+fn listen(
+    config: &Config,
+    tls: MakeTlsConnector,
+    sender: Sender<Received>,
+) -> Result<(Runtime, Client), Box<dyn Error + Send + Sync>> {
+    let mut runtime = Runtime::new()?;
+    let (mut client, mut connection) = runtime.block_on(config.connect(tls))?;
+    let errors = sender.clone();
+
+    let forward = stream::poll_fn(move || connection.poll_message())
+        .map_err(Some)
+        .for_each(move |message| match message {
+            AsyncMessage::Notification(notification) => {
+                sender.send(Ok(notification)).map_err(|_| None)
+            }
+            _ => Ok(()),
+        })
+        .then(move |result| {
+            if let Err(Some(err)) = result {
+                let _ = errors.send(Err(err));
+            }
+
+            Ok(())
+        });
+
+    let _ = runtime.spawn(forward);
+    let _ = runtime.block_on(
+        client
+            .simple_query(&format!("LISTEN {}", CHANNEL))
+            .collect(),
+    )?;
+
+    Ok((runtime, client))
+}
+
+/// Returns a connector used to connect to the database over TLS, if its
+/// `sslmode` asks for it.
+///
+/// The certificate of the server is not verified, matching the `prefer` and
+/// `require` modes of libpq.
+fn tls_connector() -> Result<MakeTlsConnector, ErrorStack> {
+    let mut builder = SslConnector::builder(SslMethod::tls())?;
+    builder.set_verify(SslVerifyMode::NONE);
+
+    Ok(MakeTlsConnector::new(builder.build()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{connection, database_url};
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn listener() -> Listener {
+        Listener::new(&database_url()).unwrap()
+    }
+
+    #[test]
+    fn test_notify_round_trip() {
+        let listener = listener();
+        let notification = Notification::JobStepLog {
+            job_id: -1,
+            job_step_id: -2,
+        };
+
+        notify(&connection(), &notification).unwrap();
+
+        let received = listener
+            .wait_for(TIMEOUT, |n| n.job_id() == -1)
+            .unwrap()
+            .unwrap();
+
+        match received {
+            Notification::JobStepLog {
+                job_id: -1,
+                job_step_id: -2,
+            } => {}
+            _ => panic!("unexpected notification: {:?}", received),
+        }
+    }
+
+    #[test]
+    fn test_notify_discards_unmatched() {
+        let listener = listener();
+        let conn = connection();
+
+        for job_id in &[-3, -4] {
+            let notification = Notification::JobStatus {
+                job_id: *job_id,
+                status: JobStatus::Failed,
+            };
+
+            notify(&conn, &notification).unwrap();
+        }
+
+        let received = listener.wait_for(TIMEOUT, |n| n.job_id() == -4).unwrap();
+        assert_eq!(received.map(|n| n.job_id()), Some(-4));
+
+        let received = listener
+            .wait_for(Duration::from_millis(100), |n| n.job_id() == -3)
+            .unwrap();
+        assert!(received.is_none());
+    }
+
+    #[test]
+    fn test_notify_after_commit() {
+        let listener = listener();
+        let conn = connection();
+        let notification = Notification::JobStatus {
+            job_id: -5,
+            status: JobStatus::Cancelled,
+        };
+
+        conn.test_transaction::<_, diesel::result::Error, _>(|| notify(&conn, &notification));
+
+        let received = listener
+            .wait_for(Duration::from_millis(100), |n| n.job_id() == -5)
+            .unwrap();
+        assert!(received.is_none());
+    }
+}
@@ -4,15 +4,29 @@ use serde::{Deserialize, Serialize};
 use std::convert::TryFrom;
 use std::error;
 
+mod require_approval;
+mod run_task;
+
+pub(crate) use require_approval::RequireApproval;
+pub(crate) use run_task::RunTask;
+
 // Macro to create all required processor implementations without having to
 // change tens of lines for every new processor added.
 //
+// Processors before the `;` are separate crates, processors after it are
+// defined by the server itself, and are run by the job step, instead of
+// through `Processor::run`.
+//
 // See the end of this file for the actual usage.
 macro_rules! impl_processors {
-    ($($name:ident: $processor:ident),+) => {
+    (
+        $($name:ident: $processor:ident),+;
+        $($server_name:ident: $server_processor:ident),+
+    ) => {
         #[derive(Clone, Debug, Serialize, Deserialize)]
         pub(crate) enum Processor {
-            $($processor($processor)),+
+            $($processor($processor)),+,
+            $($server_processor($server_processor)),+
         }
 
         impl Processor {
@@ -21,7 +35,11 @@ macro_rules! impl_processors {
                 context: &Context,
             ) -> Result<Option<String>, Box<dyn error::Error>> {
                 match self {
-                    $(Processor::$processor(p) => p.run(context).map_err(Into::into)),+
+                    $(Processor::$processor(p) => p.run(context).map_err(Into::into)),+,
+                    $(Processor::$server_processor(_) => Err(concat!(
+                        stringify!($server_processor),
+                        " processor can only run as part of a job"
+                    ).into())),+
                 }
             }
         }
@@ -30,6 +48,7 @@ macro_rules! impl_processors {
         // create types such as `GitClineInput`, etc...
         paste::item! {
             $(use [<processor_ $name _v1>]::{$processor, Input as [<$processor Input>]};)+
+            $(use $server_name::Input as [<$server_processor Input>];)+
 
             // NOTE: GraphQL does not support union input types, so this struct
             // with one field for each (optional) processor type is the best we
@@ -40,7 +59,8 @@ macro_rules! impl_processors {
             #[derive(Clone, Debug, Serialize, Deserialize, GraphQLInputObject)]
             #[graphql(name = "ProcessorInput")]
             pub(crate) struct Input {
-                $($name: Option<[<$processor Input>]>),+
+                $($name: Option<[<$processor Input>]>),+,
+                $($server_name: Option<[<$server_processor Input>]>),+
             }
         }
 
@@ -74,6 +94,7 @@ macro_rules! impl_processors {
             fn try_from(input: Input) -> Result<Self, Self::Error> {
                 let mut i = 0;
                 $(i = input.$name.iter().fold(i, |i, _| i + 1));+;
+                $(i = input.$server_name.iter().fold(i, |i, _| i + 1));+;
 
                 if i != 1 {
                     return Err("must provide exactly one processor input value".into());
@@ -83,6 +104,10 @@ macro_rules! impl_processors {
                     return Ok(Processor::$processor(processor.into()));
                 })+
 
+                $(if let Some(processor) = input.$server_name {
+                    return Ok(Processor::$server_processor(processor.into()));
+                })+
+
                 unreachable!()
             }
         }
@@ -92,6 +117,10 @@ macro_rules! impl_processors {
                 $(&$processor => match *self {
                     Processor::$processor(ref p) => Some(p),
                     _ => None
+                }),+,
+                $(&$server_processor => match *self {
+                    Processor::$server_processor(ref p) => Some(p),
+                    _ => None
                 }),+
             }
         });
@@ -104,6 +133,9 @@ macro_rules! impl_processors {
 // not `GitCloneV1`. If we ever have a need to do breaking changes, we'll add a
 // `GitClonev2` option alongside the regular `GitClone` one, and deprecate the
 // regular one.
+//
+// The `RequireApproval` and `RunTask` processors are defined by the server, see
+// the `require_approval` and `run_task` modules.
 impl_processors! {
     git_clone:     GitClone,
     http_request:  HttpRequest,
@@ -112,5 +144,52 @@ impl_processors! {
     redis_command: RedisCommand,
     shell_command: ShellCommand,
     sql_query:     SqlQuery,
-    string_regex:  StringRegex
+    string_regex:  StringRegex;
+
+    require_approval: RequireApproval,
+    run_task:         RunTask
+}
+
+/// The Redis commands that only read data.
+const READ_ONLY_REDIS_COMMANDS: &[&str] = &[
+    "EXISTS",
+    "GET",
+    "HGET",
+    "HGETALL",
+    "HKEYS",
+    "HVALS",
+    "KEYS",
+    "LINDEX",
+    "LRANGE",
+    "MGET",
+    "SMEMBERS",
+    "SRANDMEMBER",
+    "ZRANGE",
+    "ZRANGEBYSCORE",
+    "ZREVRANGE",
+];
+
+impl Processor {
+    /// Returns `true` if running the processor can't change anything, which
+    /// makes it safe to run outside of a job.
+    ///
+    /// The SQL query processor only runs `SELECT` statements, so it is always
+    /// considered read-only.
+    pub(crate) fn is_read_only(&self) -> bool {
+        use processor_http_request_v1::Method;
+
+        match self {
+            Self::JsonEdit(_) | Self::PrintOutput(_) | Self::SqlQuery(_) | Self::StringRegex(_) => {
+                true
+            }
+            Self::HttpRequest(p) => p.method == Method::GET,
+            Self::RedisCommand(p) => {
+                READ_ONLY_REDIS_COMMANDS.contains(&p.command.to_uppercase().as_str())
+            }
+            Self::GitClone(_)
+            | Self::ShellCommand(_)
+            | Self::RequireApproval(_)
+            | Self::RunTask(_) => false,
+        }
+    }
 }
@@ -0,0 +1,48 @@
+//! The `RequireApproval` processor pauses a job until it is approved, or
+//! rejected, by a session with the configured privilege.
+//!
+//! No worker runs the job while it is paused. Once the job continues, it runs
+//! in a new workspace, so files written by the steps that ran before are no
+//! longer available, and any secrets in the output of those steps are masked.
+//!
+//! Similar to the `RunTask` processor, this processor needs access to the
+//! database, and is run by the job step itself. See [`JobStep`] for more
+//! details.
+//!
+//! [`JobStep`]: crate::resources::JobStep
+
+use juniper::{GraphQLInputObject, GraphQLObject};
+use serde::{Deserialize, Serialize};
+
+/// The processor configuration.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, GraphQLObject)]
+pub(crate) struct RequireApproval {
+    /// The privilege a session needs to approve or reject the job.
+    pub(crate) privilege: String,
+
+    /// An optional message shown to the approvers, explaining what they are
+    /// approving.
+    ///
+    /// The message is a template, and can use the same data as the templates
+    /// of any other processor.
+    pub(crate) message: Option<String>,
+}
+
+/// The GraphQL [Input Object][io] used to initialize the processor via an API.
+///
+/// [io]: https://graphql.github.io/graphql-spec/June2018/#sec-Input-Objects
+#[graphql(name = "RequireApprovalInput")]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, GraphQLInputObject)]
+pub(crate) struct Input {
+    privilege: String,
+    message: Option<String>,
+}
+
+impl From<Input> for RequireApproval {
+    fn from(input: Input) -> Self {
+        Self {
+            privilege: input.privilege,
+            message: input.message,
+        }
+    }
+}
@@ -0,0 +1,72 @@
+//! The `RunTask` processor runs another task as a sub-job of the job it is
+//! part of.
+//!
+//! Contrary to the other processors, this processor is not a separate crate,
+//! as it needs access to the database to create the sub-job, and is run by
+//! the job step itself. See [`JobStep`] for more details.
+//!
+//! [`JobStep`]: crate::resources::JobStep
+
+use juniper::{GraphQLInputObject, GraphQLObject};
+use serde::{Deserialize, Serialize};
+
+/// The processor configuration.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, GraphQLObject)]
+pub(crate) struct RunTask {
+    /// The name of the task to run as a sub-job.
+    pub(crate) task: String,
+
+    /// The variable values to run the task with.
+    ///
+    /// Values are templates, and can use the same data as the templates of
+    /// any other processor.
+    pub(crate) variables: Vec<Variable>,
+}
+
+/// A variable value provided to the task that runs as a sub-job.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, GraphQLObject)]
+#[graphql(name = "RunTaskVariable")]
+pub(crate) struct Variable {
+    /// The key of the task variable.
+    pub(crate) key: String,
+
+    /// The value of the task variable.
+    pub(crate) value: String,
+}
+
+/// The GraphQL [Input Object][io] used to initialize the processor via an API.
+///
+/// [io]: https://graphql.github.io/graphql-spec/June2018/#sec-Input-Objects
+#[graphql(name = "RunTaskInput")]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, GraphQLInputObject)]
+pub(crate) struct Input {
+    task: String,
+    variables: Option<Vec<VariableInput>>,
+}
+
+/// The GraphQL input object for a [`Variable`].
+#[graphql(name = "RunTaskVariableInput")]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, GraphQLInputObject)]
+pub(crate) struct VariableInput {
+    key: String,
+    value: String,
+}
+
+impl From<Input> for RunTask {
+    fn from(input: Input) -> Self {
+        let variables = input
+            .variables
+            .unwrap_or_default()
+            .into_iter()
+            .map(|variable| Variable {
+                key: variable.key,
+                value: variable.value,
+            })
+            .collect();
+
+        Self {
+            task: input.task,
+            variables,
+        }
+    }
+}
@@ -1,23 +1,41 @@
 mod global_variable;
 mod job;
+mod schedule;
 mod session;
 mod step;
 mod task;
 pub(crate) mod variable;
 
 pub(crate) use global_variable::graphql::GlobalVariableInput;
+pub(crate) use job::secrets::Secrets;
+pub(crate) use job::step::approval::JobStepApproval;
+pub(crate) use job::step::log::{LogHandle, LogWriter, StreamMapping as JobStepLogStreamMapping};
 pub(crate) use job::step::{
-    JobStep, NewJobStep, Status as JobStepStatus, StatusMapping as JobStepStatusMapping,
+    JobStep, NewJobStep, Results as JobStepResults, Status as JobStepStatus,
+    StatusMapping as JobStepStatusMapping,
 };
 pub(crate) use job::variable::{graphql::JobVariableInput, JobVariable, NewJobVariable};
 pub(crate) use job::{
-    graphql::CreateJobFromTaskInput, Job, NewJob, StatusMapping as JobStatusMapping,
+    graphql::CreateJobFromTaskInput, Job, NewJob, Recovery as JobRecovery, Status as JobStatus,
+    StatusMapping as JobStatusMapping,
 };
+pub(crate) use schedule::graphql::{CreateScheduleInput, UpdateScheduleInput};
+pub(crate) use schedule::{NewSchedule, Schedule};
 pub(crate) use session::graphql::{CreateSessionInput, UpdatePrivilegesInput};
-pub(crate) use step::{graphql::CreateStepInput, NewStep, Step};
+pub(crate) use step::dependencies::Graph as StepGraph;
+pub(crate) use step::retry_policy::{graphql::RetryPolicyInput, RetryPolicy};
+pub(crate) use step::{
+    graphql::CreateStepInput, NewStep, RunWhen as StepRunWhen,
+    RunWhenMapping as StepRunWhenMapping, Step,
+};
+pub(crate) use task::definition::{Change, Definition};
+pub(crate) use task::version::TaskVersion;
 pub(crate) use task::{
-    graphql::{CreateTaskInput, SearchTaskInput},
-    NewTask, Task,
+    graphql::{CreateTaskInput, SearchTaskInput, UpdateTaskInput},
+    NewTask, Task, TaskPatch,
+};
+pub(crate) use variable::constraints::{
+    Constraints as VariableConstraints, Kind as VariableKind, KindMapping as VariableKindMapping,
 };
 pub(crate) use variable::{graphql::CreateVariableInput, NewVariable, Variable};
 
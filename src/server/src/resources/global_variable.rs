@@ -1,5 +1,5 @@
 use super::OnConflict;
-use crate::models::NewGlobalVariable;
+use crate::models::{GlobalVariable, NewGlobalVariable};
 use serde::{Deserialize, Serialize};
 
 pub(crate) mod graphql {
@@ -14,7 +14,10 @@ pub(crate) mod graphql {
     //! mutation, and type documentation.
 
     use super::*;
-    use juniper::GraphQLInputObject;
+    use crate::graphql::authorization_guard;
+    use crate::server::RequestState;
+    use chrono::{DateTime, Utc};
+    use juniper::{object, GraphQLInputObject, ID};
 
     /// Create a new global variable.
     #[derive(Clone, Debug, Deserialize, Serialize, GraphQLInputObject)]
@@ -34,6 +37,28 @@ pub(crate) mod graphql {
         /// task variables) are encrypted at rest.
         pub(crate) value: String,
 
+        /// Whether the value of the global variable is secret (defaults to
+        /// `false`).
+        ///
+        /// A secret value is replaced by a placeholder in anything stored
+        /// about the jobs using it, such as their processor configuration,
+        /// output, and logs.
+        pub(crate) secret: Option<bool>,
+
+        /// An optional description of what the global variable is used for.
+        pub(crate) description: Option<String>,
+
+        /// An optional set of task labels to which the global variable is
+        /// scoped.
+        ///
+        /// A scoped global variable is only available to tasks carrying at
+        /// least one of these labels. Without labels, the global variable is
+        /// available to all tasks.
+        ///
+        /// When updating an existing global variable, its labels are left
+        /// unchanged if none are provided.
+        pub(crate) labels: Option<Vec<String>>,
+
         /// Define what to do when the global variable key already exists.
         ///
         /// By default, updating an existing key is disallowed, to prevent
@@ -44,10 +69,82 @@ pub(crate) mod graphql {
         /// updated to the newly provided value.
         pub(crate) on_conflict: Option<OnConflict>,
     }
+
+    #[object(Context = RequestState)]
+    impl GlobalVariable {
+        /// The unique identifier for a specific global variable.
+        fn id() -> ID {
+            ID::new(self.id.to_string())
+        }
+
+        /// The key of the global variable, by which it is referenced in task
+        /// templates.
+        fn key() -> &str {
+            self.key.as_str()
+        }
+
+        /// The value of the global variable.
+        ///
+        /// This field returns `null`, unless the `query_global_variable_values`
+        /// privilege is set for the provided session. If the global variable
+        /// is scoped to any labels, one of those labels also has to be set as
+        /// a privilege.
+        fn value(context: &RequestState) -> Option<&str> {
+            let labels = self.labels.iter().map(String::as_str).collect::<Vec<_>>();
+            let privileged =
+                authorization_guard(&["query_global_variable_values"], &context.session).is_ok()
+                    && authorization_guard(&labels, &context.session).is_ok();
+
+            if privileged {
+                Some(self.value.as_str())
+            } else {
+                None
+            }
+        }
+
+        /// Whether the value is masked in anything stored about the jobs
+        /// using it.
+        fn secret() -> bool {
+            self.secret
+        }
+
+        /// An optional description of what the global variable is used for.
+        fn description() -> Option<&str> {
+            self.description.as_ref().map(String::as_ref)
+        }
+
+        /// The task labels to which the global variable is scoped.
+        ///
+        /// If empty, the global variable is available to all tasks.
+        fn labels() -> Vec<&str> {
+            self.labels.iter().map(String::as_str).collect()
+        }
+
+        /// The moment at which the global variable was created.
+        fn created_at() -> DateTime<Utc> {
+            DateTime::from_utc(self.created_at, Utc)
+        }
+
+        /// The moment at which the global variable was last updated.
+        fn updated_at() -> DateTime<Utc> {
+            DateTime::from_utc(self.updated_at, Utc)
+        }
+    }
 }
 
 impl<'a> From<&'a graphql::GlobalVariableInput> for NewGlobalVariable<'a> {
     fn from(input: &'a graphql::GlobalVariableInput) -> Self {
-        Self::new(&input.key, &input.value)
+        let mut variable = Self::new(&input.key, &input.value);
+        variable.with_secret(input.secret.unwrap_or(false));
+
+        if let Some(description) = &input.description {
+            variable.with_description(description);
+        }
+
+        if let Some(labels) = &input.labels {
+            variable.with_labels(labels.iter().map(String::as_str).collect());
+        }
+
+        variable
     }
 }
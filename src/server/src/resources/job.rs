@@ -5,22 +5,37 @@
 //! a set of steps that are _ready to run_ and have their variables swapped for
 //! real values.
 
-use crate::resources::{JobStep, JobStepStatus, JobVariable, NewJobStep, NewJobVariable, Task};
+use crate::encryption::decrypt;
+use crate::models::GlobalVariable;
+use crate::notification::{self, Notification};
+use crate::resources::{
+    JobStep, JobStepResults, JobStepStatus, JobVariable, LogHandle, NewJobStep, NewJobVariable,
+    Secrets, StepGraph, StepRunWhen, Task, TaskVersion,
+};
 use crate::schema::jobs;
-use crate::{server::RequestState, ENCRYPTION_SECRET};
+use crate::server::RequestState;
+use advertiser::Advertiser;
 use automaat_core::Context;
+use chrono::{NaiveDateTime, Utc};
 use diesel::prelude::*;
 use juniper::GraphQLEnum;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::convert::{Into, TryInto};
+use std::convert::{Into, TryFrom, TryInto};
 use std::error::Error;
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+use step::AwaitingApproval;
 
+pub(crate) mod advertiser;
+pub(crate) mod secrets;
 pub(crate) mod step;
 pub(crate) mod variable;
 
 /// The status of the [`Job`].
-#[derive(Clone, Copy, Debug, Serialize, Deserialize, GraphQLEnum, DbEnum)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, GraphQLEnum, DbEnum)]
 #[PgType = "JobStatus"]
 #[graphql(name = "JobStatus")]
 pub(crate) enum Status {
@@ -34,6 +49,10 @@ pub(crate) enum Status {
     /// The job is currently running its steps one by one.
     Running,
 
+    /// The job is paused until a privileged user approves or rejects one of
+    /// its steps. No worker runs the job while it waits.
+    Waiting,
+
     /// One of the job steps failed, resulting in the job itself to fail.
     Failed,
 
@@ -44,6 +63,34 @@ pub(crate) enum Status {
     Ok,
 }
 
+/// The way a worker recovers a job that was orphaned by another worker.
+///
+/// A job is orphaned if it is running, but the worker running it stopped
+/// sending heartbeats, for example because the worker crashed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Recovery {
+    /// Mark the job and its running step as failed.
+    Fail,
+
+    /// Mark the job as pending, so that it runs again from its first step.
+    Requeue,
+}
+
+impl FromStr for Recovery {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fail" => Ok(Recovery::Fail),
+            "requeue" => Ok(Recovery::Requeue),
+            _ => Err(format!(
+                "unknown job recovery (expected fail or requeue): {}",
+                s
+            )),
+        }
+    }
+}
+
 impl From<JobStepStatus> for Status {
     fn from(status: JobStepStatus) -> Self {
         use Status::*;
@@ -54,7 +101,7 @@ impl From<JobStepStatus> for Status {
             JobStepStatus::Running => Running,
             JobStepStatus::Failed => Failed,
             JobStepStatus::Cancelled => Cancelled,
-            JobStepStatus::Ok => Ok,
+            JobStepStatus::Skipped | JobStepStatus::Ok => Ok,
         }
     }
 }
@@ -78,6 +125,41 @@ pub(crate) struct Job {
     // Similarly, a job can be created separately from a task, in which case
     // this field is also `None`.
     pub(crate) task_reference: Option<i32>,
+
+    /// The moment at which a scheduled job becomes pending. If `None`, the
+    /// job was pending from the moment it was created.
+    pub(crate) run_at: Option<NaiveDateTime>,
+    pub(crate) timeout_seconds: Option<i32>,
+
+    /// The ID of the worker that last claimed the job, if any.
+    pub(crate) worker_id: Option<String>,
+
+    /// The last moment at which the worker running the job reported that it
+    /// is still running the job.
+    pub(crate) heartbeat_at: Option<NaiveDateTime>,
+
+    /// An explanation of the current status of the job, if the status was
+    /// changed by something other than the job steps, such as the recovery of
+    /// an orphaned job.
+    pub(crate) status_reason: Option<String>,
+
+    /// The job that ran this job as a sub-job, if any.
+    pub(crate) parent_id: Option<i32>,
+
+    /// The ID of the session that requested the job, if any.
+    pub(crate) requested_by: Option<i32>,
+
+    /// The version of the task the job was created from, if any. Similar to
+    /// `task_reference`, this is a weak reference.
+    pub(crate) task_version_id: Option<i32>,
+
+    /// Whether missing values of required task variables are resolved by
+    /// running the tasks advertising them, before running the job steps.
+    pub(crate) resolve_variables: bool,
+
+    /// The key of the variable of the parent job for which this job provided
+    /// the value, if it ran to resolve that variable.
+    pub(crate) provides_variable: Option<String>,
 }
 
 impl Job {
@@ -91,14 +173,86 @@ impl Job {
             .optional()
     }
 
-    pub(crate) fn as_running(&mut self, conn: &PgConnection) -> QueryResult<Self> {
+    /// Mark all scheduled jobs that are due to run as pending, so that they
+    /// can be picked up by a worker.
+    ///
+    /// Returns the number of jobs that became pending.
+    pub(crate) fn enqueue_due_scheduled(conn: &PgConnection) -> QueryResult<usize> {
+        use diesel::dsl::now;
+
+        let due = jobs::table
+            .filter(jobs::status.eq(Status::Scheduled))
+            .filter(jobs::run_at.le(now.nullable()));
+
+        let jobs: Vec<Self> = diesel::update(due)
+            .set(jobs::status.eq(Status::Pending))
+            .get_results(conn)?;
+
+        jobs.iter().try_for_each(|job| job.notify(conn))?;
+        Ok(jobs.len())
+    }
+
+    /// Mark the job as running, and claim it for the worker with the provided
+    /// ID.
+    ///
+    /// The worker is expected to keep sending heartbeats (see
+    /// [`Job::heartbeat`]) while the job runs, otherwise the job is considered
+    /// orphaned.
+    pub(crate) fn as_running(&mut self, conn: &PgConnection, worker_id: &str) -> QueryResult<Self> {
         self.status = Status::Running;
-        self.save_changes(conn)
+        self.worker_id = Some(worker_id.to_owned());
+        self.heartbeat_at = Some(Utc::now().naive_utc());
+        self.save_and_notify(conn)
     }
 
     pub(crate) fn as_failed(&mut self, conn: &PgConnection) -> QueryResult<Self> {
         self.status = Status::Failed;
-        self.save_changes(conn)
+        self.save_and_notify(conn)
+    }
+
+    /// Change the status of the job with the provided ID, but only if it
+    /// currently has the `from` status.
+    ///
+    /// Nothing is updated if the job has a different status, for example
+    /// because it was cancelled in the meantime.
+    pub(crate) fn transition(
+        job_id: i32,
+        from: Status,
+        to: Status,
+        conn: &PgConnection,
+    ) -> QueryResult<()> {
+        let job = jobs::table
+            .filter(jobs::id.eq(job_id))
+            .filter(jobs::status.eq(from));
+
+        let job: Option<Self> = diesel::update(job)
+            .set(jobs::status.eq(to))
+            .get_result(conn)
+            .optional()?;
+
+        match job {
+            Some(job) => job.notify(conn),
+            None => Ok(()),
+        }
+    }
+
+    fn save_and_notify(&self, conn: &PgConnection) -> QueryResult<Self> {
+        let job: Self = self.save_changes(conn)?;
+        job.notify(conn)?;
+
+        Ok(job)
+    }
+
+    /// Notify any listeners of the current status of the job.
+    ///
+    /// See [`crate::notification`] for more details.
+    pub(crate) fn notify(&self, conn: &PgConnection) -> QueryResult<()> {
+        let notification = Notification::JobStatus {
+            job_id: self.id,
+            status: self.status,
+        };
+
+        notification::notify(conn, &notification)
     }
 
     pub(crate) fn task(&self, conn: &PgConnection) -> QueryResult<Option<Task>> {
@@ -110,46 +264,732 @@ impl Job {
         }
     }
 
+    /// Returns the version of the task the job was created from, if the
+    /// version still exists.
+    pub(crate) fn task_version(&self, conn: &PgConnection) -> QueryResult<Option<TaskVersion>> {
+        use crate::schema::task_versions::dsl::*;
+
+        match self.task_version_id {
+            None => Ok(None),
+            Some(version_id) => task_versions.find(version_id).first(conn).optional(),
+        }
+    }
+
+    /// Returns the job that ran this job as a sub-job, if any.
+    pub(crate) fn parent(&self, conn: &PgConnection) -> QueryResult<Option<Self>> {
+        match self.parent_id {
+            None => Ok(None),
+            Some(parent_id) => jobs::table.find(parent_id).first(conn).optional(),
+        }
+    }
+
+    /// Returns the jobs that ran as a sub-job of this job.
+    pub(crate) fn children(&self, conn: &PgConnection) -> QueryResult<Vec<Self>> {
+        jobs::table
+            .filter(jobs::parent_id.eq(self.id))
+            .order(jobs::id.asc())
+            .load(conn)
+    }
+
+    /// Returns the output of the job, which is the output of the last step
+    /// that ran successfully, if any.
+    pub(crate) fn output(&self, conn: &PgConnection) -> QueryResult<Option<String>> {
+        Ok(self
+            .steps(conn)?
+            .into_iter()
+            .filter(|step| step.status == JobStepStatus::Ok)
+            .filter_map(|step| step.output)
+            .last())
+    }
+
+    /// Returns the steps of the job, ordered by their position.
+    ///
+    /// Child job steps, created when a job step runs for each item in a list,
+    /// are returned by their parent instead.
     pub(crate) fn steps(&self, conn: &PgConnection) -> QueryResult<Vec<JobStep>> {
         use crate::schema::job_steps::dsl::*;
 
-        JobStep::belonging_to(self).order(position.asc()).load(conn)
+        JobStep::belonging_to(self)
+            .filter(parent_id.is_null())
+            .order(position.asc())
+            .load(conn)
     }
 
     pub(crate) fn variables(&self, conn: &PgConnection) -> QueryResult<Vec<JobVariable>> {
         use crate::schema::job_variables::dsl::*;
 
-        let secret = ENCRYPTION_SECRET.as_str();
         JobVariable::belonging_to(self)
-            .select((id, key, variable::pgp_sym_decrypt(value, secret), job_id))
+            .select((id, key, decrypt(value, key_id), job_id, secret))
             .load(conn)
     }
 
-    // TODO: implement some kind of `JobRunner`, that has a reference to
-    // &Database, and then impl `Drop` so that if the runner stops, we can check
-    // the result, and update the database based on the final status.
-    pub(crate) fn run(&self, conn: &PgConnection) -> Result<(), Box<dyn Error>> {
+    /// Record that the worker with the provided ID is still running the job.
+    ///
+    /// The sub-jobs of the job (and their sub-jobs) are included, as they run
+    /// as part of the job.
+    ///
+    /// Nothing is updated if the job stopped running, or was claimed by
+    /// another worker in the meantime.
+    pub(crate) fn heartbeat(job_id: i32, worker_id: &str, conn: &PgConnection) -> QueryResult<()> {
+        let mut ids = vec![job_id];
+        let mut parent_ids = vec![job_id];
+        while !parent_ids.is_empty() {
+            parent_ids = jobs::table
+                .select(jobs::id)
+                .filter(jobs::parent_id.eq_any(&parent_ids))
+                .load(conn)?;
+
+            ids.extend(&parent_ids);
+        }
+
+        let job = jobs::table
+            .filter(jobs::id.eq_any(ids))
+            .filter(jobs::worker_id.eq(worker_id))
+            .filter(jobs::status.eq(Status::Running));
+
+        diesel::update(job)
+            .set(jobs::heartbeat_at.eq(Utc::now().naive_utc()))
+            .execute(conn)
+            .map(|_| ())
+    }
+
+    /// Recover all running jobs for which no heartbeat was received within
+    /// the provided timeout, as their worker stopped running them.
+    ///
+    /// Waiting jobs are not recovered, as no worker runs them.
+    ///
+    /// Jobs that are being recovered by another worker are skipped.
+    ///
+    /// Returns the number of recovered jobs.
+    pub(crate) fn recover_orphaned(
+        conn: &PgConnection,
+        timeout: Duration,
+        recovery: Recovery,
+    ) -> Result<usize, Box<dyn Error>> {
+        let cutoff = Utc::now().naive_utc() - chrono::Duration::from_std(timeout)?;
+
+        conn.transaction(|| {
+            let jobs: Vec<Self> = jobs::table
+                .filter(jobs::status.eq(Status::Running))
+                .filter(
+                    jobs::heartbeat_at
+                        .is_null()
+                        .or(jobs::heartbeat_at.lt(cutoff)),
+                )
+                .for_update()
+                .skip_locked()
+                .load(conn)?;
+
+            jobs.iter()
+                .try_for_each(|job| job.recover(conn, recovery))?;
+            Ok(jobs.len())
+        })
+    }
+
+    /// Recover a single orphaned job, recording the reason of the recovery on
+    /// the job, and the output of the step that was running.
+    fn recover(&self, conn: &PgConnection, recovery: Recovery) -> Result<(), Box<dyn Error>> {
+        use crate::schema::job_steps;
+
+        let worker = self.worker_id.as_ref().map_or("unknown", String::as_str);
+        let reason = match recovery {
+            Recovery::Fail => format!("worker {} stopped responding while running the job", worker),
+            Recovery::Requeue => format!("requeued after worker {} stopped responding", worker),
+        };
+
+        let steps = job_steps::table.filter(job_steps::job_id.eq(self.id));
+        let status = match recovery {
+            Recovery::Fail => {
+                let running = steps.filter(job_steps::status.eq(JobStepStatus::Running));
+                let _ = diesel::update(running)
+                    .set((
+                        job_steps::status.eq(JobStepStatus::Failed),
+                        job_steps::finished_at.eq(Utc::now().naive_utc()),
+                        job_steps::output.eq(&reason),
+                    ))
+                    .execute(conn)?;
+
+                let pending = steps.filter(job_steps::status.eq(JobStepStatus::Pending));
+                let _ = diesel::update(pending)
+                    .set(job_steps::status.eq(JobStepStatus::Cancelled))
+                    .execute(conn)?;
+
+                Status::Failed
+            }
+            Recovery::Requeue => {
+                // Child job steps are created again when their parent runs.
+                let children = steps.filter(job_steps::parent_id.is_not_null());
+                let _ = diesel::delete(children).execute(conn)?;
+
+                let _ = diesel::update(steps)
+                    .set((
+                        job_steps::status.eq(JobStepStatus::Pending),
+                        job_steps::started_at.eq(None::<NaiveDateTime>),
+                        job_steps::finished_at.eq(None::<NaiveDateTime>),
+                        job_steps::output.eq(None::<String>),
+                    ))
+                    .execute(conn)?;
+
+                Status::Pending
+            }
+        };
+
+        let job: Self = diesel::update(self)
+            .set((
+                jobs::status.eq(status),
+                jobs::worker_id.eq(None::<String>),
+                jobs::heartbeat_at.eq(None::<NaiveDateTime>),
+                jobs::status_reason.eq(&reason),
+            ))
+            .get_result(conn)?;
+
+        job.notify(conn).map_err(Into::into)
+    }
+
+    /// Release the running job from its worker, until the approval requested
+    /// by one of its steps is decided.
+    ///
+    /// Nothing is updated if the job is no longer running, for example because
+    /// it was cancelled in the meantime.
+    fn wait_for_approval(&self, conn: &PgConnection) -> Result<(), Box<dyn Error>> {
+        let job = jobs::table
+            .filter(jobs::id.eq(self.id))
+            .filter(jobs::status.eq(Status::Running));
+
+        let job: Option<Self> = diesel::update(job)
+            .set((
+                jobs::status.eq(Status::Waiting),
+                jobs::worker_id.eq(None::<String>),
+                jobs::heartbeat_at.eq(None::<NaiveDateTime>),
+            ))
+            .get_result(conn)
+            .optional()?;
+
+        match job {
+            Some(job) => job.notify(conn).map_err(Into::into),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns `true` if the job with the given ID has been cancelled.
+    pub(crate) fn is_cancelled(job_id: i32, conn: &PgConnection) -> QueryResult<bool> {
+        jobs::table
+            .filter(jobs::id.eq(job_id))
+            .select(jobs::status)
+            .first(conn)
+            .map(|status: Status| status == Status::Cancelled)
+    }
+
+    /// Cancel the job.
+    ///
+    /// Any job steps that haven't started running yet are cancelled as well.
+    /// If the job is already running, the worker running the job stops before
+    /// running the next step, and interrupts the active step, if its processor
+    /// supports it. If the job is waiting for approval, the step that requested
+    /// approval is cancelled.
+    ///
+    /// An error is returned if the job already finished running.
+    pub(crate) fn cancel(&self, conn: &PgConnection) -> Result<Self, Box<dyn Error>> {
+        use crate::schema::job_steps;
+
+        conn.transaction(|| {
+            let previous: Option<Status> = jobs::table
+                .find(self.id)
+                .select(jobs::status)
+                .for_update()
+                .first(conn)
+                .optional()?;
+
+            let unfinished = jobs::status
+                .eq(Status::Scheduled)
+                .or(jobs::status.eq(Status::Pending))
+                .or(jobs::status.eq(Status::Running))
+                .or(jobs::status.eq(Status::Waiting));
+
+            let job: Self =
+                diesel::update(jobs::table.filter(jobs::id.eq(self.id)).filter(unfinished))
+                    .set(jobs::status.eq(Status::Cancelled))
+                    .get_result(conn)
+                    .optional()?
+                    .ok_or("job already finished running")?;
+
+            let unstarted = job_steps::status
+                .eq(JobStepStatus::Initialized)
+                .or(job_steps::status.eq(JobStepStatus::Pending));
+
+            let steps = job_steps::table
+                .filter(job_steps::job_id.eq(job.id))
+                .filter(unstarted);
+
+            let _ = diesel::update(steps)
+                .set(job_steps::status.eq(JobStepStatus::Cancelled))
+                .execute(conn)?;
+
+            // No worker runs a waiting job, to cancel its running step.
+            if previous == Some(Status::Waiting) {
+                let running = job_steps::table
+                    .filter(job_steps::job_id.eq(job.id))
+                    .filter(job_steps::status.eq(JobStepStatus::Running));
+
+                let _ = diesel::update(running)
+                    .set((
+                        job_steps::status.eq(JobStepStatus::Cancelled),
+                        job_steps::finished_at.eq(Utc::now().naive_utc()),
+                    ))
+                    .execute(conn)?;
+            }
+
+            job.notify(conn)?;
+            Ok(job)
+        })
+    }
+
+    /// Run a task as a sub-job of this job, and return the output of the
+    /// sub-job.
+    ///
+    /// The sub-job is claimed by the worker running this job, and runs on the
+    /// current thread using the provided context, so that it shares the
+    /// workspace of this job, and is cancelled along with it. The output of
+    /// the sub-job is stored using the log of this job.
+    ///
+    /// An error is returned if the task is already running as this job, or as
+    /// any of its parent jobs, as the sub-job would never finish.
+    pub(crate) fn run_sub_job(
+        &self,
+        conn: &PgConnection,
+        task: &Task,
+        variables: Vec<NewJobVariable<'_>>,
+        database_url: &str,
+        context: &Context,
+        log: &LogHandle,
+    ) -> Result<Option<String>, Box<dyn Error>> {
+        self.claim_sub_job(conn, task, variables, None)?
+            .run_as_sub_job(conn, database_url, context, log)?
+            .output(conn)
+            .map_err(Into::into)
+    }
+
+    /// Create a sub-job of this job for the provided task, as described in
+    /// [`Job::run_sub_job`], and claim it for the worker running this job.
+    ///
+    /// If the sub-job runs to provide the value of a variable of this job,
+    /// the key of that variable is stored with the sub-job.
+    fn claim_sub_job(
+        &self,
+        conn: &PgConnection,
+        task: &Task,
+        variables: Vec<NewJobVariable<'_>>,
+        provides_variable: Option<&str>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut ancestor = Some(self.clone());
+        while let Some(job) = ancestor {
+            if job.task_reference == Some(task.id) {
+                return Err(format!("task {} cannot run as a sub-job of itself", task.name).into());
+            }
+
+            ancestor = job.parent(conn)?;
+        }
+
+        let worker = self
+            .worker_id
+            .as_ref()
+            .ok_or("job is not claimed by a worker")?;
+
+        // The sub-job is claimed before the transaction commits, so that no
+        // other worker picks it up.
+        conn.transaction::<_, Box<dyn Error>, _>(|| {
+            let mut job =
+                NewJob::create_from_task(conn, task, variables, None, self.requested_by, false)?;
+            job.parent_id = Some(self.id);
+            job.provides_variable = provides_variable.map(ToOwned::to_owned);
+
+            job.as_running(conn, worker).map_err(Into::into)
+        })
+    }
+
+    /// Run this job as a claimed sub-job, and return the finished sub-job.
+    ///
+    /// An error is returned if the sub-job was cancelled or did not succeed.
+    fn run_as_sub_job(
+        &self,
+        conn: &PgConnection,
+        database_url: &str,
+        context: &Context,
+        log: &LogHandle,
+    ) -> Result<Self, Box<dyn Error>> {
+        let result = self.run(conn, database_url, context, log);
+
+        if context.is_cancelled() {
+            let _ = self.cancel(conn);
+            return Err(format!("sub-job {} was cancelled", self.name).into());
+        }
+
+        if let Err(err) = result {
+            // The sub-job changed while it ran, so only its status is updated.
+            if let Err(err) = Self::transition(self.id, Status::Running, Status::Failed, conn) {
+                println!("failed to mark sub-job {} as failed: {}", self.id, err);
+            }
+
+            return Err(format!("sub-job {} failed: {}", self.name, err).into());
+        }
+
+        let job: Self = jobs::table.find(self.id).first(conn)?;
+        if job.status != Status::Ok {
+            return Err(format!("sub-job {} did not succeed", job.name).into());
+        }
+
+        Ok(job)
+    }
+
+    /// Resolve the values of the required task variables for which no value
+    /// was provided when the job was created.
+    ///
+    /// For each missing value, the task advertising the variable runs as a
+    /// sub-job, using the variables of this job that it needs. The output of
+    /// the advertising step becomes the value of the variable. Values that
+    /// are resolved first are available to the advertisers of the remaining
+    /// variables.
+    fn resolve_missing_variables(
+        &self,
+        conn: &PgConnection,
+        database_url: &str,
+        context: &Context,
+        log: &LogHandle,
+    ) -> Result<(), Box<dyn Error>> {
+        let task = match self.task(conn)? {
+            None => return Ok(()),
+            Some(task) => task,
+        };
+
+        for variable in task.variables(conn)? {
+            let variables = self.variables(conn)?;
+            if !variable.is_required() || variables.iter().any(|v| v.key == variable.key) {
+                continue;
+            }
+
+            let available = variables.iter().map(|v| v.key.as_str()).collect::<Vec<_>>();
+            let advertiser =
+                Advertiser::find(&variable.key, task.id, &available, self.requested_by, conn)?
+                    .ok_or_else(|| format!(r#"no task can provide variable "{}""#, variable.key))?;
+
+            let needed = advertiser.task.variables(conn)?;
+            let inputs = variables
+                .iter()
+                .filter(|v| needed.iter().any(|n| n.key == v.key))
+                .map(|v| {
+                    let mut input = NewJobVariable::new(&v.key, &v.value);
+                    if v.secret {
+                        input.with_secret();
+                    }
+
+                    input
+                })
+                .collect();
+
+            let job = self
+                .claim_sub_job(conn, &advertiser.task, inputs, Some(variable.key.as_str()))?
+                .run_as_sub_job(conn, database_url, context, log)?;
+
+            let value = job
+                .steps(conn)?
+                .into_iter()
+                .find(|step| step.name == advertiser.step_name)
+                .and_then(|step| step.output)
+                .ok_or_else(|| {
+                    format!(
+                        r#"step {} of sub-job {} did not provide variable "{}""#,
+                        advertiser.step_name, job.name, variable.key
+                    )
+                })?;
+
+            NewJobVariable::new(&variable.key, value.trim()).add_to_job(conn, self)?;
+        }
+
+        Ok(())
+    }
+
+    /// Run the steps of the job, in the order defined by their dependencies.
+    ///
+    /// A step runs as soon as all the steps it depends on finished
+    /// successfully, or were skipped because their condition did not hold.
+    /// If a single step is ready to run, it runs using the provided
+    /// connection. If multiple steps are ready to run, each step runs in its
+    /// own thread, using its own database connection.
+    ///
+    /// Once a step fails, no new steps are started, and the job fails after
+    /// all running steps finished.
+    ///
+    /// Cleanup steps run one by one after all other steps finished, in the
+    /// order of their position. Steps that run `ALWAYS` run regardless of the
+    /// outcome of the other steps, steps that run `ON_FAILURE` only run if any
+    /// step failed. Cleanup steps are bound by their own timeout, but not by
+    /// the timeout of the job, so that they can clean up after a job that timed
+    /// out.
+    ///
+    /// Any steps that never ran are marked as skipped, unless the job was
+    /// cancelled.
+    ///
+    /// Any output written by the step processors while running is stored using
+    /// the provided log, which is shared with any sub-jobs.
+    ///
+    /// If the job resolves missing variables, this happens before any step
+    /// runs. If a variable can't be resolved, all steps are skipped, and the
+    /// reason is stored with the job.
+    ///
+    /// Once a step requests approval, no new steps are started, and the job
+    /// waits for the request to be decided after all running steps finished.
+    /// Once decided, the job runs again, continuing at the step that requested
+    /// approval, without running the steps that already finished. The timeout
+    /// of the job starts again when it continues.
+    pub(crate) fn run(
+        &self,
+        conn: &PgConnection,
+        database_url: &str,
+        context: &Context,
+        log: &LogHandle,
+    ) -> Result<(), Box<dyn Error>> {
         use crate::schema::jobs::dsl::*;
 
-        let output: HashMap<String, String> = HashMap::default();
-        let context = Context::new()?;
         let mut steps = self.steps(conn)?;
 
-        let _ = steps
-            .iter_mut()
-            .try_fold(output, |output, step| step.run(conn, &context, output))?;
+        if self.resolve_variables {
+            if let Err(err) = self.resolve_missing_variables(conn, database_url, context, log) {
+                let reason = format!("unable to resolve variables: {}", err);
+                for step in &mut steps {
+                    step.skip(conn)?;
+                }
 
-        match steps.last() {
-            Some(step) => diesel::update(self)
-                .set(status.eq(Status::from(step.status)))
-                .execute(conn)
-                .map(|_| ())
-                .map_err(Into::into),
+                let _ = diesel::update(self)
+                    .set(status_reason.eq(&reason))
+                    .execute(conn)?;
+
+                return Err(reason.into());
+            }
+        }
+
+        let (cleanup, regular): (Vec<usize>, Vec<usize>) =
+            (0..steps.len()).partition(|&index| steps[index].run_when.is_cleanup());
+
+        // The graph only contains the regular steps, so graph indices have to
+        // be mapped to step indices.
+        let dependencies = regular
+            .iter()
+            .map(|&index| (steps[index].name.as_str(), steps[index].depends_on()))
+            .collect::<Vec<_>>();
+
+        let graph = StepGraph::new(&dependencies)?;
+        let job_deadline = deadline(self.timeout_seconds);
+        let secrets = Secrets::load(self, conn)?;
+
+        // A job that waited for approval continues where it left off.
+        let mut results = JobStepResults::default();
+        let mut started = vec![false; regular.len()];
+        let mut succeeded = vec![false; regular.len()];
+        for (position, &index) in regular.iter().enumerate() {
+            let step = &steps[index];
+            if let JobStepStatus::Ok | JobStepStatus::Skipped = step.status {
+                started[position] = true;
+                succeeded[position] = true;
+                results.succeeded(step, step.output.clone());
+            }
+        }
+
+        let mut cancelled = false;
+        let mut waiting = vec![];
+        let mut running = 0;
+        let (sender, receiver) = mpsc::channel();
+
+        loop {
+            // The job can be cancelled while it is running, in which case the
+            // remaining steps are already marked as cancelled, and we stop
+            // running them.
+            cancelled = cancelled || context.is_cancelled() || Self::is_cancelled(self.id, conn)?;
+
+            let ready = match results.failure {
+                None if !cancelled && waiting.is_empty() => graph.ready(&started, &succeeded),
+                _ => vec![],
+            };
+
+            if ready.is_empty() && running == 0 {
+                break;
+            }
+
+            for &index in &ready {
+                started[index] = true;
+            }
+
+            let (index, step, result) = match ready.as_slice() {
+                // A single step that can't run in parallel with any other step
+                // runs on the current thread.
+                &[index] if running == 0 => {
+                    let mut step = steps[regular[index]].clone();
+                    let context = step_context(context, &step, job_deadline, log, &secrets);
+                    let result = step.run(conn, &context, &results, database_url, log);
+
+                    (index, step, result.map_err(StepError::from))
+                }
+                _ => {
+                    for &index in &ready {
+                        let mut step = steps[regular[index]].clone();
+                        let context = step_context(context, &step, job_deadline, log, &secrets);
+                        let results = results.clone();
+                        let database_url = database_url.to_owned();
+                        let log = log.clone();
+                        let sender = sender.clone();
+                        running += 1;
+
+                        let _ = thread::spawn(move || {
+                            let result = PgConnection::establish(&database_url)
+                                .map_err(|err| StepError::Failed(err.to_string()))
+                                .and_then(|conn| {
+                                    step.run(&conn, &context, &results, &database_url, &log)
+                                        .map_err(StepError::from)
+                                });
+
+                            let _ = sender.send((index, step, result));
+                        });
+                    }
+
+                    running -= 1;
+                    receiver.recv()?
+                }
+            };
+
+            match result {
+                Ok(out) => {
+                    succeeded[index] = true;
+                    results.succeeded(&step, out);
+                }
+                Err(StepError::AwaitingApproval) => waiting.push(regular[index]),
+                Err(_) if context.is_cancelled() => cancelled = true,
+                Err(StepError::Failed(error)) => results.failed(&step, error),
+            };
+
+            steps[regular[index]] = step;
+        }
+
+        if !waiting.is_empty() {
+            if results.failure.is_none() && !cancelled {
+                return self.wait_for_approval(conn);
+            }
+
+            // The job won't continue at the steps waiting for approval.
+            for &index in &waiting {
+                steps[index].stop_waiting(conn, cancelled)?;
+            }
+        }
+
+        for index in cleanup {
+            cancelled = cancelled || context.is_cancelled() || Self::is_cancelled(self.id, conn)?;
+            if cancelled {
+                break;
+            }
+
+            let step = &mut steps[index];
+            if step.run_when == StepRunWhen::OnFailure && results.failure.is_none() {
+                continue;
+            }
+
+            let context = step_context(context, step, None, log, &secrets);
+            match step.run(conn, &context, &results, database_url, log) {
+                Ok(out) => results.succeeded(step, out),
+                Err(_) if context.is_cancelled() => cancelled = true,
+                Err(error) => results.failed(step, error.to_string()),
+            };
+        }
+
+        // Steps of a cancelled job are already marked as cancelled.
+        if !cancelled {
+            for step in &mut steps {
+                if let JobStepStatus::Initialized | JobStepStatus::Pending = step.status {
+                    step.skip(conn)?;
+                }
+            }
+        }
+
+        if let Some(failure) = results.failure {
+            return Err(failure.error.into());
+        }
+
+        // Only update the status of the job if it wasn't cancelled while
+        // running its steps.
+        if cancelled || steps.is_empty() {
+            return Ok(());
+        }
+
+        let job: Option<Self> = diesel::update(
+            jobs.filter(id.eq(self.id))
+                .filter(status.eq(Status::Running)),
+        )
+        .set(status.eq(Status::Ok))
+        .get_result(conn)
+        .optional()?;
+
+        match job {
+            Some(job) => job.notify(conn).map_err(Into::into),
             None => Ok(()),
         }
     }
 }
 
+/// Returns a context to run a single job step with.
+///
+/// A step has to finish before both its own deadline, and the deadline of the
+/// job, as well as any deadline of the provided context. Any output written by
+/// the step is stored in the log of the step, with the provided secrets masked.
+fn step_context(
+    context: &Context,
+    step: &JobStep,
+    job_deadline: Option<Instant>,
+    log: &LogHandle,
+    secrets: &Secrets,
+) -> Context {
+    let mut context = context.fork();
+
+    // A job running as a sub-job is also bound by the deadline of its parent.
+    let parent_deadline = context.time_remaining().map(|r| Instant::now() + r);
+    let earliest = vec![
+        parent_deadline,
+        job_deadline,
+        deadline(step.timeout_seconds),
+    ]
+    .into_iter()
+    .flatten()
+    .min();
+
+    context.set_deadline(earliest);
+
+    context.set_output_sink(Some(log.sink(step, secrets.clone())));
+    context
+}
+
+/// The reason a job step did not succeed.
+enum StepError {
+    /// The job step requested approval, and waits for the request to be
+    /// decided.
+    AwaitingApproval,
+
+    /// The job step failed, or was cancelled.
+    Failed(String),
+}
+
+impl From<Box<dyn Error>> for StepError {
+    fn from(err: Box<dyn Error>) -> Self {
+        if err.is::<AwaitingApproval>() {
+            StepError::AwaitingApproval
+        } else {
+            StepError::Failed(err.to_string())
+        }
+    }
+}
+
+/// Returns the deadline for a timeout that starts now, if any.
+fn deadline(timeout_seconds: Option<i32>) -> Option<Instant> {
+    timeout_seconds
+        .map(|seconds| Duration::from_secs(u64::try_from(seconds).unwrap_or_default()))
+        .map(|timeout| Instant::now() + timeout)
+}
+
 /// Contains all the details needed to store a job in the database.
 ///
 /// The fields are private, use [`NewJob::new`] to initialize this struct.
@@ -159,6 +999,11 @@ pub(crate) struct NewJob<'a> {
     description: Option<&'a str>,
     status: Status,
     task_reference: Option<i32>,
+    run_at: Option<NaiveDateTime>,
+    timeout_seconds: Option<i32>,
+    requested_by: Option<i32>,
+    task_version_id: Option<i32>,
+    resolve_variables: bool,
     steps: Vec<NewJobStep<'a>>,
     variables: Vec<NewJobVariable<'a>>,
 }
@@ -172,6 +1017,11 @@ impl<'a> NewJob<'a> {
             description,
             status: Status::Pending,
             task_reference: None,
+            run_at: None,
+            timeout_seconds: None,
+            requested_by: None,
+            task_version_id: None,
+            resolve_variables: false,
             steps: vec![],
             variables: vec![],
         }
@@ -181,6 +1031,9 @@ impl<'a> NewJob<'a> {
         conn: &PgConnection,
         task: &'a Task,
         variables: Vec<NewJobVariable<'a>>,
+        run_at: Option<NaiveDateTime>,
+        requested_by: Option<i32>,
+        resolve_variables: bool,
     ) -> Result<Job, Box<dyn Error>> {
         let steps = task.steps(conn)?;
         let steps = steps
@@ -188,11 +1041,27 @@ impl<'a> NewJob<'a> {
             .map(TryInto::try_into)
             .collect::<Result<_, _>>()?;
 
+        let version = TaskVersion::record(task, conn)?;
+
         let mut job = Self::new(&task.name, task.description.as_ref().map(String::as_ref));
         job.with_task_reference(task.id);
+        job.with_task_version(version.id);
+        job.with_timeout_seconds(task.timeout_seconds);
         job.with_steps(steps);
         job.with_variables(variables);
 
+        if let Some(run_at) = run_at {
+            job.with_run_at(run_at);
+        }
+
+        if let Some(session_id) = requested_by {
+            job.with_requested_by(session_id);
+        }
+
+        if resolve_variables {
+            job.with_resolve_variables();
+        }
+
         job.create(conn).map_err(Into::into)
     }
 
@@ -200,6 +1069,42 @@ impl<'a> NewJob<'a> {
         self.task_reference = Some(task_id)
     }
 
+    /// Record the version of the task the job is created from.
+    pub(crate) fn with_task_version(&mut self, task_version_id: i32) {
+        self.task_version_id = Some(task_version_id)
+    }
+
+    /// Limit the time the job is allowed to run.
+    pub(crate) fn with_timeout_seconds(&mut self, timeout_seconds: Option<i32>) {
+        self.timeout_seconds = timeout_seconds
+    }
+
+    /// Schedule the job to run at a moment in the future.
+    ///
+    /// The job is created with the `Scheduled` status, and is marked as
+    /// pending by the worker once the provided moment has passed.
+    pub(crate) fn with_run_at(&mut self, run_at: NaiveDateTime) {
+        self.status = Status::Scheduled;
+        self.run_at = Some(run_at)
+    }
+
+    /// Record the session that requested the job.
+    ///
+    /// This session is not allowed to approve the steps of the job that
+    /// require approval.
+    pub(crate) fn with_requested_by(&mut self, session_id: i32) {
+        self.requested_by = Some(session_id)
+    }
+
+    /// Allow the job to be created without the values of required task
+    /// variables, if another task advertises them.
+    ///
+    /// The missing values are resolved when the job runs, by running the
+    /// advertising tasks as sub-jobs first.
+    pub(crate) fn with_resolve_variables(&mut self) {
+        self.resolve_variables = true
+    }
+
     /// Attach zero or more steps to this job.
     ///
     /// `NewJob` takes ownership of the steps, but you are required to
@@ -225,6 +1130,7 @@ impl<'a> NewJob<'a> {
         use crate::schema::jobs::dsl::*;
 
         let mut job_name = self.name.to_owned();
+        let mut defaults = vec![];
 
         // Job names are unique over (name, task_reference). If a reference
         // exists, we add a count (such as "My Job #3") to the name of the
@@ -248,8 +1154,10 @@ impl<'a> NewJob<'a> {
             job_name = format!("{} #{}", task.name, total + 1);
 
             // If we're dealing with a job created from a task, we also need to
-            // validate that the task variables are all present.
+            // validate that the task variables are all present, and use the
+            // default values of any variables without a value.
             self.validate_task_variables(&task, conn)?;
+            defaults = self.task_variable_defaults(&task, conn)?;
         }
 
         conn.transaction(|| {
@@ -259,18 +1167,36 @@ impl<'a> NewJob<'a> {
                 description.eq(&self.description),
                 status.eq(self.status),
                 task_reference.eq(self.task_reference),
+                run_at.eq(self.run_at),
+                timeout_seconds.eq(self.timeout_seconds),
+                requested_by.eq(self.requested_by),
+                task_version_id.eq(self.task_version_id),
+                resolve_variables.eq(self.resolve_variables),
             );
 
-            let job = diesel::insert_into(jobs).values(&values).get_result(conn)?;
+            let job: Job = diesel::insert_into(jobs).values(&values).get_result(conn)?;
 
             self.variables
                 .into_iter()
+                .filter(|v| !defaults.iter().any(|(key, _, _)| key == v.key()))
                 .try_for_each(|s| s.add_to_job(conn, &job))?;
 
+            defaults.iter().try_for_each(|(key, value, secret)| {
+                let mut variable = NewJobVariable::new(key, value);
+                if *secret {
+                    variable.with_secret();
+                }
+
+                variable.add_to_job(conn, &job)
+            })?;
+
             self.steps
                 .into_iter()
                 .try_for_each(|s| s.add_to_job(conn, &job))?;
 
+            // Workers waiting for new jobs are notified once the transaction
+            // commits.
+            job.notify(conn)?;
             Ok(job)
         })
     }
@@ -280,22 +1206,33 @@ impl<'a> NewJob<'a> {
     /// This is only relevant if the job is created from an existing task, in
     /// which case the task can have any number of variables, and the provided
     /// job variables should match those.
+    ///
+    /// Variables that are optional, or have a default value, don't have to be
+    /// provided. If the job resolves missing variables, variables advertised
+    /// by another task don't have to be provided either.
     fn validate_task_variables(
         &self,
         task: &Task,
         conn: &PgConnection,
     ) -> Result<(), Box<dyn Error>> {
         let task_variables = task.variables(conn)?;
+        let provided = self.variables.iter().map(|v| v.key()).collect::<Vec<_>>();
 
-        let missing = task_variables
-            .iter()
-            .filter_map(|variable| {
-                self.variables
-                    .iter()
-                    .find(|v| v.key() == variable.key)
-                    .map_or(Some(variable.key.as_str()), |_| None)
-            })
-            .collect::<Vec<_>>();
+        let mut missing = vec![];
+        for variable in task_variables.iter().filter(|v| v.is_required()) {
+            let key = variable.key.as_str();
+            if provided.contains(&key) {
+                continue;
+            }
+
+            if self.resolve_variables
+                && Advertiser::find(key, task.id, &provided, self.requested_by, conn)?.is_some()
+            {
+                continue;
+            }
+
+            missing.push(key);
+        }
 
         if missing.is_empty() {
             return Ok(());
@@ -303,6 +1240,69 @@ impl<'a> NewJob<'a> {
 
         Err(format!("missing variable values: {}", missing.join(", ")).into())
     }
+
+    /// Returns the values of all task variables for which no value (or an
+    /// empty value) is provided, but which have a default value, or are
+    /// optional.
+    ///
+    /// Default values are templates, rendered using the global variables
+    /// available to the task, and the values provided for the other
+    /// variables. An optional variable without a default value gets an empty
+    /// value.
+    ///
+    /// A default value is secret if the task variable itself is secret, or if
+    /// its template refers to a secret global variable, or to a secret value
+    /// of another variable.
+    fn task_variable_defaults(
+        &self,
+        task: &Task,
+        conn: &PgConnection,
+    ) -> Result<Vec<(String, String, bool)>, Box<dyn Error>> {
+        let mut globals: Vec<GlobalVariable> = GlobalVariable::all().get_results(conn)?;
+        globals.retain(|v| v.is_available_to(&task.labels));
+
+        let global = globals
+            .iter()
+            .map(|v| (v.key.as_str(), v.value.as_str()))
+            .collect();
+
+        let var = self
+            .variables
+            .iter()
+            .filter(|v| !v.value().is_empty())
+            .map(|v| (v.key(), v.value()))
+            .collect::<HashMap<_, _>>();
+
+        let mut defaults = vec![];
+        for variable in task.variables(conn)? {
+            if var.contains_key(variable.key.as_str()) {
+                continue;
+            }
+
+            let provided = self.variables.iter().any(|v| v.key() == variable.key);
+
+            let value = match variable.render_default_value(&var, &global)? {
+                Some(value) => value,
+                None if variable.optional && !provided => String::new(),
+                None => continue,
+            };
+
+            let secret = variable.secret
+                || globals
+                    .iter()
+                    .filter(|v| v.secret)
+                    .any(|v| variable.default_value_refers_to("global", &v.key))
+                || self
+                    .variables
+                    .iter()
+                    .filter(|v| v.is_secret())
+                    .any(|v| variable.default_value_refers_to("var", v.key()));
+
+            defaults.push((variable.key, value, secret));
+        }
+
+        Ok(defaults)
+    }
 }
 
 pub(crate) mod graphql {
@@ -317,7 +1317,8 @@ pub(crate) mod graphql {
     //! mutation, and type documentation.
 
     use super::*;
-    use crate::resources::JobVariableInput;
+    use crate::resources::{JobStepApproval, JobVariableInput};
+    use chrono::{DateTime, Utc};
     use juniper::{object, FieldResult, GraphQLInputObject, ID};
 
     /// Contains all the data needed to create a new `Task`.
@@ -334,6 +1335,25 @@ pub(crate) mod graphql {
         /// variables in the task before creating the job. The final step
         /// configs are then stored alongside the job in the database.
         pub(crate) variables: Vec<JobVariableInput>,
+
+        /// An optional moment in the future at which the job should run.
+        ///
+        /// If this value is provided, the job is created with the `SCHEDULED`
+        /// status, and becomes `PENDING` once the provided moment has passed.
+        ///
+        /// If no value is provided, the job is scheduled to run immediately.
+        pub(crate) run_at: Option<DateTime<Utc>>,
+
+        /// Resolve the values of missing variables by running the tasks
+        /// advertising them (defaults to `false`).
+        ///
+        /// If enabled, the job can be created without the values of required
+        /// variables, as long as another task advertises them (see
+        /// `Variable.valueAdvertisers`). Before the job steps run, the
+        /// advertising task runs as a sub-job, using the variables of this job
+        /// that it needs, and the output of its advertising step becomes the
+        /// value of the variable.
+        pub(crate) resolve_variables: Option<bool>,
     }
 
     #[object(Context = RequestState)]
@@ -362,6 +1382,51 @@ pub(crate) mod graphql {
             self.status
         }
 
+        /// The moment at which a scheduled job is set to run.
+        ///
+        /// This value is `null` if the job was scheduled to run immediately
+        /// after it was created.
+        fn run_at() -> Option<DateTime<Utc>> {
+            self.run_at.map(|t| DateTime::from_utc(t, Utc))
+        }
+
+        /// The number of seconds the job is allowed to run, if any.
+        fn timeout_seconds() -> Option<i32> {
+            self.timeout_seconds
+        }
+
+        /// The ID of the worker that last claimed the job, if any.
+        fn worker_id() -> Option<&str> {
+            self.worker_id.as_ref().map(String::as_ref)
+        }
+
+        /// An explanation of the current status of the job, if the status was
+        /// not the result of running the job steps.
+        ///
+        /// For example, if the worker running the job stopped responding, the
+        /// job is either failed or requeued, and this field explains why.
+        fn status_reason() -> Option<&str> {
+            self.status_reason.as_ref().map(String::as_ref)
+        }
+
+        /// The ID of the session that requested the job, if any.
+        ///
+        /// Jobs created by a schedule are not requested by any session.
+        fn requested_by() -> Option<ID> {
+            self.requested_by.map(|id| ID::new(id.to_string()))
+        }
+
+        /// The approval request the job is waiting for, if any.
+        ///
+        /// This returns `null` if the job is not waiting for approval.
+        ///
+        /// This field can also return `null` if a database error prevents the
+        /// data from being retrieved, in which case an `errors` object will be
+        /// attached to the result.
+        fn pending_approval(context: &RequestState) -> FieldResult<Option<JobStepApproval>> {
+            JobStepApproval::pending_for_job(self, &context.conn).map_err(Into::into)
+        }
+
         /// The steps belonging to the job.
         ///
         /// This field can return `null`, but _only_ if a database error
@@ -382,6 +1447,69 @@ pub(crate) mod graphql {
             self.steps(&context.conn).map(Some).map_err(Into::into)
         }
 
+        /// The job that ran this job as a sub-job, using a `RunTask` step, or
+        /// to resolve one of its variables.
+        ///
+        /// If the job did not run as a sub-job, or if the parent job has been
+        /// removed, this will return `null`.
+        ///
+        /// This field can also return `null` if a database error prevents the
+        /// data from being retrieved, in which case an `errors` object will be
+        /// attached to the result.
+        fn parent(context: &RequestState) -> FieldResult<Option<Job>> {
+            self.parent(&context.conn).map_err(Into::into)
+        }
+
+        /// The jobs that ran as a sub-job of this job, using `RunTask` steps,
+        /// or to resolve its variables.
+        ///
+        /// This field can return `null`, but _only_ if a database error
+        /// prevents the data from being retrieved.
+        ///
+        /// If the job did not run any sub-jobs, an empty array is returned
+        /// instead.
+        ///
+        /// If a `null` value is returned, it is up to the client to decide the
+        /// best course of action. The following actions are advised, sorted by
+        /// preference:
+        ///
+        /// 1. continue execution if the information is not critical to success,
+        /// 2. retry the request to try and get the relevant information,
+        /// 3. disable parts of the application reliant on the information,
+        /// 4. show a global error, and ask the user to retry.
+        fn children(context: &RequestState) -> FieldResult<Option<Vec<Job>>> {
+            self.children(&context.conn).map(Some).map_err(Into::into)
+        }
+
+        /// Whether the job resolves missing variable values by running the
+        /// tasks advertising them.
+        fn resolve_variables() -> bool {
+            self.resolve_variables
+        }
+
+        /// The key of the variable of the parent job for which this job
+        /// provided the value, if it ran to resolve that variable.
+        ///
+        /// The value is the output of the step advertising the variable.
+        fn provides_variable() -> Option<&str> {
+            self.provides_variable.as_ref().map(String::as_ref)
+        }
+
+        /// The version of the task from which the job was created.
+        ///
+        /// This makes it possible to see which definition of the task the job
+        /// ran, even if the task changed since.
+        ///
+        /// If the job was not created from a task, or if the task has been
+        /// removed since the job was created, this will return `null`.
+        ///
+        /// This field can also return `null` if a database error prevents the
+        /// data from being retrieved, in which case an `errors` object will be
+        /// attached to the result.
+        fn task_version(context: &RequestState) -> FieldResult<Option<TaskVersion>> {
+            self.task_version(&context.conn).map_err(Into::into)
+        }
+
         /// The task from which the job was created.
         ///
         /// A job _can_ but _does not have to_ be created from an existing
@@ -415,3 +1543,501 @@ pub(crate) mod graphql {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{NewGlobalVariable, NewSession};
+    use crate::resources::{JobStepApproval, LogWriter, NewStep, NewTask, NewVariable};
+    use crate::test_support::{connection, create_task, database_url};
+    use crate::Processor;
+    use diesel::result::Error;
+    use serde_json::{json, Value};
+
+    /// Create a job for the provided task, claimed by a worker.
+    fn running_job(conn: &PgConnection, task: &Task) -> Job {
+        let mut job = NewJob::create_from_task(conn, task, vec![], None, None, false).unwrap();
+        job.as_running(conn, "worker").unwrap()
+    }
+
+    /// Run the job with a new context, storing its output in a new log.
+    fn run(conn: &PgConnection, job: &Job) -> Result<(), Box<dyn std::error::Error>> {
+        let log = LogWriter::start(connection());
+        let result = job.run(conn, &database_url(), &Context::new()?, &log.handle());
+        log.finish();
+
+        result
+    }
+
+    /// Create a task with steps with the provided names, processor
+    /// configurations, and run conditions.
+    fn create_task_with_run_when(
+        conn: &PgConnection,
+        steps: &[(&str, Value, StepRunWhen)],
+    ) -> Task {
+        let mut task = NewTask::new("cleanup", None, vec![]);
+        task.with_steps(
+            steps
+                .iter()
+                .enumerate()
+                .map(|(position, (name, processor, run_when))| {
+                    let processor: Processor = serde_json::from_value(processor.clone()).unwrap();
+                    let position = i32::try_from(position).unwrap();
+                    let mut step = NewStep::new(name, None, processor, position, None);
+                    step.with_run_when(*run_when);
+
+                    step
+                })
+                .collect(),
+        );
+
+        task.create(conn).unwrap()
+    }
+
+    fn print() -> Value {
+        json!({ "PrintOutput": { "output": "hello" } })
+    }
+
+    fn fail() -> Value {
+        json!({ "StringRegex": { "input": "a", "regex": "^b$", "mismatch_error": "no match" } })
+    }
+
+    fn approval() -> Value {
+        json!({ "RequireApproval": { "privilege": "approver" } })
+    }
+
+    /// Run the job until it waits for approval, and decide the request on
+    /// behalf of a new session, of which the ID is returned.
+    fn run_and_decide(conn: &PgConnection, job: &Job, approved: bool) -> QueryResult<i32> {
+        run(conn, job).unwrap();
+
+        let job: Job = jobs::table.find(job.id).first(conn)?;
+        assert_eq!(job.status, Status::Waiting);
+        assert_eq!(job.worker_id, None);
+
+        let session = NewSession::new(vec!["approver"]).create(conn)?;
+        let approval = JobStepApproval::pending_for_job(&job, conn)?.unwrap();
+        let _ = approval.decide(conn, session.id, approved).unwrap();
+
+        Ok(session.id)
+    }
+
+    #[test]
+    fn test_recovery_from_str() {
+        assert_eq!("fail".parse::<Recovery>().unwrap(), Recovery::Fail);
+        assert_eq!("requeue".parse::<Recovery>().unwrap(), Recovery::Requeue);
+        assert!("retry".parse::<Recovery>().is_err());
+    }
+
+    #[test]
+    fn test_default_value_from_secret_global() {
+        let conn = connection();
+
+        conn.test_transaction::<_, Error, _>(|| {
+            let mut token = NewGlobalVariable::new("test_token", "hunter2");
+            token.with_secret(true);
+            let _ = token.create(&conn)?;
+            let _ = NewGlobalVariable::new("test_host", "example.com").create(&conn)?;
+
+            let header = "Bearer {{ global.test_token }}";
+            let host = "{{ global.test_host }}";
+            let mut url = NewVariable::new("url", None, Some(host), None, None).unwrap();
+            url.with_secret(true);
+
+            let mut task = NewTask::new("defaults", None, vec![]);
+            task.with_variables(vec![
+                NewVariable::new("header", None, Some(header), None, None).unwrap(),
+                NewVariable::new("host", None, Some(host), None, None).unwrap(),
+                url,
+            ]);
+
+            let task = task.create(&conn).unwrap();
+            let job = NewJob::create_from_task(&conn, &task, vec![], None, None, false).unwrap();
+
+            let variables = job.variables(&conn)?;
+            let variable = |key: &str| variables.iter().find(|v| v.key == key).unwrap();
+
+            assert_eq!(variable("header").value, "Bearer hunter2");
+            assert!(variable("header").secret);
+            assert_eq!(variable("host").value, "example.com");
+            assert!(!variable("host").secret);
+
+            // The task variable itself is secret, even if the global it refers
+            // to is not.
+            assert_eq!(variable("url").value, "example.com");
+            assert!(variable("url").secret);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_heartbeat_sub_jobs() {
+        let conn = connection();
+
+        conn.test_transaction::<_, Error, _>(|| {
+            let tasks = [
+                "job",
+                "sub-job",
+                "nested sub-job",
+                "other job",
+                "other sub-job",
+            ]
+            .iter()
+            .map(|name| create_task(&conn, name, &[], &[]))
+            .collect::<Vec<_>>();
+
+            let job = running_job(&conn, &tasks[0]);
+            let sub_job = job.claim_sub_job(&conn, &tasks[1], vec![], None).unwrap();
+            let nested = sub_job
+                .claim_sub_job(&conn, &tasks[2], vec![], None)
+                .unwrap();
+            let other = running_job(&conn, &tasks[3]);
+            let other_sub_job = other.claim_sub_job(&conn, &tasks[4], vec![], None).unwrap();
+
+            let ids = [job.id, sub_job.id, nested.id, other.id, other_sub_job.id];
+            let past = Utc::now().naive_utc() - chrono::Duration::hours(1);
+            let _ = diesel::update(jobs::table.filter(jobs::id.eq_any(&ids[..])))
+                .set(jobs::heartbeat_at.eq(past))
+                .execute(&conn)?;
+
+            Job::heartbeat(job.id, "worker", &conn)?;
+
+            for (index, id) in ids.iter().enumerate() {
+                let heartbeat_at: Option<NaiveDateTime> = jobs::table
+                    .find(*id)
+                    .select(jobs::heartbeat_at)
+                    .first(&conn)?;
+
+                assert_eq!(heartbeat_at > Some(past), index < 3, "job {}", index);
+            }
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_cleanup_steps_after_failure() {
+        let conn = connection();
+
+        conn.test_transaction::<_, Error, _>(|| {
+            let task = create_task_with_run_when(
+                &conn,
+                &[
+                    ("always", print(), StepRunWhen::Always),
+                    ("fail", fail(), StepRunWhen::OnSuccess),
+                    ("skipped", print(), StepRunWhen::OnSuccess),
+                    ("on failure", print(), StepRunWhen::OnFailure),
+                ],
+            );
+
+            let job = running_job(&conn, &task);
+            assert!(run(&conn, &job).is_err());
+
+            let steps = job.steps(&conn)?;
+            let step = |name: &str| steps.iter().find(|s| s.name == name).unwrap();
+
+            assert_eq!(step("fail").status, JobStepStatus::Failed);
+            assert_eq!(step("skipped").status, JobStepStatus::Skipped);
+            assert_eq!(step("always").status, JobStepStatus::Ok);
+            assert_eq!(step("on failure").status, JobStepStatus::Ok);
+
+            // Cleanup steps run after the regular steps, by position.
+            assert!(step("fail").started_at < step("always").started_at);
+            assert!(step("always").started_at < step("on failure").started_at);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_cleanup_steps_after_success() {
+        let conn = connection();
+
+        conn.test_transaction::<_, Error, _>(|| {
+            let task = create_task_with_run_when(
+                &conn,
+                &[
+                    ("on failure", print(), StepRunWhen::OnFailure),
+                    ("always", print(), StepRunWhen::Always),
+                    ("print", print(), StepRunWhen::OnSuccess),
+                ],
+            );
+
+            let job = running_job(&conn, &task);
+            run(&conn, &job).unwrap();
+
+            let steps = job.steps(&conn)?;
+            let step = |name: &str| steps.iter().find(|s| s.name == name).unwrap();
+
+            assert_eq!(step("print").status, JobStepStatus::Ok);
+            assert_eq!(step("always").status, JobStepStatus::Ok);
+            assert_eq!(step("on failure").status, JobStepStatus::Skipped);
+            assert!(step("print").started_at < step("always").started_at);
+
+            let job: Job = jobs::table.find(job.id).first(&conn)?;
+            assert_eq!(job.status, Status::Ok);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_continue_after_approval() {
+        let conn = connection();
+
+        conn.test_transaction::<_, Error, _>(|| {
+            let task = create_task(
+                &conn,
+                "approval",
+                &[],
+                &[
+                    ("before", print()),
+                    ("approve", approval()),
+                    ("after", print()),
+                ],
+            );
+
+            let mut job = running_job(&conn, &task);
+            let session_id = run_and_decide(&conn, &job, true)?;
+
+            let steps = job.steps(&conn)?;
+            assert_eq!(steps[0].status, JobStepStatus::Ok);
+            assert_eq!(steps[1].status, JobStepStatus::Running);
+            assert_eq!(steps[2].status, JobStepStatus::Pending);
+
+            // The job continues at the step that requested approval.
+            let job = job.as_running(&conn, "worker")?;
+            run(&conn, &job).unwrap();
+
+            let steps = job.steps(&conn)?;
+            assert_eq!(steps[0].attempts(&conn)?.len(), 1);
+            assert_eq!(steps[1].status, JobStepStatus::Ok);
+            assert_eq!(
+                steps[1].output,
+                Some(format!("approved by session {}", session_id))
+            );
+            assert_eq!(steps[2].status, JobStepStatus::Ok);
+
+            let job: Job = jobs::table.find(job.id).first(&conn)?;
+            assert_eq!(job.status, Status::Ok);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_rejected_approval() {
+        let conn = connection();
+
+        conn.test_transaction::<_, Error, _>(|| {
+            let task = create_task(
+                &conn,
+                "approval",
+                &[],
+                &[("approve", approval()), ("after", print())],
+            );
+
+            let mut job = running_job(&conn, &task);
+            let session_id = run_and_decide(&conn, &job, false)?;
+
+            let job = job.as_running(&conn, "worker")?;
+            let err = run(&conn, &job).unwrap_err().to_string();
+            assert_eq!(err, format!("rejected by session {}", session_id));
+
+            let steps = job.steps(&conn)?;
+            assert_eq!(steps[0].status, JobStepStatus::Failed);
+            assert_eq!(steps[0].attempts(&conn)?.len(), 1);
+            assert_eq!(steps[1].status, JobStepStatus::Skipped);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_cancel_waiting_job() {
+        let conn = connection();
+
+        conn.test_transaction::<_, Error, _>(|| {
+            let task = create_task(&conn, "approval", &[], &[("approve", approval())]);
+            let job = running_job(&conn, &task);
+            run(&conn, &job).unwrap();
+
+            let job = job.cancel(&conn).unwrap();
+            assert_eq!(job.status, Status::Cancelled);
+
+            let steps = job.steps(&conn)?;
+            assert_eq!(steps[0].status, JobStepStatus::Cancelled);
+            assert!(JobStepApproval::pending_for_job(&job, &conn)?.is_none());
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_sub_job_failure() {
+        let conn = connection();
+
+        conn.test_transaction::<_, Error, _>(|| {
+            let _ = create_task(&conn, "child", &[], &[("match", fail())]);
+            let run_child = json!({ "RunTask": { "task": "child", "variables": [] } });
+            let parent = create_task(&conn, "parent", &[], &[("run child", run_child)]);
+            let job = running_job(&conn, &parent);
+
+            let err = run(&conn, &job).unwrap_err().to_string();
+            assert!(err.contains("sub-job child #1 failed: no match"), "{}", err);
+
+            let children = job.children(&conn)?;
+            assert_eq!(children.len(), 1);
+            assert_eq!(children[0].status, Status::Failed);
+            assert_eq!(
+                children[0].worker_id.as_ref().map(String::as_str),
+                Some("worker")
+            );
+
+            let steps = job.steps(&conn)?;
+            assert_eq!(steps[0].status, JobStepStatus::Failed);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_resolve_variable_without_advertiser() {
+        use crate::schema::variable_advertisements;
+
+        let conn = connection();
+
+        conn.test_transaction::<_, Error, _>(|| {
+            let processor: Processor = serde_json::from_value(print()).unwrap();
+            let mut provider = NewTask::new("provider", None, vec![]);
+            provider.with_steps(vec![NewStep::new(
+                "provide",
+                None,
+                processor,
+                0,
+                Some("test_unadvertised"),
+            )]);
+            let _ = provider.create(&conn).unwrap();
+
+            let task = create_task(
+                &conn,
+                "consumer",
+                &["test_unadvertised"],
+                &[("print", print())],
+            );
+            let mut job = NewJob::create_from_task(&conn, &task, vec![], None, None, true).unwrap();
+            let job = job.as_running(&conn, "worker").unwrap();
+
+            // The advertisement disappears between creating and running the job.
+            let advertisements = variable_advertisements::table
+                .filter(variable_advertisements::key.eq("test_unadvertised"));
+            let _ = diesel::delete(advertisements).execute(&conn)?;
+
+            let err = run(&conn, &job).unwrap_err().to_string();
+            assert!(
+                err.contains(r#"no task can provide variable "test_unadvertised""#),
+                "{}",
+                err
+            );
+
+            let steps = job.steps(&conn)?;
+            assert!(steps.iter().all(|s| s.status == JobStepStatus::Skipped));
+            assert!(job.children(&conn)?.is_empty());
+
+            let job: Job = jobs::table.find(job.id).first(&conn)?;
+            assert_eq!(
+                job.status_reason.as_ref().map(String::as_str),
+                Some(err.as_str())
+            );
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_resolve_variable_with_unauthorized_advertiser() {
+        use crate::schema::sessions;
+
+        let conn = connection();
+
+        conn.test_transaction::<_, Error, _>(|| {
+            let processor: Processor = serde_json::from_value(print()).unwrap();
+            let mut provider = NewTask::new("provider", None, vec!["ops"]);
+            provider.with_steps(vec![NewStep::new(
+                "provide",
+                None,
+                processor,
+                0,
+                Some("test_restricted"),
+            )]);
+            let _ = provider.create(&conn).unwrap();
+
+            let task = create_task(
+                &conn,
+                "consumer",
+                &["test_restricted"],
+                &[("print", print())],
+            );
+
+            // Without a session, the labeled task can't provide the value.
+            let err = NewJob::create_from_task(&conn, &task, vec![], None, None, true)
+                .unwrap_err()
+                .to_string();
+            assert_eq!(err, "missing variable values: test_restricted");
+
+            let session = NewSession::new(vec!["ops"]).create(&conn)?;
+            let mut job =
+                NewJob::create_from_task(&conn, &task, vec![], None, Some(session.id), true)
+                    .unwrap();
+            let job = job.as_running(&conn, "worker").unwrap();
+
+            // The session loses its privilege before the job runs.
+            let privileges: Vec<String> = vec![];
+            let _ = diesel::update(&session)
+                .set(sessions::privileges.eq(privileges))
+                .execute(&conn)?;
+
+            let err = run(&conn, &job).unwrap_err().to_string();
+            assert!(
+                err.contains(r#"no task can provide variable "test_restricted""#),
+                "{}",
+                err
+            );
+            assert!(job.children(&conn)?.is_empty());
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_sub_job_cancelled() {
+        let conn = connection();
+
+        conn.test_transaction::<_, Error, _>(|| {
+            let child = create_task(&conn, "child", &[], &[("print", print())]);
+            let parent = create_task(&conn, "parent", &[], &[]);
+            let job = running_job(&conn, &parent);
+            let sub_job = job.claim_sub_job(&conn, &child, vec![], None).unwrap();
+
+            let context = Context::new().unwrap();
+            context.canceller().cancel();
+
+            let log = LogWriter::start(connection());
+            let result = sub_job.run_as_sub_job(&conn, &database_url(), &context, &log.handle());
+            log.finish();
+
+            let err = result.unwrap_err().to_string();
+            assert!(err.contains("sub-job child #1 was cancelled"), "{}", err);
+
+            let sub_job: Job = jobs::table.find(sub_job.id).first(&conn)?;
+            assert_eq!(sub_job.status, Status::Cancelled);
+            assert_eq!(sub_job.parent_id, Some(job.id));
+
+            let steps = sub_job.steps(&conn)?;
+            assert!(steps.iter().all(|s| s.status == JobStepStatus::Cancelled));
+
+            Ok(())
+        })
+    }
+}
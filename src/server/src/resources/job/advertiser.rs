@@ -0,0 +1,76 @@
+//! An [`Advertiser`] is a task that can provide the value of a variable,
+//! because one of its steps advertises the variable key.
+//!
+//! A job created with `resolve_variables` enabled can be created without the
+//! values of its required variables, as long as a task advertises them. When
+//! the job runs, the advertising task runs first as a sub-job, and the output
+//! of the advertising step becomes the value of the variable.
+//!
+//! A task carrying labels only advertises its variables to jobs requested by
+//! a session with a privilege matching one of those labels, the same as
+//! when creating a job from the task directly.
+
+use crate::graphql::authorization_guard;
+use crate::models::Session;
+use crate::resources::Task;
+use crate::schema::{sessions, steps, tasks, variable_advertisements};
+use diesel::prelude::*;
+
+/// A task advertising the value of a variable.
+#[derive(Clone, Debug)]
+pub(crate) struct Advertiser {
+    /// The task to run to get the value.
+    pub(crate) task: Task,
+
+    /// The name of the step whose output is the value.
+    pub(crate) step_name: String,
+}
+
+impl Advertiser {
+    /// Returns the first task advertising the variable with the provided key,
+    /// for which all required variables are available, and which the session
+    /// with the provided ID (usually the session that requested the job) is
+    /// authorized to run.
+    ///
+    /// The task with the provided ID (usually the task of the job missing the
+    /// variable) is never returned.
+    pub(crate) fn find(
+        key: &str,
+        exclude_task_id: i32,
+        available: &[&str],
+        requested_by: Option<i32>,
+        conn: &PgConnection,
+    ) -> QueryResult<Option<Self>> {
+        let session: Option<Session> = match requested_by {
+            None => None,
+            Some(id) => sessions::table.find(id).first(conn).optional()?,
+        };
+
+        let candidates: Vec<(String, Task)> = variable_advertisements::table
+            .inner_join(steps::table.inner_join(tasks::table))
+            .filter(variable_advertisements::key.eq(key))
+            .filter(tasks::id.ne(exclude_task_id))
+            .order(steps::id.asc())
+            .select((steps::name, tasks::all_columns))
+            .load(conn)?;
+
+        for (step_name, task) in candidates {
+            let labels = task.labels.iter().map(String::as_str).collect::<Vec<_>>();
+            if authorization_guard(&labels, &session).is_err() {
+                continue;
+            }
+
+            let satisfied = task
+                .variables(conn)?
+                .iter()
+                .filter(|variable| variable.is_required())
+                .all(|variable| available.contains(&variable.key.as_str()));
+
+            if satisfied {
+                return Ok(Some(Self { task, step_name }));
+            }
+        }
+
+        Ok(None)
+    }
+}
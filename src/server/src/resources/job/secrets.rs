@@ -0,0 +1,115 @@
+//! The [`Secrets`] of a job are the values of its secret variables, and of
+//! all secret global variables.
+//!
+//! Variable values are encrypted at rest, but once they are used in the
+//! templates of a processor, they can end up in the stored processor
+//! configuration, the output of a step, or its log. Any secret value that
+//! appears in those is replaced by a placeholder before it is stored.
+
+use crate::models::GlobalVariable;
+use crate::resources::Job;
+use diesel::prelude::*;
+
+/// The placeholder that replaces any secret value.
+pub(crate) const MASK: &str = "[secret]";
+
+/// The secret values that have to be masked for a single job.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Secrets(Vec<String>);
+
+impl Secrets {
+    /// Create a set of secrets from the provided values.
+    ///
+    /// Empty values are ignored, as they can't be masked.
+    pub(crate) fn new(mut values: Vec<String>) -> Self {
+        values.retain(|value| !value.is_empty());
+
+        // Longer values are masked first, in case a secret contains another
+        // secret.
+        values.sort_unstable_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+        values.dedup();
+
+        Self(values)
+    }
+
+    /// Load the values of the secret variables of the provided job, and of
+    /// all secret global variables.
+    pub(crate) fn load(job: &Job, conn: &PgConnection) -> QueryResult<Self> {
+        let globals: Vec<GlobalVariable> = GlobalVariable::all().load(conn)?;
+
+        let values = job
+            .variables(conn)?
+            .into_iter()
+            .filter(|variable| variable.secret)
+            .map(|variable| variable.value)
+            .chain(
+                globals
+                    .into_iter()
+                    .filter(|variable| variable.secret)
+                    .map(|variable| variable.value),
+            )
+            .collect();
+
+        Ok(Self::new(values))
+    }
+
+    /// Returns `true` if any of the secret values appear in the provided
+    /// text.
+    pub(crate) fn appear_in(&self, text: &str) -> bool {
+        self.0.iter().any(|secret| text.contains(secret.as_str()))
+    }
+
+    /// Replace all secret values in the provided text with a placeholder.
+    pub(crate) fn mask(&self, text: &str) -> String {
+        self.0.iter().fold(text.to_owned(), |text, secret| {
+            text.replace(secret.as_str(), MASK)
+        })
+    }
+
+    /// Replace all secret values in the strings of the provided JSON value
+    /// with a placeholder.
+    pub(crate) fn redact(&self, value: &mut serde_json::Value) {
+        use serde_json::Value;
+
+        match value {
+            Value::String(string) => *string = self.mask(string),
+            Value::Array(values) => values.iter_mut().for_each(|v| self.redact(v)),
+            Value::Object(map) => map.values_mut().for_each(|v| self.redact(v)),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_mask() {
+        let secrets = Secrets::new(vec!["hunter2".to_owned(), String::new()]);
+
+        assert_eq!(secrets.mask("password: hunter2"), "password: [secret]");
+        assert_eq!(secrets.mask("nothing to see"), "nothing to see");
+    }
+
+    #[test]
+    fn test_mask_overlapping() {
+        let secrets = Secrets::new(vec!["abc".to_owned(), "abcdef".to_owned()]);
+
+        assert_eq!(secrets.mask("abcdef abc"), "[secret] [secret]");
+    }
+
+    #[test]
+    fn test_redact() {
+        let secrets = Secrets::new(vec!["hunter2".to_owned()]);
+        let mut value = json!({ "Shell": { "args": ["-p", "hunter2"], "count": 1 } });
+
+        secrets.redact(&mut value);
+
+        assert_eq!(
+            value,
+            json!({ "Shell": { "args": ["-p", "[secret]"], "count": 1 } })
+        );
+    }
+}
@@ -11,8 +11,13 @@
 //! [`Step`]: crate::resources::Step
 
 use crate::models::GlobalVariable;
-use crate::resources::{Job, Step};
-use crate::schema::job_steps;
+use crate::notification::{self, Notification};
+use crate::processor::{RequireApproval, RunTask};
+use crate::resources::step::{condition, for_each};
+use crate::resources::{
+    Job, LogHandle, NewJobVariable, RetryPolicy, Secrets, Step, StepRunWhen, Task,
+};
+use crate::schema::{job_steps, tasks};
 use crate::{server::RequestState, Processor};
 use automaat_core::Context;
 use chrono::prelude::*;
@@ -23,10 +28,40 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::convert::{AsRef, TryFrom};
 use std::error::Error;
+use std::time::{Duration, Instant};
+use std::{fmt, thread};
 use tera::{Context as TContext, Tera};
 
+pub(crate) mod approval;
+pub(crate) mod attempt;
+pub(crate) mod log;
+
+use approval::{JobStepApproval, NewJobStepApproval};
+use attempt::{JobStepAttempt, NewJobStepAttempt};
+use log::JobStepLog;
+
 const INVALID_SERIALIZED_DATA: &str = "unexpected serialized data stored in database";
 
+/// The interval at which a job step waiting to retry checks if its job was
+/// cancelled, or timed out.
+const RETRY_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The error returned when running a job step that requested approval, while
+/// the request is pending.
+///
+/// The job step keeps running, but its job stops running until the request is
+/// decided. The job then runs again, continuing at this job step.
+#[derive(Debug)]
+pub(crate) struct AwaitingApproval;
+
+impl fmt::Display for AwaitingApproval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("waiting for approval")
+    }
+}
+
+impl Error for AwaitingApproval {}
+
 /// Contains all the data that can be used in processor templates.
 #[derive(Serialize)]
 struct TemplateData<'a> {
@@ -49,6 +84,58 @@ struct TemplateData<'a> {
     /// If two steps have the same name, the one that ran last will occupy that
     /// key with its output value.
     output: HashMap<&'a str, &'a str>,
+
+    /// The step that failed, if any. This allows cleanup steps to report the
+    /// failure.
+    failure: Option<&'a Failure>,
+
+    /// The item a child job step runs for, if the job step runs for each item
+    /// in a list.
+    item: Option<&'a serde_json::Value>,
+}
+
+/// The results of the job steps that already ran, which are available to the
+/// templates of the job steps that run next.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Results {
+    /// The output of each job step that ran successfully, keyed by the name
+    /// of the step.
+    pub(crate) output: HashMap<String, String>,
+
+    /// The first job step that failed, if any.
+    pub(crate) failure: Option<Failure>,
+}
+
+impl Results {
+    /// Record the output of a job step that ran successfully.
+    ///
+    /// Skipped job steps have no output.
+    pub(crate) fn succeeded(&mut self, step: &JobStep, output: Option<String>) {
+        if let Some(output) = output {
+            let _ = self.output.insert(step.name.to_owned(), output);
+        }
+    }
+
+    /// Record the error of a failed job step, unless another job step failed
+    /// before.
+    pub(crate) fn failed(&mut self, step: &JobStep, error: String) {
+        if self.failure.is_none() {
+            self.failure = Some(Failure {
+                step: step.name.to_owned(),
+                error,
+            })
+        }
+    }
+}
+
+/// Describes the failure of a job step.
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct Failure {
+    /// The name of the job step that failed.
+    pub(crate) step: String,
+
+    /// The error returned by the job step.
+    pub(crate) error: String,
 }
 
 /// Contains all exposed system variables.
@@ -60,7 +147,7 @@ struct ContextVariables<'a> {
 }
 
 /// The status of the job step.
-#[derive(Clone, Copy, Debug, DbEnum, GraphQLEnum, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, DbEnum, GraphQLEnum, Serialize, Deserialize)]
 #[PgType = "JobStepStatus"]
 #[graphql(name = "JobStepStatus")]
 pub enum Status {
@@ -79,6 +166,10 @@ pub enum Status {
     /// The job step was cancelled, and will not run anymore.
     Cancelled,
 
+    /// The job step did not run, because its condition did not hold, or
+    /// because the outcome of the other job steps did not require it to run.
+    Skipped,
+
     /// The job step ran and succeeded.
     Ok,
 }
@@ -100,6 +191,18 @@ pub(crate) struct JobStep {
     pub(crate) status: Status,
     pub(crate) output: Option<String>,
     pub(crate) job_id: i32,
+    pub(crate) timeout_seconds: Option<i32>,
+    pub(crate) retry_policy: Option<serde_json::Value>,
+    pub(crate) depends_on: Option<Vec<String>>,
+    pub(crate) condition: Option<String>,
+    pub(crate) run_when: StepRunWhen,
+    pub(crate) for_each: Option<String>,
+    pub(crate) parent_id: Option<i32>,
+    pub(crate) item: Option<serde_json::Value>,
+
+    /// The processor configuration with all templates rendered, as used the
+    /// last time the job step ran. Any secret values are masked.
+    pub(crate) formalized_processor: Option<serde_json::Value>,
 }
 
 impl JobStep {
@@ -114,39 +217,461 @@ impl JobStep {
         serde_json::from_value(self.processor.clone()).ok()
     }
 
+    /// Returns the processor object as used the last time the job step ran,
+    /// with all templates rendered, and any secret values masked.
+    ///
+    /// `None` is returned if the job step did not run yet, or if the data
+    /// could not be deserialized into the processor type.
+    pub(crate) fn formalized_processor(&self) -> Option<Processor> {
+        self.formalized_processor
+            .clone()
+            .and_then(|processor| serde_json::from_value(processor).ok())
+    }
+
     pub(crate) fn job(&self, conn: &PgConnection) -> QueryResult<Job> {
         use crate::schema::jobs::dsl::*;
 
         jobs.filter(id.eq(self.job_id)).first(conn)
     }
 
+    /// Returns the secret values that have to be masked in anything stored
+    /// about this job step.
+    pub(crate) fn secrets(&self, conn: &PgConnection) -> QueryResult<Secrets> {
+        Secrets::load(&self.job(conn)?, conn)
+    }
+
+    /// Returns the retry policy attached to this job step.
+    ///
+    /// Similar to the processor, `None` is returned if the retry policy could
+    /// not be deserialized, in which case the step is not retried.
+    pub(crate) fn retry_policy(&self) -> Option<RetryPolicy> {
+        self.retry_policy
+            .clone()
+            .and_then(|policy| serde_json::from_value(policy).ok())
+    }
+
+    /// Returns the child job steps, one for each item the job step ran for.
+    pub(crate) fn children(&self, conn: &PgConnection) -> QueryResult<Vec<Self>> {
+        use crate::schema::job_steps::dsl::*;
+
+        job_steps
+            .filter(parent_id.eq(self.id))
+            .order(id.asc())
+            .load(conn)
+    }
+
+    pub(crate) fn approvals(&self, conn: &PgConnection) -> QueryResult<Vec<JobStepApproval>> {
+        use crate::schema::job_step_approvals::dsl::*;
+
+        JobStepApproval::belonging_to(self)
+            .order(id.asc())
+            .load(conn)
+    }
+
+    pub(crate) fn attempts(&self, conn: &PgConnection) -> QueryResult<Vec<JobStepAttempt>> {
+        use crate::schema::job_step_attempts::dsl::*;
+
+        JobStepAttempt::belonging_to(self)
+            .order(attempt.asc())
+            .load(conn)
+    }
+
+    /// Returns the lines of the job step log, starting at the provided
+    /// offset.
+    pub(crate) fn log(
+        &self,
+        conn: &PgConnection,
+        offset: i32,
+        limit: Option<i64>,
+    ) -> QueryResult<Vec<JobStepLog>> {
+        use crate::schema::job_step_logs::dsl::*;
+
+        let query = JobStepLog::belonging_to(self)
+            .filter(position.ge(offset))
+            .order(position.asc());
+
+        match limit {
+            None => query.load(conn),
+            Some(limit) => query.limit(limit).load(conn),
+        }
+    }
+
+    /// Returns the names of the job steps this step depends on, if declared.
+    ///
+    /// See [`StepGraph`] for more details.
+    ///
+    /// [`StepGraph`]: crate::resources::StepGraph
+    pub(crate) fn depends_on(&self) -> Option<&[String]> {
+        self.depends_on.as_ref().map(Vec::as_slice)
+    }
+
+    /// Run the job step, using the results of the job steps that already ran,
+    /// and return the output of this step.
+    ///
+    /// If the condition of the job step does not hold, the job step is
+    /// skipped, and `None` is returned.
+    ///
+    /// If the job step runs for each item in a list, a child job step is
+    /// created and run for each item.
+    ///
+    /// The database URL and log are used to run any sub-job started by the
+    /// job step.
+    ///
+    /// If the job step requests approval, [`AwaitingApproval`] is returned,
+    /// without finishing the job step. Running the job step again continues
+    /// where it left off, once the request is decided.
+    ///
+    /// Only the shell command and HTTP request processors, and sub-jobs are
+    /// interrupted when the deadline of the job step passes. Any other
+    /// processor runs to completion, after which the job step fails if it
+    /// finished too late.
     pub(crate) fn run(
         &mut self,
         conn: &PgConnection,
         context: &Context,
-        mut output: HashMap<String, String>,
-    ) -> Result<HashMap<String, String>, Box<dyn Error>> {
-        self.start(conn)?;
+        results: &Results,
+        database_url: &str,
+        log: &LogHandle,
+    ) -> Result<Option<String>, Box<dyn Error>> {
+        match self.condition_holds(results, context, conn) {
+            Ok(true) => {}
+            Ok(false) => {
+                return self
+                    .finished(conn, Status::Skipped, None)
+                    .map(|_| None)
+                    .map_err(Into::into)
+            }
+            Err(err) => {
+                let message = format!("step condition cannot be evaluated: {}", err);
+                self.finished(conn, Status::Failed, Some(message.clone()))?;
+
+                return Err(message.into());
+            }
+        };
+
+        if let Some(template) = self.for_each.clone() {
+            return self.run_for_each(conn, context, results, &template, database_url, log);
+        }
+
+        // A job step that is still running was waiting for approval, and
+        // continues counting its attempts.
+        let mut attempt = if self.status == Status::Running {
+            i32::try_from(self.attempts(conn)?.len())? + 1
+        } else {
+            self.start(conn)?;
+            1
+        };
 
         // TODO: this needs to go in a transaction, and the changes reverted if
         // they can't be saved... Also goes for many other places.
 
-        let result = match self.formalize_processor(&mut output, context, conn) {
-            Ok(p) => p.run(context),
-            Err(err) => Err(format!("job processor cannot be deserialized: {}", err).into()),
+        let policy = self.retry_policy();
+        let secrets = self.secrets(conn)?;
+
+        loop {
+            let started_at = Utc::now().naive_utc();
+            let result = match self.run_attempt(results, context, conn, database_url, log) {
+                Err(err) if err.is::<AwaitingApproval>() => return Err(err),
+                Ok(_) if context.is_timed_out() => {
+                    Err("processor finished after the deadline".into())
+                }
+                result => result,
+            };
+
+            let (status, out) = match &result {
+                Ok(out) => (Status::Ok, out.clone()),
+                Err(err) => {
+                    // A processor can fail because its run was interrupted by
+                    // the job being cancelled.
+                    let status = if context.is_cancelled() {
+                        Status::Cancelled
+                    } else {
+                        Status::Failed
+                    };
+
+                    // Processors report a timeout in their own way, so we make
+                    // it explicit that the step failed because it ran out of
+                    // time.
+                    let message = if context.is_timed_out() {
+                        format!("step timed out: {}", err)
+                    } else {
+                        err.to_string()
+                    };
+
+                    (status, Some(message))
+                }
+            };
+
+            let masked = out.as_ref().map(|out| secrets.mask(out));
+            let _ = NewJobStepAttempt::new(
+                self,
+                attempt,
+                started_at,
+                Utc::now().naive_utc(),
+                status,
+                masked.as_ref().map(String::as_str),
+            )
+            .create(conn)?;
+
+            let err = match result {
+                Ok(_) => {
+                    self.finished(conn, status, out.clone())?;
+
+                    return Ok(Some(out.unwrap_or_default()));
+                }
+                Err(err) => err,
+            };
+
+            // Cancelled or timed out steps are never retried.
+            let delay = policy
+                .as_ref()
+                .filter(|_| status == Status::Failed && !context.is_timed_out())
+                .filter(|p| p.should_retry(attempt, out.as_ref().map_or("", String::as_str)))
+                .map(|p| p.delay(attempt));
+
+            match delay {
+                Some(delay) if wait(delay, context) => attempt += 1,
+                _ => {
+                    self.finished(conn, status, out)?;
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    /// Run the job step once for each item in the list the provided template
+    /// renders to, and return a JSON array with the output of each run.
+    ///
+    /// Each run is stored as a child job step, and the children run one by
+    /// one. The job step fails as soon as any of its children fails, in which
+    /// case the remaining children are skipped.
+    fn run_for_each(
+        &mut self,
+        conn: &PgConnection,
+        context: &Context,
+        results: &Results,
+        template: &str,
+        database_url: &str,
+        log: &LogHandle,
+    ) -> Result<Option<String>, Box<dyn Error>> {
+        self.start(conn)?;
+
+        let rendered = self.with_template_data(results, context, conn, |data| {
+            render(template, data).map_err(Into::into)
+        });
+
+        let items = match rendered {
+            Ok(rendered) => for_each::items(&rendered),
+            Err(err) => {
+                let message = format!("step for each template cannot be rendered: {}", err);
+                self.finished(conn, Status::Failed, Some(message.clone()))?;
+
+                return Err(message.into());
+            }
         };
 
-        match result {
-            Ok(out) => {
-                self.finished(conn, Status::Ok, out.clone())?;
+        let mut children = items
+            .into_iter()
+            .enumerate()
+            .map(|(index, item)| self.add_child(conn, index, item))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut outputs = Vec::with_capacity(children.len());
+        let mut failure = None;
+
+        for child in &mut children {
+            match child.run(conn, context, results, database_url, log) {
+                Ok(output) => outputs.push(output.unwrap_or_default()),
+                Err(err) => {
+                    failure = Some(format!("{} failed: {}", child.name, err));
+                    break;
+                }
+            }
+        }
 
-                let _ = output.insert(self.name.to_owned(), out.unwrap_or_default());
-                Ok(output)
+        // Children of a cancelled job are already marked as cancelled.
+        if !context.is_cancelled() {
+            for child in children.iter_mut().filter(|c| c.status == Status::Pending) {
+                child.skip(conn)?;
             }
-            Err(err) => {
-                self.finished(conn, Status::Failed, Some(err.to_string()))?;
-                Err(err)
+        }
+
+        match failure {
+            None => {
+                let output = for_each::collect(outputs);
+                self.finished(conn, Status::Ok, Some(output.clone()))?;
+
+                Ok(Some(output))
+            }
+            Some(message) => {
+                let status = if context.is_cancelled() {
+                    Status::Cancelled
+                } else {
+                    Status::Failed
+                };
+
+                self.finished(conn, status, Some(message.clone()))?;
+                Err(message.into())
+            }
+        }
+    }
+
+    /// Store a child job step, which runs the processor of this job step for
+    /// the provided item.
+    fn add_child(
+        &self,
+        conn: &PgConnection,
+        index: usize,
+        item: serde_json::Value,
+    ) -> Result<Self, Box<dyn Error>> {
+        let name = format!("{} [{}]", self.name, index);
+        let mut child = NewJobStep::new(
+            &name,
+            self.description.as_ref().map(String::as_ref),
+            serde_json::from_value(self.processor.clone())?,
+            self.position,
+        );
+
+        child.with_retry_policy(self.retry_policy.clone());
+        child.with_item(item);
+        child.add_to_parent(conn, self)
+    }
+
+    /// Mark a job step that never ran as skipped, because the outcome of the
+    /// other job steps did not require it to run.
+    pub(crate) fn skip(&mut self, conn: &PgConnection) -> QueryResult<()> {
+        self.finished(conn, Status::Skipped, None)
+    }
+
+    /// Stop a job step that is waiting for approval, because its job won't
+    /// continue at this step, as the job was cancelled, or another job step
+    /// failed.
+    pub(crate) fn stop_waiting(&mut self, conn: &PgConnection, cancelled: bool) -> QueryResult<()> {
+        let status = if cancelled {
+            Status::Cancelled
+        } else {
+            Status::Skipped
+        };
+
+        self.finished(conn, status, None)
+    }
+
+    /// Run the processor of the job step once.
+    fn run_attempt(
+        &mut self,
+        results: &Results,
+        context: &Context,
+        conn: &PgConnection,
+        database_url: &str,
+        log: &LogHandle,
+    ) -> Result<Option<String>, Box<dyn Error>> {
+        if context.is_timed_out() {
+            return Err("job timed out before the step started".into());
+        }
+
+        match self.formalize_processor(results, context, conn) {
+            Ok(Processor::RequireApproval(p)) => self.require_approval(&p, conn),
+            Ok(Processor::RunTask(p)) => self.run_task(&p, context, conn, database_url, log),
+            Ok(p) => p.run(context),
+            Err(err) => Err(format!("job processor cannot be deserialized: {}", err).into()),
+        }
+    }
+
+    /// Run another task as a sub-job of the job this step belongs to, and
+    /// return the output of the sub-job.
+    fn run_task(
+        &self,
+        processor: &RunTask,
+        context: &Context,
+        conn: &PgConnection,
+        database_url: &str,
+        log: &LogHandle,
+    ) -> Result<Option<String>, Box<dyn Error>> {
+        let task: Task = tasks::table
+            .filter(tasks::name.eq(&processor.task))
+            .first(conn)
+            .optional()?
+            .ok_or_else(|| format!("unknown task: {}", processor.task))?;
+
+        // Values containing a secret stay secret in the sub-job.
+        let secrets = self.secrets(conn)?;
+        let variables = processor
+            .variables
+            .iter()
+            .map(|v| {
+                let mut variable = NewJobVariable::new(&v.key, &v.value);
+                if secrets.appear_in(&v.value) {
+                    variable.with_secret();
+                }
+
+                variable
+            })
+            .collect();
+
+        self.job(conn)?
+            .run_sub_job(conn, &task, variables, database_url, context, log)
+    }
+
+    /// Request approval to continue running the job, and return the decision
+    /// once the request is approved or rejected.
+    ///
+    /// While the request is pending, [`AwaitingApproval`] is returned, and the
+    /// job waits until the request is decided. The job step fails if the
+    /// request is rejected.
+    ///
+    /// Approval can't be requested by a sub-job, or for each item in a list,
+    /// as only a job on its own can wait.
+    fn require_approval(
+        &self,
+        processor: &RequireApproval,
+        conn: &PgConnection,
+    ) -> Result<Option<String>, Box<dyn Error>> {
+        let job = self.job(conn)?;
+        if job.parent_id.is_some() {
+            return Err("approval cannot be required by a sub-job".into());
+        }
+
+        if self.parent_id.is_some() {
+            return Err("approval cannot be required for each item".into());
+        }
+
+        // The last request belongs to this attempt, unless an attempt finished
+        // after it was made.
+        let last_attempt = self.attempts(conn)?.pop();
+        let requested = self.approvals(conn)?.pop().filter(|approval| {
+            last_attempt.map_or(true, |attempt| attempt.finished_at < approval.requested_at)
+        });
+
+        let approval = match requested {
+            Some(approval) => approval,
+            None => {
+                let message = processor
+                    .message
+                    .as_ref()
+                    .map(|message| Secrets::load(&job, conn).map(|s| s.mask(message)))
+                    .transpose()?;
+
+                let _ = NewJobStepApproval::new(
+                    self,
+                    &processor.privilege,
+                    message.as_ref().map(String::as_str),
+                    job.requested_by,
+                )
+                .create(conn)?;
+
+                return Err(AwaitingApproval.into());
             }
+        };
+
+        let session = approval.decided_by.map_or_else(
+            || "unknown session".to_owned(),
+            |id| format!("session {}", id),
+        );
+
+        match approval.approved {
+            None => Err(AwaitingApproval.into()),
+            Some(true) => Ok(Some(format!("approved by {}", session))),
+            Some(false) => Err(format!("rejected by {}", session).into()),
         }
     }
 
@@ -155,7 +680,7 @@ impl JobStep {
         self.started_at = Some(Utc::now().naive_utc());
 
         match self.save_changes::<Self>(conn) {
-            Ok(_) => Ok(()),
+            Ok(_) => self.notify(conn),
             Err(err) => {
                 self.status = Status::Failed;
                 Err(err)
@@ -163,35 +688,84 @@ impl JobStep {
         }
     }
 
+    /// Store the final status and output of the job step.
+    ///
+    /// Any secret values in the output are masked.
     fn finished(
         &mut self,
         conn: &PgConnection,
         status: Status,
         output: Option<String>,
     ) -> QueryResult<()> {
+        let output = match output {
+            Some(output) => Some(self.secrets(conn)?.mask(&output)),
+            None => None,
+        };
+
         self.finished_at = Some(Utc::now().naive_utc());
         self.status = status;
         self.output = output;
 
-        self.save_changes::<Self>(conn).map(|_| ())
+        let _ = self.save_changes::<Self>(conn)?;
+        self.notify(conn)
     }
 
-    /// Takes the associated job step processor, and formalizes its definition
-    /// by replacing any templated variables.
-    fn formalize_processor(
-        &mut self,
-        output_values: &mut HashMap<String, String>,
+    /// Notify any listeners of the current status of the job step.
+    ///
+    /// See [`crate::notification`] for more details.
+    fn notify(&self, conn: &PgConnection) -> QueryResult<()> {
+        let notification = Notification::JobStepStatus {
+            job_id: self.job_id,
+            job_step_id: self.id,
+            status: self.status,
+        };
+
+        notification::notify(conn, &notification)
+    }
+
+    /// Returns `true` if the job step has no condition, or if its condition
+    /// holds.
+    fn condition_holds(
+        &self,
+        results: &Results,
         context: &Context,
         conn: &PgConnection,
-    ) -> Result<Processor, Box<dyn Error>> {
-        let variables = self
-            .job(conn)
-            .and_then(|j| j.variables(conn))
-            .map_err(Into::<Box<dyn Error>>::into)?;
+    ) -> Result<bool, Box<dyn Error>> {
+        let template = match &self.condition {
+            None => return Ok(true),
+            Some(expression) => condition::template(expression),
+        };
 
-        let global_variables: Vec<GlobalVariable> = GlobalVariable::all()
-            .get_results(conn)
-            .map_err(Into::<Box<dyn Error>>::into)?;
+        self.with_template_data(results, context, conn, |data| {
+            render(&template, data)
+                .map(|rendered| condition::holds(&rendered))
+                .map_err(Into::into)
+        })
+    }
+
+    /// Collect all the data that can be used in the templates of the job
+    /// step, and pass it to the provided function.
+    fn with_template_data<T, F>(
+        &self,
+        results: &Results,
+        context: &Context,
+        conn: &PgConnection,
+        f: F,
+    ) -> Result<T, Box<dyn Error>>
+    where
+        F: FnOnce(&TemplateData<'_>) -> Result<T, Box<dyn Error>>,
+    {
+        let job = self.job(conn)?;
+        let variables = job.variables(conn)?;
+
+        // Global variables scoped to task labels are only available to tasks
+        // carrying one of those labels.
+        let labels = job.task(conn)?.map_or_else(Vec::new, |task| task.labels);
+        let global_variables = GlobalVariable::all()
+            .get_results::<GlobalVariable>(conn)?
+            .into_iter()
+            .filter(|variable| variable.is_available_to(&labels))
+            .collect::<Vec<_>>();
 
         let var = variables
             .iter()
@@ -207,7 +781,8 @@ impl JobStep {
             workspace_path: context.workspace_path().to_str().unwrap_or(""),
         };
 
-        let output = output_values
+        let output = results
+            .output
             .iter()
             .map(|(k, v)| (k.as_str(), v.as_str()))
             .collect();
@@ -219,29 +794,54 @@ impl JobStep {
             global,
             context: context_variables,
             output,
+            failure: results.failure.as_ref(),
+            item: self.item.as_ref(),
         };
 
-        // The processor is serialized as `{ "ProcessorType": { ... } }` in the
-        // database in order for Serde to know to which processor to deserialize
-        // the JSON to.
-        //
-        // In this case, we want the configuration of the processor as a JSON
-        // object, so we take "all values" (there's only one, the `{ ... }`
-        // part).
-        let mut processor = self.processor.clone();
-        let config = processor
-            .as_object_mut()
-            .ok_or(INVALID_SERIALIZED_DATA)?
-            .values_mut()
-            .flat_map(serde_json::Value::as_object_mut)
-            .next()
-            .ok_or(INVALID_SERIALIZED_DATA)?;
-
-        // process all values in the processor configuration as their own
-        // templates.
-        config
-            .values_mut()
-            .try_for_each(|v| self.formalize_value(v, &data))?;
+        f(&data)
+    }
+
+    /// Takes the associated job step processor, and formalizes its definition
+    /// by replacing any templated variables.
+    ///
+    /// The formalized processor is stored with the job step, after masking
+    /// any secret values.
+    fn formalize_processor(
+        &mut self,
+        results: &Results,
+        context: &Context,
+        conn: &PgConnection,
+    ) -> Result<Processor, Box<dyn Error>> {
+        let processor = self.with_template_data(results, context, conn, |data| {
+            // The processor is serialized as `{ "ProcessorType": { ... } }` in the
+            // database in order for Serde to know to which processor to deserialize
+            // the JSON to.
+            //
+            // In this case, we want the configuration of the processor as a JSON
+            // object, so we take "all values" (there's only one, the `{ ... }`
+            // part).
+            let mut processor = self.processor.clone();
+            let config = processor
+                .as_object_mut()
+                .ok_or(INVALID_SERIALIZED_DATA)?
+                .values_mut()
+                .flat_map(serde_json::Value::as_object_mut)
+                .next()
+                .ok_or(INVALID_SERIALIZED_DATA)?;
+
+            // process all values in the processor configuration as their own
+            // templates.
+            config
+                .values_mut()
+                .try_for_each(|v| self.formalize_value(v, data))?;
+
+            Ok(processor)
+        })?;
+
+        let mut redacted = processor.clone();
+        self.secrets(conn)?.redact(&mut redacted);
+        self.formalized_processor = Some(redacted);
+        let _ = self.save_changes::<Self>(conn)?;
 
         serde_json::from_value(processor).map_err(Into::into)
     }
@@ -276,33 +876,55 @@ impl JobStep {
             return Ok(());
         };
 
-        let context = TContext::from_serialize(data).map_err(|e| e.to_string())?;
+        *value = render(value.as_str().unwrap(), data)?.into();
+        Ok(())
+    }
+}
 
-        let mut tera = Tera::default();
-        tera.add_raw_template("processor configuration", value.as_str().unwrap())
-            .map_err(|e| e.to_string())?;
+/// Render a template using a Jinja-like templating language (using the Tera
+/// crate), with the provided dataset of key/value pairs.
+fn render(template: &str, data: &TemplateData<'_>) -> Result<String, String> {
+    let context = TContext::from_serialize(data).map_err(|e| e.to_string())?;
+
+    let mut tera = Tera::default();
+    tera.add_raw_template("processor configuration", template)
+        .map_err(|e| e.to_string())?;
+
+    tera.render("processor configuration", context)
+        .map_err(|err| {
+            use tera::ErrorKind::*;
+
+            match err.kind {
+                FilterNotFound(string) => format!("missing template filter: {}", string),
+                TestNotFound(string) => format!("missing template test: {}", string),
+                FunctionNotFound(string) => format!("missing template function: {}", string),
+                Json(string) => format!("template json error: {}", string),
+                _ => match err.source() {
+                    Some(source) => format!("template error: {}", source.to_string()),
+                    None => format!("unknown template error: {}", err.to_string()),
+                },
+            }
+        })
+}
 
-        match tera.render("processor configuration", context) {
-            Ok(string) => *value = string.into(),
-            Err(err) => {
-                use tera::ErrorKind::*;
-
-                let string = match err.kind {
-                    FilterNotFound(string) => format!("missing template filter: {}", string),
-                    TestNotFound(string) => format!("missing template test: {}", string),
-                    FunctionNotFound(string) => format!("missing template function: {}", string),
-                    Json(string) => format!("template json error: {}", string),
-                    _ => match err.source() {
-                        Some(source) => format!("template error: {}", source.to_string()),
-                        None => format!("unknown template error: {}", err.to_string()),
-                    },
-                };
+/// Wait for the provided duration before retrying a job step.
+///
+/// Returns `false` if the job was cancelled, or timed out while waiting, in
+/// which case the job step should not be retried.
+fn wait(duration: Duration, context: &Context) -> bool {
+    let until = Instant::now() + duration;
+
+    loop {
+        if context.is_cancelled() || context.is_timed_out() {
+            return false;
+        }
 
-                return Err(string);
-            }
-        };
+        let now = Instant::now();
+        if now >= until {
+            return true;
+        }
 
-        Ok(())
+        thread::sleep(RETRY_POLL_INTERVAL.min(until - now));
     }
 }
 
@@ -319,6 +941,13 @@ pub(crate) struct NewJobStep<'a> {
     finished_at: Option<NaiveDateTime>,
     output: Option<&'a str>,
     status: Status,
+    timeout_seconds: Option<i32>,
+    retry_policy: Option<serde_json::Value>,
+    depends_on: Option<Vec<String>>,
+    condition: Option<String>,
+    run_when: StepRunWhen,
+    for_each: Option<String>,
+    item: Option<serde_json::Value>,
 }
 
 impl<'a> NewJobStep<'a> {
@@ -339,9 +968,56 @@ impl<'a> NewJobStep<'a> {
             finished_at: None,
             output: None,
             status: Status::Initialized,
+            timeout_seconds: None,
+            retry_policy: None,
+            depends_on: None,
+            condition: None,
+            run_when: StepRunWhen::OnSuccess,
+            for_each: None,
+            item: None,
         }
     }
 
+    /// Define when the job step runs, depending on the outcome of the other
+    /// job steps.
+    pub(crate) fn with_run_when(&mut self, run_when: StepRunWhen) {
+        self.run_when = run_when
+    }
+
+    /// Run the job step once for each item in the list the provided template
+    /// renders to.
+    pub(crate) fn with_for_each(&mut self, template: Option<String>) {
+        self.for_each = template
+    }
+
+    /// Run the job step for the provided item, as a child of another job
+    /// step.
+    fn with_item(&mut self, item: serde_json::Value) {
+        self.item = Some(item)
+    }
+
+    /// Only run the job step if the provided condition holds.
+    pub(crate) fn with_condition(&mut self, condition: Option<String>) {
+        self.condition = condition
+    }
+
+    /// Run the job step once the job steps with the provided names finished
+    /// running, instead of after the job step preceding it.
+    pub(crate) fn with_depends_on(&mut self, depends_on: Option<Vec<String>>) {
+        self.depends_on = depends_on
+    }
+
+    /// Retry the job step when it fails, according to the provided
+    /// (serialized) policy.
+    pub(crate) fn with_retry_policy(&mut self, retry_policy: Option<serde_json::Value>) {
+        self.retry_policy = retry_policy
+    }
+
+    /// Limit the time the job step is allowed to run.
+    pub(crate) fn with_timeout_seconds(&mut self, timeout_seconds: Option<i32>) {
+        self.timeout_seconds = timeout_seconds
+    }
+
     /// Add a step to a [`Job`], by storing it in the database as an
     /// association.
     ///
@@ -350,6 +1026,25 @@ impl<'a> NewJobStep<'a> {
     ///
     /// This method can return an error if the database insert failed.
     pub(crate) fn add_to_job(self, conn: &PgConnection, job: &Job) -> Result<(), Box<dyn Error>> {
+        self.insert(conn, job.id, None).map(|_| ())
+    }
+
+    /// Add a child step to a [`JobStep`], which runs as part of its parent,
+    /// instead of being run by the job directly.
+    fn add_to_parent(
+        self,
+        conn: &PgConnection,
+        parent: &JobStep,
+    ) -> Result<JobStep, Box<dyn Error>> {
+        self.insert(conn, parent.job_id, Some(parent.id))
+    }
+
+    fn insert(
+        self,
+        conn: &PgConnection,
+        job: i32,
+        parent: Option<i32>,
+    ) -> Result<JobStep, Box<dyn Error>> {
         use crate::schema::job_steps::dsl::*;
 
         let values = (
@@ -361,13 +1056,20 @@ impl<'a> NewJobStep<'a> {
             finished_at.eq(self.finished_at),
             status.eq(Status::Pending),
             output.eq(&self.output),
-            job_id.eq(job.id),
+            job_id.eq(job),
+            timeout_seconds.eq(self.timeout_seconds),
+            retry_policy.eq(self.retry_policy),
+            depends_on.eq(self.depends_on),
+            condition.eq(self.condition),
+            run_when.eq(self.run_when),
+            for_each.eq(self.for_each),
+            parent_id.eq(parent),
+            item.eq(self.item),
         );
 
         diesel::insert_into(job_steps)
             .values(values)
-            .execute(conn)
-            .map(|_| ())
+            .get_result(conn)
             .map_err(Into::into)
     }
 }
@@ -412,12 +1114,72 @@ pub(crate) mod graphql {
             self.processor()
         }
 
+        /// The processor as used the last time the job step ran, with all
+        /// templates rendered.
+        ///
+        /// The value of any secret variable is replaced by a placeholder.
+        ///
+        /// This field returns `null` if the job step did not run yet.
+        fn formalized_processor() -> Option<Processor> {
+            self.formalized_processor()
+        }
+
         /// The position of the step in a job, compared to other steps in the
         /// same job. A lower number means the step runs earlier in the job.
         fn position() -> i32 {
             self.position
         }
 
+        /// The number of seconds the job step is allowed to run, if any.
+        fn timeout_seconds() -> Option<i32> {
+            self.timeout_seconds
+        }
+
+        /// The policy defining if, and how, the job step is retried when it
+        /// fails.
+        fn retry_policy() -> Option<RetryPolicy> {
+            self.retry_policy()
+        }
+
+        /// The names of the job steps that have to finish successfully before
+        /// this job step runs.
+        ///
+        /// If this value is `null`, the job step runs after the job step
+        /// preceding it.
+        fn depends_on() -> Option<Vec<&str>> {
+            self.depends_on
+                .as_ref()
+                .map(|names| names.iter().map(String::as_str).collect())
+        }
+
+        /// The condition that has to hold for the job step to run, if any.
+        ///
+        /// If the condition did not hold, the job step has the `SKIPPED`
+        /// status.
+        fn condition() -> Option<&str> {
+            self.condition.as_ref().map(String::as_ref)
+        }
+
+        /// Defines when the job step runs, depending on the outcome of the
+        /// other job steps.
+        fn run_when() -> StepRunWhen {
+            self.run_when
+        }
+
+        /// The template rendering the list of items the job step runs for,
+        /// if any.
+        ///
+        /// Each item runs as a child job step, see `children`.
+        fn for_each() -> Option<&str> {
+            self.for_each.as_ref().map(String::as_ref)
+        }
+
+        /// The item this job step ran for, serialized as JSON, if this is a
+        /// child of a job step that runs for each item in a list.
+        fn item() -> Option<String> {
+            self.item.as_ref().map(ToString::to_string)
+        }
+
         fn started_at() -> Option<DateTime<Utc>> {
             self.started_at.map(|t| DateTime::from_utc(t, Utc))
         }
@@ -435,6 +1197,112 @@ pub(crate) mod graphql {
             StepOutput(self.output.as_ref().map(String::as_ref))
         }
 
+        /// The approval requests made by the job step, ordered from first to
+        /// last.
+        ///
+        /// Only job steps using the `RequireApproval` processor request
+        /// approval. A job step makes a new request each time it is retried.
+        ///
+        /// This field can return `null`, but _only_ if a database error
+        /// prevents the data from being retrieved.
+        ///
+        /// If the job step did not request approval, an empty array is
+        /// returned instead.
+        ///
+        /// If a `null` value is returned, it is up to the client to decide the
+        /// best course of action. The following actions are advised, sorted by
+        /// preference:
+        ///
+        /// 1. continue execution if the information is not critical to success,
+        /// 2. retry the request to try and get the relevant information,
+        /// 3. disable parts of the application reliant on the information,
+        /// 4. show a global error, and ask the user to retry.
+        fn approvals(context: &RequestState) -> FieldResult<Option<Vec<JobStepApproval>>> {
+            self.approvals(&context.conn).map(Some).map_err(Into::into)
+        }
+
+        /// The attempts made to run the job step, ordered from first to last.
+        ///
+        /// A job step has a single attempt, unless it has a retry policy that
+        /// allowed the step to be retried after it failed.
+        ///
+        /// This field can return `null`, but _only_ if a database error
+        /// prevents the data from being retrieved.
+        ///
+        /// If the job step has not run yet, an empty array is returned
+        /// instead.
+        ///
+        /// If a `null` value is returned, it is up to the client to decide the
+        /// best course of action. The following actions are advised, sorted by
+        /// preference:
+        ///
+        /// 1. continue execution if the information is not critical to success,
+        /// 2. retry the request to try and get the relevant information,
+        /// 3. disable parts of the application reliant on the information,
+        /// 4. show a global error, and ask the user to retry.
+        fn attempts(context: &RequestState) -> FieldResult<Option<Vec<JobStepAttempt>>> {
+            self.attempts(&context.conn).map(Some).map_err(Into::into)
+        }
+
+        /// The child job steps, one for each item the job step ran for, in the
+        /// order of the items.
+        ///
+        /// This field can return `null`, but _only_ if a database error
+        /// prevents the data from being retrieved.
+        ///
+        /// If the job step does not run for each item in a list, or has not
+        /// run yet, an empty array is returned instead.
+        ///
+        /// If a `null` value is returned, it is up to the client to decide the
+        /// best course of action. The following actions are advised, sorted by
+        /// preference:
+        ///
+        /// 1. continue execution if the information is not critical to success,
+        /// 2. retry the request to try and get the relevant information,
+        /// 3. disable parts of the application reliant on the information,
+        /// 4. show a global error, and ask the user to retry.
+        fn children(context: &RequestState) -> FieldResult<Option<Vec<JobStep>>> {
+            self.children(&context.conn).map(Some).map_err(Into::into)
+        }
+
+        /// The lines of output written by the processor of the job step while
+        /// it ran, ordered by their position.
+        ///
+        /// Only lines with a position equal to, or higher than `offset` are
+        /// returned (defaults to `0`), and at most `limit` lines are returned
+        /// (defaults to all lines). To follow the log of a running job step,
+        /// use the position of the last received line, plus one, as the
+        /// offset of the next request.
+        ///
+        /// Not all processors write their output while running, in which case
+        /// the log stays empty, and the output is only available once the job
+        /// step finishes.
+        ///
+        /// This field can return `null`, but _only_ if a database error
+        /// prevents the data from being retrieved.
+        ///
+        /// If a `null` value is returned, it is up to the client to decide the
+        /// best course of action. The following actions are advised, sorted by
+        /// preference:
+        ///
+        /// 1. continue execution if the information is not critical to success,
+        /// 2. retry the request to try and get the relevant information,
+        /// 3. disable parts of the application reliant on the information,
+        /// 4. show a global error, and ask the user to retry.
+        fn log(
+            context: &RequestState,
+            offset: Option<i32>,
+            limit: Option<i32>,
+        ) -> FieldResult<Option<Vec<JobStepLog>>> {
+            if limit.map_or(false, |limit| limit < 0) {
+                return Err("Log limit cannot be a negative number.".into());
+            }
+
+            self.log(&context.conn, offset.unwrap_or(0), limit.map(i64::from))
+                .map(Some)
+                .map_err(Into::into)
+        }
+
         /// The job to which the step belongs.
         ///
         /// This field can return `null`, but _only_ if a database error
@@ -459,7 +1327,7 @@ pub(crate) mod graphql {
 
     /// The output of the step, presented in different formats.
     #[derive(Clone, Debug, Deserialize, Serialize)]
-    pub(crate) struct StepOutput<'a>(Option<&'a str>);
+    pub(crate) struct StepOutput<'a>(pub(crate) Option<&'a str>);
 
     #[object]
     impl<'a> StepOutput<'a> {
@@ -494,11 +1362,19 @@ impl<'a> TryFrom<&'a Step> for NewJobStep<'a> {
     type Error = serde_json::Error;
 
     fn try_from(step: &'a Step) -> Result<Self, Self::Error> {
-        Ok(Self::new(
+        let mut job_step = Self::new(
             &step.name,
             step.description.as_ref().map(String::as_ref),
             serde_json::from_value(step.processor.clone())?,
             step.position,
-        ))
+        );
+
+        job_step.with_timeout_seconds(step.timeout_seconds);
+        job_step.with_retry_policy(step.retry_policy.clone());
+        job_step.with_depends_on(step.depends_on.clone());
+        job_step.with_condition(step.condition.clone());
+        job_step.with_run_when(step.run_when);
+        job_step.with_for_each(step.for_each.clone());
+        Ok(job_step)
     }
 }
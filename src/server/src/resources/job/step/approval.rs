@@ -0,0 +1,217 @@
+//! A [`JobStepApproval`] records a request to approve a [`JobStep`], before
+//! the job it belongs to continues running.
+//!
+//! An approval is requested by a job step using the `RequireApproval`
+//! processor. While the request is pending, the job is `Waiting`, and no
+//! worker runs it. The request is decided by a session with the privilege
+//! configured on the processor, which can't be the session that requested the
+//! job.
+//!
+//! Once the request is decided, the job is `Pending` again, and the worker that
+//! picks it up continues running the job at the step that requested approval.
+
+use crate::resources::{Job, JobStatus, JobStep};
+use crate::schema::{job_step_approvals, job_steps, jobs};
+use crate::server::RequestState;
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// The model representing a job step approval stored in the database.
+#[derive(Clone, Debug, Deserialize, Serialize, Associations, Identifiable, Queryable)]
+#[belongs_to(JobStep)]
+#[table_name = "job_step_approvals"]
+pub(crate) struct JobStepApproval {
+    pub(crate) id: i32,
+    pub(crate) job_step_id: i32,
+    pub(crate) privilege: String,
+    pub(crate) message: Option<String>,
+
+    /// The ID of the session that requested the job, if any.
+    pub(crate) requested_by: Option<i32>,
+    pub(crate) requested_at: NaiveDateTime,
+
+    /// Whether the request was approved or rejected. If `None`, the request
+    /// is still pending.
+    pub(crate) approved: Option<bool>,
+
+    /// The ID of the session that approved or rejected the request, if any.
+    pub(crate) decided_by: Option<i32>,
+    pub(crate) decided_at: Option<NaiveDateTime>,
+}
+
+impl JobStepApproval {
+    /// Returns all pending approvals of jobs that are waiting for them.
+    pub(crate) fn pending(conn: &PgConnection) -> QueryResult<Vec<Self>> {
+        job_step_approvals::table
+            .inner_join(job_steps::table.inner_join(jobs::table))
+            .filter(job_step_approvals::approved.is_null())
+            .filter(jobs::status.eq(JobStatus::Waiting))
+            .select(job_step_approvals::all_columns)
+            .order(job_step_approvals::requested_at.asc())
+            .load(conn)
+    }
+
+    /// Returns the pending approval of the provided job, if the job is
+    /// waiting for one.
+    pub(crate) fn pending_for_job(job: &Job, conn: &PgConnection) -> QueryResult<Option<Self>> {
+        job_step_approvals::table
+            .inner_join(job_steps::table.inner_join(jobs::table))
+            .filter(job_step_approvals::approved.is_null())
+            .filter(jobs::id.eq(job.id))
+            .filter(jobs::status.eq(JobStatus::Waiting))
+            .select(job_step_approvals::all_columns)
+            .order(job_step_approvals::id.desc())
+            .first(conn)
+            .optional()
+    }
+
+    /// Approve or reject the request on behalf of the session with the
+    /// provided ID.
+    ///
+    /// Returns an error if the request was already decided.
+    pub(crate) fn decide(
+        &self,
+        conn: &PgConnection,
+        session_id: i32,
+        approved: bool,
+    ) -> Result<Self, String> {
+        let pending = job_step_approvals::table
+            .filter(job_step_approvals::id.eq(self.id))
+            .filter(job_step_approvals::approved.is_null());
+
+        diesel::update(pending)
+            .set((
+                job_step_approvals::approved.eq(approved),
+                job_step_approvals::decided_by.eq(session_id),
+                job_step_approvals::decided_at.eq(Utc::now().naive_utc()),
+            ))
+            .get_result(conn)
+            .optional()
+            .map_err(|err| err.to_string())?
+            .ok_or_else(|| "approval request was already decided".to_owned())
+    }
+
+    pub(crate) fn job_step(&self, conn: &PgConnection) -> QueryResult<JobStep> {
+        job_steps::table.find(self.job_step_id).first(conn)
+    }
+}
+
+/// Contains all the details needed to store a job step approval in the
+/// database.
+///
+/// Use [`NewJobStepApproval::new`] to initialize this struct.
+#[derive(Clone, Debug, Insertable)]
+#[table_name = "job_step_approvals"]
+pub(crate) struct NewJobStepApproval<'a> {
+    job_step_id: i32,
+    privilege: &'a str,
+    message: Option<&'a str>,
+    requested_by: Option<i32>,
+    requested_at: NaiveDateTime,
+}
+
+impl<'a> NewJobStepApproval<'a> {
+    /// Initialize a `NewJobStepApproval` struct, which can be inserted into
+    /// the database using the [`NewJobStepApproval#create`] method.
+    pub(crate) fn new(
+        step: &JobStep,
+        privilege: &'a str,
+        message: Option<&'a str>,
+        requested_by: Option<i32>,
+    ) -> Self {
+        Self {
+            job_step_id: step.id,
+            privilege,
+            message,
+            requested_by,
+            requested_at: Utc::now().naive_utc(),
+        }
+    }
+
+    /// Save the approval request in the database.
+    pub(crate) fn create(self, conn: &PgConnection) -> QueryResult<JobStepApproval> {
+        diesel::insert_into(job_step_approvals::table)
+            .values(&self)
+            .get_result(conn)
+    }
+}
+
+pub(crate) mod graphql {
+    //! All GraphQL related functionality is encapsulated in this module. The
+    //! relevant functions and structs are re-exported through
+    //! [`crate::graphql`].
+    //!
+    //! API documentation in this module is also used in the GraphQL API itself
+    //! as documentation for the clients.
+    //!
+    //! You can browse to `/graphql/playground` to see all relevant query,
+    //! mutation, and type documentation.
+
+    use super::*;
+    use chrono::{DateTime, Utc};
+    use juniper::{object, FieldResult, ID};
+
+    #[object(Context = RequestState)]
+    impl JobStepApproval {
+        /// The unique identifier for a specific approval request.
+        fn id() -> ID {
+            ID::new(self.id.to_string())
+        }
+
+        /// The privilege a session needs to approve or reject the request.
+        fn privilege() -> &str {
+            &self.privilege
+        }
+
+        /// An (optional) message explaining what is being approved.
+        fn message() -> Option<&str> {
+            self.message.as_ref().map(String::as_ref)
+        }
+
+        /// The ID of the session that requested the job, if any.
+        ///
+        /// This session is not allowed to decide the request.
+        fn requested_by() -> Option<ID> {
+            self.requested_by.map(|id| ID::new(id.to_string()))
+        }
+
+        fn requested_at() -> DateTime<Utc> {
+            DateTime::from_utc(self.requested_at, Utc)
+        }
+
+        /// Whether the request was approved (`true`) or rejected (`false`).
+        ///
+        /// This value is `null` while the request is pending.
+        fn approved() -> Option<bool> {
+            self.approved
+        }
+
+        /// The ID of the session that approved or rejected the request, if
+        /// any.
+        fn decided_by() -> Option<ID> {
+            self.decided_by.map(|id| ID::new(id.to_string()))
+        }
+
+        fn decided_at() -> Option<DateTime<Utc>> {
+            self.decided_at.map(|t| DateTime::from_utc(t, Utc))
+        }
+
+        /// The job step that requested the approval.
+        ///
+        /// This field can return `null`, but _only_ if a database error
+        /// prevents the data from being retrieved.
+        ///
+        /// If a `null` value is returned, it is up to the client to decide the
+        /// best course of action. The following actions are advised, sorted by
+        /// preference:
+        ///
+        /// 1. continue execution if the information is not critical to success,
+        /// 2. retry the request to try and get the relevant information,
+        /// 3. disable parts of the application reliant on the information,
+        /// 4. show a global error, and ask the user to retry.
+        fn job_step(context: &RequestState) -> FieldResult<Option<JobStep>> {
+            self.job_step(&context.conn).map(Some).map_err(Into::into)
+        }
+    }
+}
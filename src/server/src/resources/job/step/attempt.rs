@@ -0,0 +1,114 @@
+//! A [`JobStepAttempt`] records a single run of a [`JobStep`].
+//!
+//! A job step runs at least once, but can run multiple times if its retry
+//! policy allows it. Each run is stored as a separate attempt, so that the
+//! history of a retried step is available after the job finished running.
+
+use crate::resources::{JobStep, JobStepStatus};
+use crate::schema::job_step_attempts;
+use crate::server::RequestState;
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// The model representing a job step attempt stored in the database.
+#[derive(Clone, Debug, Deserialize, Serialize, Associations, Identifiable, Queryable)]
+#[belongs_to(JobStep)]
+#[table_name = "job_step_attempts"]
+pub(crate) struct JobStepAttempt {
+    pub(crate) id: i32,
+    pub(crate) attempt: i32,
+    pub(crate) started_at: NaiveDateTime,
+    pub(crate) finished_at: NaiveDateTime,
+    pub(crate) status: JobStepStatus,
+    pub(crate) output: Option<String>,
+    pub(crate) job_step_id: i32,
+}
+
+/// Contains all the details needed to store a job step attempt in the
+/// database.
+///
+/// Use [`NewJobStepAttempt::new`] to initialize this struct.
+#[derive(Clone, Debug, Insertable)]
+#[table_name = "job_step_attempts"]
+pub(crate) struct NewJobStepAttempt<'a> {
+    attempt: i32,
+    started_at: NaiveDateTime,
+    finished_at: NaiveDateTime,
+    status: JobStepStatus,
+    output: Option<&'a str>,
+    job_step_id: i32,
+}
+
+impl<'a> NewJobStepAttempt<'a> {
+    /// Initialize a `NewJobStepAttempt` struct, which can be inserted into the
+    /// database using the [`NewJobStepAttempt#create`] method.
+    pub(crate) const fn new(
+        step: &JobStep,
+        attempt: i32,
+        started_at: NaiveDateTime,
+        finished_at: NaiveDateTime,
+        status: JobStepStatus,
+        output: Option<&'a str>,
+    ) -> Self {
+        Self {
+            attempt,
+            started_at,
+            finished_at,
+            status,
+            output,
+            job_step_id: step.id,
+        }
+    }
+
+    /// Save the attempt in the database.
+    pub(crate) fn create(self, conn: &PgConnection) -> QueryResult<JobStepAttempt> {
+        diesel::insert_into(job_step_attempts::table)
+            .values(&self)
+            .get_result(conn)
+    }
+}
+
+pub(crate) mod graphql {
+    //! All GraphQL related functionality is encapsulated in this module. The
+    //! relevant functions and structs are re-exported through
+    //! [`crate::graphql`].
+    //!
+    //! API documentation in this module is also used in the GraphQL API itself
+    //! as documentation for the clients.
+    //!
+    //! You can browse to `/graphql/playground` to see all relevant query,
+    //! mutation, and type documentation.
+
+    use super::*;
+    use crate::resources::job::step::graphql::StepOutput;
+    use chrono::{DateTime, Utc};
+    use juniper::object;
+
+    #[object(Context = RequestState)]
+    impl JobStepAttempt {
+        /// The number of the attempt, starting at `1` for the first run of the
+        /// job step.
+        fn attempt() -> i32 {
+            self.attempt
+        }
+
+        fn started_at() -> DateTime<Utc> {
+            DateTime::from_utc(self.started_at, Utc)
+        }
+
+        fn finished_at() -> DateTime<Utc> {
+            DateTime::from_utc(self.finished_at, Utc)
+        }
+
+        /// The status of the job step after this attempt finished.
+        fn status() -> JobStepStatus {
+            self.status
+        }
+
+        /// The output of the attempt, available in different formats.
+        fn output() -> StepOutput<'_> {
+            StepOutput(self.output.as_ref().map(String::as_ref))
+        }
+    }
+}
@@ -0,0 +1,306 @@
+//! A [`JobStepLog`] is a single line of output, written by the processor of a
+//! [`JobStep`] while it runs.
+//!
+//! Processors only return their output once they finish running. Long-running
+//! processors (such as shell commands) can also write their output line by
+//! line to the [`Context`] of the job, so that clients can follow the progress
+//! of a job step while it runs.
+//!
+//! Lines are stored by a [`LogWriter`], which uses its own database connection
+//! in a background thread, to prevent slowing down the running processor.
+//!
+//! [`Context`]: automaat_core::Context
+
+use crate::notification::{self, Notification};
+use crate::resources::{JobStep, Secrets};
+use crate::schema::job_step_logs;
+use crate::server::RequestState;
+use automaat_core::{OutputSink, OutputStream};
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use juniper::GraphQLEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::{Entry, HashMap};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+/// The output stream to which a line was written.
+#[derive(Clone, Copy, Debug, PartialEq, DbEnum, GraphQLEnum, Serialize, Deserialize)]
+#[PgType = "JobStepLogStream"]
+#[graphql(name = "JobStepLogStream")]
+pub(crate) enum Stream {
+    /// The standard output of the processor.
+    Stdout,
+
+    /// The error output of the processor.
+    Stderr,
+}
+
+impl From<OutputStream> for Stream {
+    fn from(stream: OutputStream) -> Self {
+        match stream {
+            OutputStream::Stdout => Stream::Stdout,
+            OutputStream::Stderr => Stream::Stderr,
+        }
+    }
+}
+
+/// The model representing a job step log line stored in the database.
+#[derive(Clone, Debug, Deserialize, Serialize, Associations, Identifiable, Queryable)]
+#[belongs_to(JobStep)]
+#[table_name = "job_step_logs"]
+pub(crate) struct JobStepLog {
+    pub(crate) id: i32,
+    pub(crate) position: i32,
+    pub(crate) stream: Stream,
+    pub(crate) line: String,
+    pub(crate) created_at: NaiveDateTime,
+    pub(crate) job_step_id: i32,
+}
+
+/// Contains all the details needed to store a job step log line in the
+/// database.
+#[derive(Clone, Debug, Insertable)]
+#[table_name = "job_step_logs"]
+struct NewJobStepLog {
+    position: i32,
+    stream: Stream,
+    line: String,
+    job_step_id: i32,
+}
+
+/// A line written by a processor, waiting to be stored.
+#[derive(Debug)]
+struct Line {
+    job_id: i32,
+    job_step_id: i32,
+    stream: Stream,
+    line: String,
+}
+
+/// A message sent to the background thread of a [`LogWriter`].
+#[derive(Debug)]
+enum Message {
+    /// A line to store.
+    Line(Line),
+
+    /// Stop storing lines, once all lines sent before are stored.
+    Finish,
+}
+
+/// Stores the output written by processors in the database.
+///
+/// Use [`LogWriter::handle`] to get a handle that creates output sinks for job
+/// steps, and [`LogWriter::finish`] to wait for all written lines to be
+/// stored.
+pub(crate) struct LogWriter {
+    sender: mpsc::Sender<Message>,
+    handle: thread::JoinHandle<()>,
+}
+
+impl LogWriter {
+    /// Start a background thread that stores all lines written to the sinks
+    /// of this writer, using the provided database connection.
+    pub(crate) fn start(conn: PgConnection) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let handle = thread::spawn(move || store(&conn, &receiver));
+
+        Self { sender, handle }
+    }
+
+    /// Returns a handle to create output sinks with, which can be sent to
+    /// other threads.
+    pub(crate) fn handle(&self) -> LogHandle {
+        LogHandle {
+            sender: self.sender.clone(),
+        }
+    }
+
+    /// Wait until all lines written so far are stored, and stop the
+    /// background thread.
+    ///
+    /// This does not wait for the handles and sinks created by this writer to
+    /// be dropped. Any line written to them afterwards is discarded.
+    pub(crate) fn finish(self) {
+        let _ = self.sender.send(Message::Finish);
+        let _ = self.handle.join();
+    }
+}
+
+/// A handle to a [`LogWriter`], shared by a job with its steps and sub-jobs.
+#[derive(Clone, Debug)]
+pub(crate) struct LogHandle {
+    sender: mpsc::Sender<Message>,
+}
+
+impl LogHandle {
+    /// Returns an output sink that writes lines to the log of the provided
+    /// job step, masking the provided secrets.
+    pub(crate) fn sink(&self, step: &JobStep, secrets: Secrets) -> Arc<dyn OutputSink> {
+        Arc::new(LogSink {
+            job_id: step.job_id,
+            job_step_id: step.id,
+            secrets,
+            sender: Mutex::new(self.sender.clone()),
+        })
+    }
+}
+
+/// An output sink for a single job step.
+#[derive(Debug)]
+struct LogSink {
+    job_id: i32,
+    job_step_id: i32,
+    secrets: Secrets,
+    sender: Mutex<mpsc::Sender<Message>>,
+}
+
+impl OutputSink for LogSink {
+    fn write_line(&self, stream: OutputStream, line: &str) {
+        let line = Line {
+            job_id: self.job_id,
+            job_step_id: self.job_step_id,
+            stream: stream.into(),
+            line: self.secrets.mask(line),
+        };
+
+        if let Ok(sender) = self.sender.lock() {
+            let _ = sender.send(Message::Line(line));
+        }
+    }
+}
+
+/// Store all received lines, until the writer finishes.
+///
+/// Lines received in quick succession are stored in a single batch. After
+/// each batch, listeners are notified of the new lines.
+///
+/// Lines that can't be stored are reported, but otherwise ignored, as a
+/// missing log line should not fail the job step.
+fn store(conn: &PgConnection, receiver: &mpsc::Receiver<Message>) {
+    let mut positions = HashMap::new();
+
+    while let Ok(Message::Line(line)) = receiver.recv() {
+        let mut lines = vec![line];
+        let mut finished = false;
+
+        for message in receiver.try_iter() {
+            match message {
+                Message::Line(line) => lines.push(line),
+                Message::Finish => {
+                    finished = true;
+                    break;
+                }
+            }
+        }
+
+        if let Err(err) = store_batch(conn, &mut positions, lines) {
+            println!("failed to store job step log: {}", err);
+        }
+
+        if finished {
+            break;
+        }
+    }
+}
+
+/// Store a batch of lines, assigning each line the next position in the log
+/// of its job step.
+fn store_batch(
+    conn: &PgConnection,
+    positions: &mut HashMap<i32, i32>,
+    lines: Vec<Line>,
+) -> QueryResult<()> {
+    let mut steps = vec![];
+    let mut logs = Vec::with_capacity(lines.len());
+
+    for line in lines {
+        let position = match positions.entry(line.job_step_id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(next_position(conn, line.job_step_id)?),
+        };
+
+        logs.push(NewJobStepLog {
+            position: *position,
+            stream: line.stream,
+            line: line.line,
+            job_step_id: line.job_step_id,
+        });
+
+        *position += 1;
+
+        if !steps.contains(&(line.job_id, line.job_step_id)) {
+            steps.push((line.job_id, line.job_step_id));
+        }
+    }
+
+    let _ = diesel::insert_into(job_step_logs::table)
+        .values(&logs)
+        .execute(conn)?;
+
+    steps.into_iter().try_for_each(|(job_id, job_step_id)| {
+        notification::notify(
+            conn,
+            &Notification::JobStepLog {
+                job_id,
+                job_step_id,
+            },
+        )
+    })
+}
+
+/// Returns the position of the next line in the log of a job step.
+///
+/// A job step can already have a log, if it ran before.
+fn next_position(conn: &PgConnection, step_id: i32) -> QueryResult<i32> {
+    use crate::schema::job_step_logs::dsl::*;
+    use diesel::dsl::max;
+
+    job_step_logs
+        .filter(job_step_id.eq(step_id))
+        .select(max(position))
+        .first::<Option<i32>>(conn)
+        .map(|last| last.map_or(0, |last| last + 1))
+}
+
+pub(crate) mod graphql {
+    //! All GraphQL related functionality is encapsulated in this module. The
+    //! relevant functions and structs are re-exported through
+    //! [`crate::graphql`].
+    //!
+    //! API documentation in this module is also used in the GraphQL API itself
+    //! as documentation for the clients.
+    //!
+    //! You can browse to `/graphql/playground` to see all relevant query,
+    //! mutation, and type documentation.
+
+    use super::*;
+    use chrono::{DateTime, Utc};
+    use juniper::object;
+
+    #[object(Context = RequestState)]
+    impl JobStepLog {
+        /// The position of the line in the log of the job step, starting at
+        /// `0` for the first line.
+        ///
+        /// Use this value as the `offset` when fetching the next lines of the
+        /// log.
+        fn position() -> i32 {
+            self.position
+        }
+
+        /// The output stream to which the line was written.
+        fn stream() -> Stream {
+            self.stream
+        }
+
+        /// The line of output, without its trailing newline.
+        fn line() -> &str {
+            self.line.as_ref()
+        }
+
+        fn created_at() -> DateTime<Utc> {
+            DateTime::from_utc(self.created_at, Utc)
+        }
+    }
+}
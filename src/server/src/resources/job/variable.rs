@@ -1,8 +1,8 @@
-use crate::resources::Job;
+use crate::encryption::encrypt;
+use crate::resources::{Job, Variable};
 use crate::schema::job_variables;
-use crate::ENCRYPTION_SECRET;
+use crate::ENCRYPTION_KEYS;
 use diesel::prelude::*;
-use diesel::sql_types::{Bytea, Text};
 use serde::{Deserialize, Serialize};
 use std::{error::Error, str};
 
@@ -16,10 +16,10 @@ pub(crate) struct JobVariable {
     pub(crate) key: String,
     pub(crate) value: String,
     pub(crate) job_id: i32,
-}
 
-sql_function!(fn pgp_sym_encrypt(data: Text, secret: Text) -> Bytea);
-sql_function!(fn pgp_sym_decrypt(data: Bytea, secret: Text) -> Text);
+    /// Whether the value is masked in anything stored about the job.
+    pub(crate) secret: bool,
+}
 
 /// Contains all the details needed to store a job variable in the database.
 ///
@@ -28,19 +28,41 @@ sql_function!(fn pgp_sym_decrypt(data: Bytea, secret: Text) -> Text);
 pub(crate) struct NewJobVariable<'a> {
     key: &'a str,
     value: &'a str,
+    secret: bool,
 }
 
 impl<'a> NewJobVariable<'a> {
     /// Initialize a `NewJobVariable` struct, which can be inserted into the
     /// database using the [`NewJobVariable#add_to_job`] method.
     pub(crate) const fn new(key: &'a str, value: &'a str) -> Self {
-        Self { key, value }
+        Self {
+            key,
+            value,
+            secret: false,
+        }
+    }
+
+    /// Mark the variable as secret, masking its value in anything stored
+    /// about the job.
+    ///
+    /// A variable is also secret if the matching variable of the task the job
+    /// is created from is secret.
+    pub(crate) fn with_secret(&mut self) {
+        self.secret = true
     }
 
     pub(crate) const fn key(&self) -> &str {
         self.key
     }
 
+    pub(crate) const fn value(&self) -> &str {
+        self.value
+    }
+
+    pub(crate) const fn is_secret(&self) -> bool {
+        self.secret
+    }
+
     /// Add a variable to a [`Job`], by storing it in the database as an
     /// association.
     ///
@@ -51,13 +73,23 @@ impl<'a> NewJobVariable<'a> {
     pub(crate) fn add_to_job(self, conn: &PgConnection, job: &Job) -> Result<(), Box<dyn Error>> {
         use crate::schema::job_variables::dsl::*;
 
-        self.validate_selection_constraint(conn, job)?;
+        // An optional variable without a value is not validated.
+        let mut is_secret = self.secret;
+        if let Some(variable) = self.task_variable(conn, job)? {
+            if !(variable.optional && self.value.is_empty()) {
+                self.validate_selection_constraint(&variable, conn)?;
+                self.validate_constraints(&variable)?;
+            }
+
+            is_secret = is_secret || variable.secret;
+        }
 
-        let secret = ENCRYPTION_SECRET.as_str();
         let values = (
             key.eq(&self.key),
-            value.eq(pgp_sym_encrypt(&self.value, secret)),
+            value.eq(encrypt(self.value)),
             job_id.eq(job.id),
+            secret.eq(is_secret),
+            key_id.eq(ENCRYPTION_KEYS.active_id()),
         );
 
         diesel::insert_into(job_variables)
@@ -67,25 +99,24 @@ impl<'a> NewJobVariable<'a> {
             .map_err(Into::into)
     }
 
-    /// If the variable has a job, and that job has a task, check if the task
-    /// has any selection constraint set, and if so, check that the variable
-    /// value matches that constraint.
+    /// If the job has a task, return the variable of that task matching the
+    /// key of this variable, if any.
+    fn task_variable(&self, conn: &PgConnection, job: &Job) -> QueryResult<Option<Variable>> {
+        match job.task(conn)? {
+            None => Ok(None),
+            Some(task) => task.variable_with_key(self.key, conn),
+        }
+    }
+
+    /// Check if the task variable is limited to a set of values, either by a
+    /// selection or an options constraint, and if so, check that the
+    /// variable value is one of those values.
     fn validate_selection_constraint(
         &self,
+        variable: &Variable,
         conn: &PgConnection,
-        job: &Job,
     ) -> Result<(), Box<dyn Error>> {
-        let task = match job.task(conn)? {
-            None => return Ok(()),
-            Some(task) => task,
-        };
-
-        let variable = match task.variable_with_key(self.key, conn)? {
-            None => return Ok(()),
-            Some(variable) => variable,
-        };
-
-        let selection = match variable.selection_constraint {
+        let selection = match variable.options(conn)? {
             None => return Ok(()),
             Some(selection) => selection,
         };
@@ -101,6 +132,14 @@ impl<'a> NewJobVariable<'a> {
         )
         .into())
     }
+
+    /// Check that the variable value matches the kind of the task variable,
+    /// and any other constraints set on it.
+    fn validate_constraints(&self, variable: &Variable) -> Result<(), Box<dyn Error>> {
+        variable
+            .validate_value(self.value)
+            .map_err(|err| format!(r#"variable "{}" {}"#, self.key, err).into())
+    }
 }
 
 pub(crate) mod graphql {
@@ -0,0 +1,433 @@
+//! A [`Schedule`] triggers a [`Task`] on a recurring basis.
+//!
+//! Each schedule has a cron expression, defining when the task should run,
+//! and a set of variable values, used to create a new [`Job`] from the task
+//! every time the schedule is due.
+//!
+//! The worker periodically checks for schedules that are due, creates the
+//! relevant jobs, and moves the schedules forward to their next run.
+//!
+//! [`Job`]: crate::resources::Job
+
+use crate::encryption::decrypt;
+use crate::resources::{Job, NewJob, NewJobVariable, Task};
+use crate::schema::schedules;
+use crate::server::RequestState;
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::str::FromStr;
+
+pub(crate) mod variable;
+
+use variable::{NewScheduleVariable, ScheduleVariable};
+
+/// The model representing a schedule stored in the database.
+#[derive(Clone, Debug, Deserialize, Serialize, Associations, Identifiable, Queryable)]
+#[belongs_to(Task)]
+#[table_name = "schedules"]
+pub(crate) struct Schedule {
+    pub(crate) id: i32,
+    pub(crate) expression: String,
+    pub(crate) next_run_at: NaiveDateTime,
+    pub(crate) task_id: i32,
+}
+
+impl Schedule {
+    /// Find the next schedule that is due to run, and lock it, so that other
+    /// workers skip the schedule until the current transaction ends.
+    pub(crate) fn find_next_unlocked_due(conn: &PgConnection) -> QueryResult<Option<Self>> {
+        use diesel::dsl::now;
+
+        schedules::table
+            .filter(schedules::next_run_at.le(now))
+            .order(schedules::next_run_at)
+            .for_update()
+            .skip_locked()
+            .first(conn)
+            .optional()
+    }
+
+    pub(crate) fn task(&self, conn: &PgConnection) -> QueryResult<Task> {
+        use crate::schema::tasks::dsl::*;
+
+        tasks.filter(id.eq(self.task_id)).first(conn)
+    }
+
+    pub(crate) fn variables(&self, conn: &PgConnection) -> QueryResult<Vec<ScheduleVariable>> {
+        use crate::schema::schedule_variables::dsl::*;
+
+        ScheduleVariable::belonging_to(self)
+            .select((id, key, decrypt(value, key_id), schedule_id))
+            .order(id.asc())
+            .load(conn)
+    }
+
+    /// Move the schedule forward to its next run, and create a new job from
+    /// the scheduled task.
+    ///
+    /// The next run is calculated from the current time, meaning that if a
+    /// schedule was due multiple times since its last run (for example,
+    /// because no worker was running), only a single job is created.
+    ///
+    /// The schedule is moved forward before the job is created, and the job
+    /// is created in its own savepoint. If the job can't be created (for
+    /// example, because the task variables changed since the schedule was
+    /// created, or a value can't be decrypted), only the job is rolled back.
+    /// This prevents a broken schedule from being retried indefinitely, and
+    /// keeps the surrounding transaction usable for other schedules.
+    ///
+    /// If the cron expression has no upcoming runs left (for example, because
+    /// it is limited to a year in the past), the schedule is removed after
+    /// trying to create its final job.
+    pub(crate) fn enqueue(&mut self, conn: &PgConnection) -> Result<Job, Box<dyn Error>> {
+        let next = next_run_at(&self.expression).ok();
+        if let Some(next) = next {
+            self.next_run_at = next;
+            let _ = diesel::update(&*self)
+                .set(schedules::next_run_at.eq(next))
+                .execute(conn)?;
+        }
+
+        let job = conn.transaction::<_, Box<dyn Error>, _>(|| {
+            let task = self.task(conn)?;
+            let variables = self.variables(conn)?;
+            let variables = variables
+                .iter()
+                .map(|v| NewJobVariable::new(&v.key, &v.value))
+                .collect();
+
+            NewJob::create_from_task(conn, &task, variables, None, None, false)
+        });
+
+        // The schedule variables are removed together with the schedule, so a
+        // finished schedule is only removed once its final job is created.
+        if next.is_none() {
+            let _ = diesel::delete(&*self).execute(conn)?;
+        }
+
+        job
+    }
+}
+
+/// Contains all the details needed to store a schedule in the database.
+///
+/// Use [`NewSchedule::new`] to initialize this struct.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct NewSchedule<'a> {
+    expression: &'a str,
+    variables: Vec<NewScheduleVariable<'a>>,
+}
+
+impl<'a> NewSchedule<'a> {
+    /// Initialize a `NewSchedule` struct, which can be inserted into the
+    /// database using the [`NewSchedule#create`] method.
+    pub(crate) fn new(expression: &'a str) -> Self {
+        Self {
+            expression,
+            variables: vec![],
+        }
+    }
+
+    /// Attach variable values to this schedule.
+    ///
+    /// `NewSchedule` takes ownership of the variables, but you are required to
+    /// call [`NewSchedule#create`] to persist the schedule and its variables.
+    ///
+    /// Can be called multiple times to append more variables.
+    pub(crate) fn with_variables(&mut self, mut variables: Vec<NewScheduleVariable<'a>>) {
+        self.variables.append(&mut variables)
+    }
+
+    /// Persist the schedule and its variables into the database, attached to
+    /// the provided task.
+    ///
+    /// An error is returned if the cron expression is invalid, or if any of
+    /// the task variables are missing a value.
+    pub(crate) fn create(
+        self,
+        conn: &PgConnection,
+        task: &Task,
+    ) -> Result<Schedule, Box<dyn Error>> {
+        use crate::schema::schedules::dsl::*;
+
+        let next = self.validate(task, conn)?;
+
+        conn.transaction(|| {
+            let values = (
+                expression.eq(&self.expression),
+                next_run_at.eq(next),
+                task_id.eq(task.id),
+            );
+
+            let schedule: Schedule = diesel::insert_into(schedules)
+                .values(&values)
+                .get_result(conn)?;
+
+            self.variables
+                .into_iter()
+                .try_for_each(|v| v.add_to_schedule(conn, &schedule))?;
+
+            Ok(schedule)
+        })
+    }
+
+    /// Replace the cron expression and variables of an existing schedule.
+    ///
+    /// The next run of the schedule is recalculated based on the new
+    /// expression.
+    pub(crate) fn update(
+        self,
+        conn: &PgConnection,
+        schedule: &Schedule,
+    ) -> Result<Schedule, Box<dyn Error>> {
+        use crate::schema::schedule_variables;
+
+        let task = schedule.task(conn)?;
+        let next = self.validate(&task, conn)?;
+
+        conn.transaction(|| {
+            let schedule: Schedule = diesel::update(schedule)
+                .set((
+                    schedules::expression.eq(&self.expression),
+                    schedules::next_run_at.eq(next),
+                ))
+                .get_result(conn)?;
+
+            let variables =
+                schedule_variables::table.filter(schedule_variables::schedule_id.eq(schedule.id));
+            let _ = diesel::delete(variables).execute(conn)?;
+
+            self.variables
+                .into_iter()
+                .try_for_each(|v| v.add_to_schedule(conn, &schedule))?;
+
+            Ok(schedule)
+        })
+    }
+
+    /// Validate the schedule for the given task, and return the next moment
+    /// the schedule is due.
+    fn validate(&self, task: &Task, conn: &PgConnection) -> Result<NaiveDateTime, Box<dyn Error>> {
+        let next = next_run_at(self.expression)?;
+
+        let missing = task
+            .variables(conn)?
+            .into_iter()
+            .filter(|variable| !self.variables.iter().any(|v| v.key() == variable.key))
+            .map(|variable| variable.key)
+            .collect::<Vec<_>>();
+
+        if missing.is_empty() {
+            return Ok(next);
+        }
+
+        Err(format!("missing variable values: {}", missing.join(", ")).into())
+    }
+}
+
+/// Calculate the next moment (in UTC) at which the provided cron expression is
+/// due.
+///
+/// The expression uses the format `sec min hour day-of-month month
+/// day-of-week [year]`.
+fn next_run_at(expression: &str) -> Result<NaiveDateTime, Box<dyn Error>> {
+    let schedule = cron::Schedule::from_str(expression)
+        .map_err(|err| format!("invalid cron expression: {}", err))?;
+
+    schedule
+        .upcoming(Utc)
+        .next()
+        .map(|time| time.naive_utc())
+        .ok_or_else(|| "cron expression has no upcoming runs".into())
+}
+
+pub(crate) mod graphql {
+    //! All GraphQL related functionality is encapsulated in this module. The
+    //! relevant functions and structs are re-exported through
+    //! [`crate::graphql`].
+    //!
+    //! API documentation in this module is also used in the GraphQL API itself
+    //! as documentation for the clients.
+    //!
+    //! You can browse to `/graphql/playground` to see all relevant query,
+    //! mutation, and type documentation.
+
+    use super::*;
+    use crate::resources::JobVariableInput;
+    use chrono::DateTime;
+    use juniper::{object, FieldResult, GraphQLInputObject, ID};
+
+    /// Contains all the data needed to create a new `Schedule`.
+    #[derive(Clone, Debug, Deserialize, Serialize, GraphQLInputObject)]
+    pub(crate) struct CreateScheduleInput {
+        /// The `id` of the task for which to create jobs.
+        pub(crate) task_id: ID,
+
+        /// The cron expression defining when the task should run.
+        ///
+        /// The expression uses the format `sec min hour day-of-month month
+        /// day-of-week [year]`, all times are in UTC.
+        ///
+        /// For example, `0 30 2 * * *` runs the task every night at 02:30.
+        pub(crate) expression: String,
+
+        /// The variable values used to create a job from the task.
+        ///
+        /// A value has to be provided for all variables of the task.
+        pub(crate) variables: Vec<JobVariableInput>,
+    }
+
+    /// Contains all the data needed to update an existing `Schedule`.
+    #[derive(Clone, Debug, Deserialize, Serialize, GraphQLInputObject)]
+    pub(crate) struct UpdateScheduleInput {
+        /// The `id` of the schedule to update.
+        pub(crate) id: ID,
+
+        /// The new cron expression of the schedule.
+        ///
+        /// See `CreateScheduleInput` for more details on the expression
+        /// format.
+        pub(crate) expression: String,
+
+        /// The new set of variable values of the schedule.
+        ///
+        /// Any variable values existing before, but missing in this update
+        /// will be removed.
+        pub(crate) variables: Vec<JobVariableInput>,
+    }
+
+    #[object(Context = RequestState)]
+    impl Schedule {
+        /// The unique identifier for a specific schedule.
+        fn id() -> ID {
+            ID::new(self.id.to_string())
+        }
+
+        /// The cron expression defining when the task runs.
+        fn expression() -> &str {
+            self.expression.as_ref()
+        }
+
+        /// The next moment at which the schedule creates a new job.
+        fn next_run_at() -> DateTime<Utc> {
+            DateTime::from_utc(self.next_run_at, Utc)
+        }
+
+        /// The variable values used to create a new job.
+        ///
+        /// This field can return `null`, but _only_ if a database error
+        /// prevents the data from being retrieved.
+        ///
+        /// If a `null` value is returned, it is up to the client to decide the
+        /// best course of action. The following actions are advised, sorted by
+        /// preference:
+        ///
+        /// 1. continue execution if the information is not critical to success,
+        /// 2. retry the request to try and get the relevant information,
+        /// 3. disable parts of the application reliant on the information,
+        /// 4. show a global error, and ask the user to retry.
+        fn variables(context: &RequestState) -> FieldResult<Option<Vec<ScheduleVariable>>> {
+            self.variables(&context.conn).map(Some).map_err(Into::into)
+        }
+
+        /// The task for which the schedule creates jobs.
+        ///
+        /// This field can return `null`, but _only_ if a database error
+        /// prevents the data from being retrieved.
+        ///
+        /// If a `null` value is returned, it is up to the client to decide the
+        /// best course of action. The following actions are advised, sorted by
+        /// preference:
+        ///
+        /// 1. continue execution if the information is not critical to success,
+        /// 2. retry the request to try and get the relevant information,
+        /// 3. disable parts of the application reliant on the information,
+        /// 4. show a global error, and ask the user to retry.
+        fn task(context: &RequestState) -> FieldResult<Option<Task>> {
+            self.task(&context.conn).map(Some).map_err(Into::into)
+        }
+    }
+}
+
+impl<'a> From<&'a graphql::CreateScheduleInput> for NewSchedule<'a> {
+    fn from(input: &'a graphql::CreateScheduleInput) -> Self {
+        let mut schedule = Self::new(&input.expression);
+        schedule.with_variables(input.variables.iter().map(Into::into).collect());
+        schedule
+    }
+}
+
+impl<'a> From<&'a graphql::UpdateScheduleInput> for NewSchedule<'a> {
+    fn from(input: &'a graphql::UpdateScheduleInput) -> Self {
+        let mut schedule = Self::new(&input.expression);
+        schedule.with_variables(input.variables.iter().map(Into::into).collect());
+        schedule
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resources::NewVariable;
+    use crate::test_support::{connection, create_task};
+    use chrono::Duration;
+    use diesel::result::Error as DieselError;
+
+    #[test]
+    fn test_next_run_at() {
+        let next = next_run_at("0 0 * * * *").unwrap();
+        let now = Utc::now().naive_utc();
+
+        assert!(next > now);
+        assert!(next <= now + Duration::hours(1));
+    }
+
+    #[test]
+    fn test_next_run_at_invalid() {
+        assert!(next_run_at("every hour").is_err());
+        assert!(next_run_at("0 0 0 1 1 * 2000").is_err());
+    }
+
+    #[test]
+    fn test_enqueue_advances_on_failure() {
+        let conn = connection();
+
+        conn.test_transaction::<_, DieselError, _>(|| {
+            let task = create_task(&conn, "schedule enqueue failure", &["name"], &[]);
+
+            let mut schedule = NewSchedule::new("0 0 * * * *");
+            schedule.with_variables(vec![NewScheduleVariable::new("name", "world")]);
+            let schedule = schedule.create(&conn, &task).unwrap();
+
+            // The task gains a required variable the schedule has no value for,
+            // so no job can be created for the schedule anymore.
+            NewVariable::new("greeting", None, None, None, None)
+                .unwrap()
+                .create_or_update(&conn, &task)
+                .unwrap();
+
+            let past = Utc::now().naive_utc() - Duration::minutes(1);
+            let mut schedule: Schedule = diesel::update(&schedule)
+                .set(schedules::next_run_at.eq(past))
+                .get_result(&conn)?;
+
+            assert!(schedule.enqueue(&conn).is_err());
+
+            let schedule: Schedule = schedules::table.find(schedule.id).first(&conn)?;
+            assert!(schedule.next_run_at > Utc::now().naive_utc());
+            assert!(Schedule::find_next_unlocked_due(&conn)?
+                .filter(|s| s.id == schedule.id)
+                .is_none());
+
+            let jobs: i64 = crate::schema::jobs::table
+                .filter(crate::schema::jobs::task_reference.eq(task.id))
+                .count()
+                .get_result(&conn)?;
+            assert_eq!(jobs, 0);
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,104 @@
+use crate::encryption::encrypt;
+use crate::resources::{JobVariableInput, Schedule};
+use crate::schema::schedule_variables;
+use crate::ENCRYPTION_KEYS;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+
+/// The model representing a schedule variable definition (_with_ an actual
+/// value) stored in the database.
+#[derive(Clone, Debug, Deserialize, Serialize, Associations, Identifiable, Queryable)]
+#[belongs_to(Schedule)]
+#[table_name = "schedule_variables"]
+pub(crate) struct ScheduleVariable {
+    pub(crate) id: i32,
+    pub(crate) key: String,
+    pub(crate) value: String,
+    pub(crate) schedule_id: i32,
+}
+
+/// Contains all the details needed to store a schedule variable in the
+/// database.
+///
+/// Use [`NewScheduleVariable::new`] to initialize this struct.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct NewScheduleVariable<'a> {
+    key: &'a str,
+    value: &'a str,
+}
+
+impl<'a> NewScheduleVariable<'a> {
+    /// Initialize a `NewScheduleVariable` struct, which can be inserted into
+    /// the database using the [`NewScheduleVariable#add_to_schedule`] method.
+    pub(crate) const fn new(key: &'a str, value: &'a str) -> Self {
+        Self { key, value }
+    }
+
+    pub(crate) const fn key(&self) -> &str {
+        self.key
+    }
+
+    /// Add a variable to a [`Schedule`], by storing it in the database as an
+    /// association.
+    ///
+    /// The variable value is stored encrypted, similar to job variables.
+    ///
+    /// This method can return an error if the database insert failed.
+    pub(crate) fn add_to_schedule(
+        self,
+        conn: &PgConnection,
+        schedule: &Schedule,
+    ) -> Result<(), Box<dyn Error>> {
+        use crate::schema::schedule_variables::dsl::*;
+
+        let values = (
+            key.eq(&self.key),
+            value.eq(encrypt(self.value)),
+            schedule_id.eq(schedule.id),
+            key_id.eq(ENCRYPTION_KEYS.active_id()),
+        );
+
+        diesel::insert_into(schedule_variables)
+            .values(values)
+            .execute(conn)
+            .map(|_| ())
+            .map_err(Into::into)
+    }
+}
+
+pub(crate) mod graphql {
+    //! All GraphQL related functionality is encapsulated in this module. The
+    //! relevant functions and structs are re-exported through
+    //! [`crate::graphql`].
+    //!
+    //! API documentation in this module is also used in the GraphQL API itself
+    //! as documentation for the clients.
+    //!
+    //! You can browse to `/graphql/playground` to see all relevant query,
+    //! mutation, and type documentation.
+
+    use super::*;
+    use crate::server::RequestState;
+    use juniper::object;
+
+    #[object(Context = RequestState)]
+    impl ScheduleVariable {
+        /// The key of the task variable for which the value is provided.
+        fn key() -> &str {
+            self.key.as_ref()
+        }
+
+        /// The value used for the variable when the schedule creates a new
+        /// job.
+        fn value() -> &str {
+            self.value.as_ref()
+        }
+    }
+}
+
+impl<'a> From<&'a JobVariableInput> for NewScheduleVariable<'a> {
+    fn from(input: &'a JobVariableInput) -> Self {
+        Self::new(&input.key, &input.value)
+    }
+}
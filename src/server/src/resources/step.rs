@@ -5,14 +5,46 @@
 //!
 //! [`Processor`]: crate::Processor
 
-use crate::resources::Task;
+use crate::resources::{RetryPolicy, Task};
 use crate::schema::{steps, variable_advertisements};
 use crate::{server::RequestState, Processor};
 use diesel::prelude::*;
+use juniper::GraphQLEnum;
 use serde::{Deserialize, Serialize};
 use std::convert::{AsRef, TryFrom, TryInto};
 use std::error::Error;
 
+pub(crate) mod condition;
+pub(crate) mod dependencies;
+pub(crate) mod for_each;
+pub(crate) mod retry_policy;
+
+/// Defines when a step runs, depending on the outcome of the other steps.
+///
+/// Steps that run on failure, or always, are cleanup steps. They run one by
+/// one, in the order of their position, after all other steps finished (or
+/// once any of them failed).
+#[derive(Clone, Copy, Debug, PartialEq, DbEnum, GraphQLEnum, Serialize, Deserialize)]
+#[PgType = "StepRunWhen"]
+#[graphql(name = "StepRunWhen")]
+pub(crate) enum RunWhen {
+    /// The step runs as long as no other step failed.
+    OnSuccess,
+
+    /// The step only runs once another step failed.
+    OnFailure,
+
+    /// The step runs regardless of the outcome of the other steps.
+    Always,
+}
+
+impl RunWhen {
+    /// Returns `true` if steps with this value are cleanup steps.
+    pub(crate) fn is_cleanup(self) -> bool {
+        self != Self::OnSuccess
+    }
+}
+
 /// The model representing a step stored in the database.
 #[derive(Clone, Debug, Deserialize, Serialize, Associations, Identifiable, Queryable)]
 #[belongs_to(Task)]
@@ -24,6 +56,21 @@ pub(crate) struct Step {
     pub(crate) processor: serde_json::Value,
     pub(crate) position: i32,
     pub(crate) task_id: i32,
+    pub(crate) timeout_seconds: Option<i32>,
+    pub(crate) retry_policy: Option<serde_json::Value>,
+
+    /// The names of the steps that have to finish before this step runs. If
+    /// `None`, the step depends on the step preceding it.
+    pub(crate) depends_on: Option<Vec<String>>,
+
+    /// A Tera expression that has to evaluate to `true` for the step to run.
+    /// If `None`, the step always runs.
+    pub(crate) condition: Option<String>,
+    pub(crate) run_when: RunWhen,
+
+    /// A Tera template that renders to the list of items the step runs for.
+    /// If `None`, the step runs once.
+    pub(crate) for_each: Option<String>,
 }
 
 impl Step {
@@ -31,6 +78,13 @@ impl Step {
         serde_json::from_value(self.processor.clone())
     }
 
+    pub(crate) fn retry_policy(&self) -> Result<Option<RetryPolicy>, serde_json::Error> {
+        self.retry_policy
+            .clone()
+            .map(serde_json::from_value)
+            .transpose()
+    }
+
     pub(crate) fn task(&self, conn: &PgConnection) -> QueryResult<Task> {
         use crate::schema::tasks::dsl::*;
 
@@ -49,6 +103,12 @@ pub(crate) struct NewStep<'a> {
     position: i32,
     advertised_variable_key: Option<&'a str>,
     task_id: Option<i32>,
+    timeout_seconds: Option<i32>,
+    retry_policy: Option<RetryPolicy>,
+    depends_on: Option<Vec<&'a str>>,
+    condition: Option<&'a str>,
+    run_when: RunWhen,
+    for_each: Option<&'a str>,
 }
 
 impl<'a> NewStep<'a> {
@@ -68,9 +128,79 @@ impl<'a> NewStep<'a> {
             position,
             advertised_variable_key,
             task_id: None,
+            timeout_seconds: None,
+            retry_policy: None,
+            depends_on: None,
+            condition: None,
+            run_when: RunWhen::OnSuccess,
+            for_each: None,
         }
     }
 
+    /// Returns the processor used by the step.
+    pub(crate) const fn processor(&self) -> &Processor {
+        &self.processor
+    }
+
+    /// Returns when the step runs, depending on the outcome of the other
+    /// steps.
+    pub(crate) const fn run_when(&self) -> RunWhen {
+        self.run_when
+    }
+
+    /// Define when the step runs, depending on the outcome of the other
+    /// steps.
+    pub(crate) fn with_run_when(&mut self, run_when: RunWhen) {
+        self.run_when = run_when
+    }
+
+    /// Returns the names of the steps this step depends on, if declared.
+    pub(crate) fn depends_on(&self) -> Option<&[&'a str]> {
+        self.depends_on.as_ref().map(Vec::as_slice)
+    }
+
+    /// Run the step once all steps with the provided names finished running,
+    /// instead of after the step preceding it.
+    ///
+    /// An empty list means the step doesn't depend on any other step, and
+    /// runs as soon as the job starts.
+    pub(crate) fn with_depends_on(&mut self, depends_on: Vec<&'a str>) {
+        self.depends_on = Some(depends_on)
+    }
+
+    /// Only run the step if the provided condition evaluates to `true`,
+    /// otherwise the step is skipped.
+    ///
+    /// Returns an error if the condition is not a valid Tera expression.
+    pub(crate) fn with_condition(&mut self, condition: &'a str) -> Result<(), String> {
+        condition::validate(condition)?;
+
+        self.condition = Some(condition);
+        Ok(())
+    }
+
+    /// Run the step once for each item in the list the provided template
+    /// renders to.
+    ///
+    /// Returns an error if the template is not a valid Tera template.
+    pub(crate) fn with_for_each(&mut self, template: &'a str) -> Result<(), String> {
+        for_each::validate(template)?;
+
+        self.for_each = Some(template);
+        Ok(())
+    }
+
+    /// Retry the step when it fails, according to the provided policy.
+    pub(crate) fn with_retry_policy(&mut self, retry_policy: RetryPolicy) {
+        self.retry_policy = Some(retry_policy)
+    }
+
+    /// Limit the time the step is allowed to run, before it is considered to
+    /// have failed.
+    pub(crate) fn with_timeout_seconds(&mut self, timeout_seconds: i32) {
+        self.timeout_seconds = Some(timeout_seconds)
+    }
+
     /// Add a step to a [`Task`], by storing it in the database as an
     /// association.
     ///
@@ -99,18 +229,27 @@ impl<'a> NewStep<'a> {
             steps::processor.eq(serde_json::to_value(self.processor)?),
             steps::position.eq(&self.position),
             steps::task_id.eq(self.task_id.unwrap_or(task.id)),
+            steps::timeout_seconds.eq(self.timeout_seconds),
+            steps::retry_policy.eq(self.retry_policy.map(serde_json::to_value).transpose()?),
+            steps::depends_on.eq(self.depends_on),
+            steps::condition.eq(self.condition),
+            steps::for_each.eq(self.for_each),
         );
 
+        // The `run_when` value can't be cloned, as its SQL type can't, so it
+        // is added separately to the insert and the update.
+        let run_when = self.run_when;
+
         let advertised_key = &self.advertised_variable_key;
 
         conn.transaction(move || {
             let _ = conn.execute("SET CONSTRAINTS ALL DEFERRED")?;
 
             let step: Step = diesel::insert_into(steps::table)
-                .values(&values)
+                .values((&values, steps::run_when.eq(run_when)))
                 .on_conflict((steps::name, steps::task_id))
                 .do_update()
-                .set(values.clone())
+                .set((values.clone(), steps::run_when.eq(run_when)))
                 .get_result(conn)
                 .map_err(Into::<Box<dyn Error>>::into)?;
 
@@ -139,6 +278,7 @@ pub(crate) mod graphql {
     //! mutation, and type documentation.
 
     use super::*;
+    use crate::resources::RetryPolicyInput;
     use crate::ProcessorInput;
     use juniper::{object, FieldResult, GraphQLInputObject, ID};
 
@@ -185,6 +325,78 @@ pub(crate) mod graphql {
         /// its input, can use the task this step belongs to to fetch that
         /// value.
         pub(crate) advertised_variable_key: Option<String>,
+
+        /// An optional number of seconds the step is allowed to run.
+        ///
+        /// If the step runs longer than the provided timeout, it is stopped,
+        /// and the step (and the job it belongs to) fails.
+        ///
+        /// Note that the task itself can also have a timeout, in which case
+        /// the step is stopped as soon as either timeout passes.
+        pub(crate) timeout_seconds: Option<i32>,
+
+        /// An optional policy defining if, and how, the step is retried when
+        /// it fails.
+        ///
+        /// Without a retry policy, a failing step immediately results in the
+        /// job failing.
+        pub(crate) retry_policy: Option<RetryPolicyInput>,
+
+        /// An optional list of names of other steps in the same task, that
+        /// have to finish successfully before this step runs.
+        ///
+        /// By default, a step runs after the step preceding it. If this
+        /// value is provided, the step runs as soon as all listed steps are
+        /// finished instead, in parallel with any other step that is ready to
+        /// run. An empty list means the step runs as soon as the job starts.
+        ///
+        /// The output of a step is only guaranteed to be available to the
+        /// templates of steps that (indirectly) depend on it.
+        ///
+        /// Providing an unknown step name, or circular dependencies, results
+        /// in an error.
+        pub(crate) depends_on: Option<Vec<String>>,
+
+        /// An optional condition that has to hold for the step to run.
+        ///
+        /// The condition is a Tera expression, such as
+        /// `var.environment == "production"`. It can use the same data as the
+        /// processor templates of the step, including the `output` of steps
+        /// that already ran.
+        ///
+        /// The condition is evaluated right before the step would run. If it
+        /// does not hold, the step is skipped, and any steps depending on it
+        /// run as if the step succeeded.
+        pub(crate) condition: Option<String>,
+
+        /// Define when the step runs, depending on the outcome of the other
+        /// steps in the task (defaults to `ON_SUCCESS`).
+        ///
+        /// Steps that run `ON_FAILURE` or `ALWAYS` are cleanup steps, which
+        /// can be used to roll back changes, or to send a notification. They
+        /// run one by one, after all other steps finished, or once any of
+        /// them failed. Cleanup steps cannot declare dependencies.
+        ///
+        /// Templates of cleanup steps can use `failure.step` and
+        /// `failure.error` to get the name and error of the step that failed,
+        /// if any.
+        pub(crate) run_when: Option<RunWhen>,
+
+        /// An optional Tera template to run the step once for each item in a
+        /// list.
+        ///
+        /// The template can use the same data as the processor templates of
+        /// the step, for example `{{ var.hosts }}`. If the rendered value is a
+        /// JSON array, the step runs for each element of the array, otherwise
+        /// the value is split on commas.
+        ///
+        /// Each run is a child of the job step, and can use the current item
+        /// in its processor templates as `item`. The output of the step is a
+        /// JSON array containing the output of each run. Output of a run that
+        /// is valid JSON is embedded as-is.
+        ///
+        /// The step fails as soon as any of its runs fails.
+        pub(crate) for_each: Option<String>,
     }
 
     #[object(Context = RequestState)]
@@ -222,6 +434,49 @@ pub(crate) mod graphql {
             self.position
         }
 
+        /// The number of seconds the step is allowed to run, if any.
+        ///
+        /// Shell command and HTTP request processors are stopped once the time
+        /// runs out. Other processors fail the step after they finish.
+        fn timeout_seconds() -> Option<i32> {
+            self.timeout_seconds
+        }
+
+        /// The policy defining if, and how, the step is retried when it fails.
+        ///
+        /// This query can fail, if the retry policy failed to be deserialized.
+        fn retry_policy() -> FieldResult<Option<RetryPolicy>> {
+            self.retry_policy().map_err(Into::into)
+        }
+
+        /// The names of the steps that have to finish successfully before this
+        /// step runs.
+        ///
+        /// If this value is `null`, the step runs after the step preceding
+        /// it.
+        fn depends_on() -> Option<Vec<&str>> {
+            self.depends_on
+                .as_ref()
+                .map(|names| names.iter().map(String::as_str).collect())
+        }
+
+        /// The condition that has to hold for the step to run, if any.
+        fn condition() -> Option<&str> {
+            self.condition.as_ref().map(String::as_ref)
+        }
+
+        /// Defines when the step runs, depending on the outcome of the other
+        /// steps in the task.
+        fn run_when() -> RunWhen {
+            self.run_when
+        }
+
+        /// The template rendering the list of items the step runs for, if
+        /// any.
+        fn for_each() -> Option<&str> {
+            self.for_each.as_ref().map(String::as_ref)
+        }
+
         /// The task to which the step belongs.
         ///
         /// This field can return `null`, but _only_ if a database error
@@ -250,12 +505,42 @@ impl<'a> TryFrom<(usize, &'a graphql::CreateStepInput)> for NewStep<'a> {
     type Error = String;
 
     fn try_from((index, input): (usize, &'a graphql::CreateStepInput)) -> Result<Self, String> {
-        Ok(Self::new(
+        let mut step = Self::new(
             &input.name,
             input.description.as_ref().map(String::as_str),
             input.processor.clone().try_into()?,
             index as i32,
             input.advertised_variable_key.as_ref().map(String::as_str),
-        ))
+        );
+
+        if let Some(timeout) = input.timeout_seconds {
+            if timeout <= 0 {
+                return Err("Step timeout must be a positive number of seconds.".to_owned());
+            }
+
+            step.with_timeout_seconds(timeout);
+        }
+
+        if let Some(policy) = &input.retry_policy {
+            step.with_retry_policy(RetryPolicy::try_from(policy)?);
+        }
+
+        if let Some(condition) = &input.condition {
+            step.with_condition(condition)?;
+        }
+
+        if let Some(template) = &input.for_each {
+            step.with_for_each(template)?;
+        }
+
+        if let Some(run_when) = input.run_when {
+            step.with_run_when(run_when);
+        }
+
+        if let Some(depends_on) = &input.depends_on {
+            step.with_depends_on(depends_on.iter().map(String::as_str).collect());
+        }
+
+        Ok(step)
     }
 }
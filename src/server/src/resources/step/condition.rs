@@ -0,0 +1,94 @@
+//! A condition defines whether a [`Step`] runs as part of a job.
+//!
+//! A condition is a Tera expression, such as `var.environment == "production"`
+//! or `output["Fetch Status"] == "ok"`. It is evaluated against
+//! the same data that is available to the processor templates of the step,
+//! right before the step runs. If the expression evaluates to `false`, the
+//! step is skipped.
+//!
+//! [`Step`]: crate::resources::Step
+
+use tera::Tera;
+
+/// The name used to register a condition template.
+const TEMPLATE_NAME: &str = "step condition";
+
+/// Returns a template that renders to `true` if the condition holds, or an
+/// empty string if it doesn't.
+pub(crate) fn template(condition: &str) -> String {
+    format!("{{% if {} %}}true{{% endif %}}", condition)
+}
+
+/// Returns `true` if the rendered template of a condition means the condition
+/// holds.
+pub(crate) fn holds(rendered: &str) -> bool {
+    rendered == "true"
+}
+
+/// Validate that the condition is a valid Tera expression.
+///
+/// This only checks the syntax of the expression, any variables or outputs
+/// used in the expression are only known once the step runs.
+pub(crate) fn validate(condition: &str) -> Result<(), String> {
+    if condition.trim().is_empty() {
+        return Err("step condition cannot be empty".to_owned());
+    }
+
+    Tera::default()
+        .add_raw_template(TEMPLATE_NAME, &template(condition))
+        .map_err(|err| format!("invalid step condition: {}", condition_error(&err)))
+}
+
+/// Returns the most specific description of a template error.
+fn condition_error(err: &tera::Error) -> String {
+    use std::error::Error;
+
+    match err.source() {
+        Some(source) => source.to_string(),
+        None => err.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use tera::Context;
+
+    fn evaluate(condition: &str) -> bool {
+        let mut var = HashMap::new();
+        let _ = var.insert("environment", "production");
+
+        let mut context = Context::new();
+        context.insert("var", &var);
+
+        let mut tera = Tera::default();
+        tera.add_raw_template(TEMPLATE_NAME, &template(condition))
+            .unwrap();
+
+        holds(&tera.render(TEMPLATE_NAME, context).unwrap())
+    }
+
+    #[test]
+    fn test_condition_holds() {
+        assert!(evaluate(r#"var.environment == "production""#));
+        assert!(evaluate("var.environment is defined"));
+    }
+
+    #[test]
+    fn test_condition_does_not_hold() {
+        assert!(!evaluate(r#"var.environment == "staging""#));
+        assert!(!evaluate("var.region is defined"));
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(r#"var.environment == "production""#).is_ok());
+    }
+
+    #[test]
+    fn test_validate_invalid() {
+        assert!(validate("").is_err());
+        assert!(validate("var.environment ==").is_err());
+    }
+}
@@ -0,0 +1,170 @@
+//! The dependencies of a [`Step`] define which other steps of the same task
+//! have to finish, before the step can run.
+//!
+//! By default, a step depends on the step preceding it, so that steps run one
+//! by one, in the order of their position. A step can instead declare the
+//! names of the steps it depends on, in which case it runs as soon as all of
+//! those steps finished successfully. Steps that don't depend on each other
+//! run in parallel.
+//!
+//! The output of a step is available to the templates of other steps by the
+//! name of the step, so a step should depend on any step whose output it uses.
+//!
+//! Cleanup steps, which run on failure or always, are not part of the graph.
+//! They run after all other steps finished.
+//!
+//! [`Step`]: crate::resources::Step
+
+/// The dependencies between the steps of a task or job.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Graph {
+    /// For each step, the indices of the steps it depends on.
+    dependencies: Vec<Vec<usize>>,
+}
+
+impl Graph {
+    /// Build the graph from the names and (optional) declared dependencies of
+    /// a list of steps, ordered by their position.
+    ///
+    /// Returns an error if a step depends on an unknown step, on itself, or if
+    /// the dependencies contain a cycle.
+    pub(crate) fn new<S: AsRef<str>>(steps: &[(&str, Option<&[S]>)]) -> Result<Self, String> {
+        let index = |name: &str| steps.iter().position(|(n, _)| *n == name);
+
+        let dependencies = steps
+            .iter()
+            .enumerate()
+            .map(|(i, (name, depends_on))| match depends_on {
+                None => Ok(i.checked_sub(1).into_iter().collect()),
+                Some(names) => names
+                    .iter()
+                    .map(AsRef::as_ref)
+                    .map(|dependency| match index(dependency) {
+                        Some(j) if i == j => Err(format!("step {} depends on itself", name)),
+                        Some(j) => Ok(j),
+                        None => Err(format!(
+                            "step {} depends on unknown step: {}",
+                            name, dependency
+                        )),
+                    })
+                    .collect(),
+            })
+            .collect::<Result<Vec<Vec<_>>, _>>()?;
+
+        let graph = Self { dependencies };
+
+        match graph.cycle().and_then(|i| steps.get(i)) {
+            Some((name, _)) => Err(format!("step {} has circular dependencies", name)),
+            None => Ok(graph),
+        }
+    }
+
+    /// Returns the indices of all steps that haven't started yet, and of which
+    /// all dependencies finished successfully.
+    ///
+    /// Both slices are indexed by step, in the same order as the steps used
+    /// to build the graph.
+    pub(crate) fn ready(&self, started: &[bool], succeeded: &[bool]) -> Vec<usize> {
+        let is = |flags: &[bool], i: usize| flags.get(i).cloned().unwrap_or(false);
+
+        self.dependencies
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !is(started, *i))
+            .filter(|(_, dependencies)| dependencies.iter().all(|j| is(succeeded, *j)))
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Returns the index of a step that (indirectly) depends on itself, if
+    /// any.
+    ///
+    /// Steps are resolved one by one, once all their dependencies are
+    /// resolved. Any step that can't be resolved is part of, or depends on, a
+    /// cycle.
+    fn cycle(&self) -> Option<usize> {
+        let mut resolved = vec![false; self.dependencies.len()];
+
+        loop {
+            let ready = self.ready(&resolved, &resolved);
+            if ready.is_empty() {
+                return resolved.iter().position(|resolved| !resolved);
+            }
+
+            ready.into_iter().for_each(|i| resolved[i] = true);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(steps: &[(&str, Option<&[&str]>)]) -> Result<Graph, String> {
+        Graph::new(steps)
+    }
+
+    #[test]
+    fn test_sequential_by_default() {
+        let graph = graph(&[("one", None), ("two", None), ("three", None)]).unwrap();
+
+        assert_eq!(graph.dependencies, vec![vec![], vec![0], vec![1]]);
+    }
+
+    #[test]
+    fn test_declared_dependencies() {
+        let graph = graph(&[
+            ("one", None),
+            ("two", Some(&[])),
+            ("three", Some(&["one", "two"])),
+        ])
+        .unwrap();
+
+        assert_eq!(graph.dependencies, vec![vec![], vec![], vec![0, 1]]);
+    }
+
+    #[test]
+    fn test_unknown_dependency() {
+        let err = graph(&[("one", Some(&["zero"]))]).unwrap_err();
+
+        assert_eq!(err, "step one depends on unknown step: zero");
+    }
+
+    #[test]
+    fn test_self_dependency() {
+        let err = graph(&[("one", Some(&["one"]))]).unwrap_err();
+
+        assert_eq!(err, "step one depends on itself");
+    }
+
+    #[test]
+    fn test_circular_dependencies() {
+        let err = graph(&[
+            ("one", Some(&["three"])),
+            ("two", Some(&[])),
+            ("three", Some(&["one"])),
+        ])
+        .unwrap_err();
+
+        assert_eq!(err, "step one has circular dependencies");
+    }
+
+    #[test]
+    fn test_ready() {
+        let graph = graph(&[
+            ("one", Some(&[])),
+            ("two", Some(&[])),
+            ("three", Some(&["one", "two"])),
+        ])
+        .unwrap();
+
+        assert_eq!(graph.ready(&[false; 3], &[false; 3]), vec![0, 1]);
+        assert!(graph
+            .ready(&[true, true, false], &[true, false, false])
+            .is_empty());
+        assert_eq!(
+            graph.ready(&[true, true, false], &[true, true, false]),
+            vec![2]
+        );
+    }
+}
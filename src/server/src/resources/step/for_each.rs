@@ -0,0 +1,91 @@
+//! A "for each" template fans out a [`Step`] over a list of items.
+//!
+//! The template is rendered right before the step runs, using the same data
+//! that is available to the processor templates of the step, such as
+//! `{{ var.hosts }}` or `{{ output["Fetch Users"] }}`. If the rendered value
+//! is a JSON array, each element of the array is an item, otherwise the value
+//! is split into items on commas.
+//!
+//! The step then runs once for each item, with the item available to its
+//! processor templates as `item`. The output of the step is a JSON array
+//! with the output of each run.
+//!
+//! [`Step`]: crate::resources::Step
+
+use serde_json::Value;
+use tera::Tera;
+
+/// The name used to register a "for each" template.
+const TEMPLATE_NAME: &str = "step for each";
+
+/// Returns the items of a rendered "for each" template.
+pub(crate) fn items(rendered: &str) -> Vec<Value> {
+    let rendered = rendered.trim();
+
+    if let Ok(Value::Array(items)) = serde_json::from_str(rendered) {
+        return items;
+    }
+
+    rendered
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| Value::String(item.to_owned()))
+        .collect()
+}
+
+/// Combine the output of each run into a JSON array.
+///
+/// Output that is valid JSON is embedded as-is, any other output is added as
+/// a string.
+pub(crate) fn collect(outputs: Vec<String>) -> String {
+    let outputs = outputs
+        .into_iter()
+        .map(|output| serde_json::from_str(&output).unwrap_or(Value::String(output)))
+        .collect();
+
+    Value::Array(outputs).to_string()
+}
+
+/// Validate that the "for each" template is a valid Tera template.
+pub(crate) fn validate(template: &str) -> Result<(), String> {
+    if template.trim().is_empty() {
+        return Err("step for each template cannot be empty".to_owned());
+    }
+
+    Tera::default()
+        .add_raw_template(TEMPLATE_NAME, template)
+        .map_err(|err| format!("invalid step for each template: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_items_comma_separated() {
+        assert_eq!(items(" one, two ,,three "), vec!["one", "two", "three"]);
+    }
+
+    #[test]
+    fn test_items_json_array() {
+        assert_eq!(
+            items(r#"[{"id": 1}, "two", 3]"#),
+            vec![json!({"id": 1}), json!("two"), json!(3)]
+        );
+    }
+
+    #[test]
+    fn test_items_empty() {
+        assert!(items("").is_empty());
+        assert!(items("[]").is_empty());
+    }
+
+    #[test]
+    fn test_collect() {
+        let outputs = vec!["plain".to_owned(), r#"{"ok":true}"#.to_owned()];
+
+        assert_eq!(collect(outputs), r#"["plain",{"ok":true}]"#);
+    }
+}
@@ -0,0 +1,199 @@
+//! A [`RetryPolicy`] defines if, and how, a failed step is retried.
+//!
+//! By default, a step that fails results in the job failing as well. Some
+//! steps depend on external services that can fail intermittently (such as an
+//! HTTP endpoint, or a Redis server), in which case it makes sense to retry
+//! the step a number of times before giving up.
+//!
+//! The retry policy is stored alongside the step configuration, and copied to
+//! the job step once a job is created from a task.
+
+use juniper::{GraphQLEnum, GraphQLObject};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::time::Duration;
+
+/// The upper bound of the delay between two attempts, regardless of the
+/// backoff strategy.
+const MAX_DELAY: Duration = Duration::from_secs(60 * 60);
+
+/// The strategy used to calculate the delay between two attempts.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize, GraphQLEnum)]
+pub(crate) enum Backoff {
+    /// Wait the same amount of time before each attempt.
+    Fixed,
+
+    /// Double the amount of time to wait after each attempt.
+    Exponential,
+}
+
+/// The policy to apply when a step fails.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, GraphQLObject)]
+pub(crate) struct RetryPolicy {
+    /// The maximum number of times the step runs, including the first
+    /// attempt.
+    pub(crate) max_attempts: i32,
+
+    /// The strategy used to calculate the delay between two attempts.
+    pub(crate) backoff: Backoff,
+
+    /// The number of seconds to wait before the second attempt.
+    ///
+    /// Depending on the backoff strategy, later attempts can wait longer.
+    pub(crate) delay_seconds: i32,
+
+    /// An (optional) list of error kinds that can be retried.
+    ///
+    /// An error kind matches a failed attempt if the error output of the
+    /// attempt contains the error kind, for example `Response error` or
+    /// `Invalid status code: 503`.
+    ///
+    /// If this field is `null`, all errors are retried.
+    pub(crate) error_kinds: Option<Vec<String>>,
+}
+
+impl RetryPolicy {
+    /// Returns `true` if a step that failed with the provided error on the
+    /// given attempt (starting at `1`) should be retried.
+    pub(crate) fn should_retry(&self, attempt: i32, error: &str) -> bool {
+        if attempt >= self.max_attempts {
+            return false;
+        }
+
+        self.error_kinds
+            .as_ref()
+            .map_or(true, |kinds| kinds.iter().any(|kind| error.contains(kind)))
+    }
+
+    /// Returns the time to wait after the given (failed) attempt, before
+    /// starting the next attempt.
+    pub(crate) fn delay(&self, attempt: i32) -> Duration {
+        let delay = Duration::from_secs(u64::try_from(self.delay_seconds).unwrap_or_default());
+
+        let delay = match self.backoff {
+            Backoff::Fixed => Some(delay),
+            Backoff::Exponential => u32::try_from(attempt - 1)
+                .ok()
+                .and_then(|exponent| 2_u32.checked_pow(exponent))
+                .and_then(|factor| delay.checked_mul(factor)),
+        };
+
+        delay.map_or(MAX_DELAY, |delay| delay.min(MAX_DELAY))
+    }
+}
+
+pub(crate) mod graphql {
+    //! All GraphQL related functionality is encapsulated in this module. The
+    //! relevant functions and structs are re-exported through
+    //! [`crate::graphql`].
+    //!
+    //! API documentation in this module is also used in the GraphQL API itself
+    //! as documentation for the clients.
+    //!
+    //! You can browse to `/graphql/playground` to see all relevant query,
+    //! mutation, and type documentation.
+
+    use super::*;
+    use juniper::GraphQLInputObject;
+
+    /// Contains all the data needed to define the retry policy of a step.
+    #[derive(Clone, Debug, Deserialize, Serialize, GraphQLInputObject)]
+    pub(crate) struct RetryPolicyInput {
+        /// The maximum number of times the step runs, including the first
+        /// attempt.
+        ///
+        /// This value has to be at least `1`, which effectively disables
+        /// retries.
+        pub(crate) max_attempts: i32,
+
+        /// The strategy used to calculate the delay between two attempts.
+        ///
+        /// Defaults to `FIXED`.
+        pub(crate) backoff: Option<Backoff>,
+
+        /// The number of seconds to wait before the second attempt.
+        ///
+        /// Defaults to `0`, meaning the step is retried immediately.
+        pub(crate) delay_seconds: Option<i32>,
+
+        /// An optional list of error kinds that can be retried.
+        ///
+        /// A failed attempt is only retried if its error output contains one
+        /// of the provided error kinds.
+        ///
+        /// If no error kinds are provided, all errors are retried.
+        pub(crate) error_kinds: Option<Vec<String>>,
+    }
+}
+
+impl TryFrom<&graphql::RetryPolicyInput> for RetryPolicy {
+    type Error = String;
+
+    fn try_from(input: &graphql::RetryPolicyInput) -> Result<Self, Self::Error> {
+        if input.max_attempts < 1 {
+            return Err("Retry policy must allow at least one attempt.".to_owned());
+        }
+
+        let delay_seconds = input.delay_seconds.unwrap_or(0);
+        if delay_seconds < 0 {
+            return Err("Retry delay cannot be a negative number of seconds.".to_owned());
+        }
+
+        Ok(Self {
+            max_attempts: input.max_attempts,
+            backoff: input.backoff.unwrap_or(Backoff::Fixed),
+            delay_seconds,
+            error_kinds: input.error_kinds.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy_stub(backoff: Backoff) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            backoff,
+            delay_seconds: 2,
+            error_kinds: None,
+        }
+    }
+
+    #[test]
+    fn test_should_retry_until_max_attempts() {
+        let policy = policy_stub(Backoff::Fixed);
+
+        assert!(policy.should_retry(1, "error"));
+        assert!(policy.should_retry(2, "error"));
+        assert!(!policy.should_retry(3, "error"));
+    }
+
+    #[test]
+    fn test_should_retry_matching_error_kind() {
+        let mut policy = policy_stub(Backoff::Fixed);
+        policy.error_kinds = Some(vec!["Response error".to_owned()]);
+
+        assert!(policy.should_retry(1, "Response error: connection refused"));
+        assert!(!policy.should_retry(1, "Invalid status code: 404"));
+    }
+
+    #[test]
+    fn test_delay_fixed() {
+        let policy = policy_stub(Backoff::Fixed);
+
+        assert_eq!(policy.delay(1), Duration::from_secs(2));
+        assert_eq!(policy.delay(3), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_delay_exponential() {
+        let policy = policy_stub(Backoff::Exponential);
+
+        assert_eq!(policy.delay(1), Duration::from_secs(2));
+        assert_eq!(policy.delay(2), Duration::from_secs(4));
+        assert_eq!(policy.delay(3), Duration::from_secs(8));
+        assert_eq!(policy.delay(100), MAX_DELAY);
+    }
+}
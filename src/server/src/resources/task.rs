@@ -16,9 +16,9 @@
 //! [`variable`]: crate::resources::variable
 
 use super::OnConflict;
-use crate::resources::{NewStep, NewVariable, Step, Variable};
+use crate::resources::{NewStep, NewVariable, Schedule, Step, StepGraph, Variable};
 use crate::schema::{jobs, steps, tasks, variables};
-use crate::server::RequestState;
+use crate::{server::RequestState, Processor};
 use diesel::dsl::sql;
 use diesel::prelude::*;
 use diesel::sql_types::{BigInt, Integer, NotNull, Nullable, Text};
@@ -31,6 +31,11 @@ sql_function!(fn coalesce<T: NotNull>(value: Nullable<T>, replace: T) -> T);
 sql_function!(fn lower(value: Text) -> Text);
 sql_function!(fn left(source: Text, length: Integer) -> Text);
 
+pub(crate) mod definition;
+pub(crate) mod version;
+
+use version::TaskVersion;
+
 /// This is a throw-away struct to fetch the right search query details from the
 /// database using Diesel. We aren't interested in the task reference or count
 /// results, but have to define them for type safety.
@@ -52,6 +57,7 @@ pub(crate) struct Task {
     pub(crate) name: String,
     pub(crate) description: Option<String>,
     pub(crate) labels: Vec<String>,
+    pub(crate) timeout_seconds: Option<i32>,
 }
 
 impl Task {
@@ -69,6 +75,153 @@ impl Task {
         Variable::belonging_to(self).order(id.asc()).load(conn)
     }
 
+    pub(crate) fn schedules(&self, conn: &PgConnection) -> QueryResult<Vec<Schedule>> {
+        use crate::schema::schedules::dsl::*;
+
+        Schedule::belonging_to(self).order(id.asc()).load(conn)
+    }
+
+    /// Returns all recorded versions of the task, ordered from first to last.
+    pub(crate) fn versions(&self, conn: &PgConnection) -> QueryResult<Vec<TaskVersion>> {
+        use crate::schema::task_versions::dsl::*;
+
+        TaskVersion::belonging_to(self)
+            .order(version.asc())
+            .load(conn)
+    }
+
+    /// Returns the other tasks that run this task as a sub-job, using a
+    /// `RunTask` step.
+    pub(crate) fn dependents(&self, conn: &PgConnection) -> QueryResult<Vec<Self>> {
+        let steps: Vec<Step> = steps::table.filter(steps::task_id.ne(self.id)).load(conn)?;
+
+        let ids = steps
+            .iter()
+            .filter(|step| match step.processor() {
+                Ok(Processor::RunTask(processor)) => processor.task == self.name,
+                _ => false,
+            })
+            .map(|step| step.task_id)
+            .collect::<Vec<_>>();
+
+        tasks::table
+            .filter(tasks::id.eq_any(ids))
+            .order(tasks::id.asc())
+            .load(conn)
+    }
+
+    /// Delete the task, including its steps, variables and schedules.
+    ///
+    /// Jobs created from the task are kept, but lose their reference to the
+    /// task.
+    ///
+    /// An error is returned if any other task runs this task as a sub-job.
+    pub(crate) fn delete(&self, conn: &PgConnection) -> Result<(), Box<dyn error::Error>> {
+        self.ensure_no_dependents(conn)?;
+
+        diesel::delete(self)
+            .execute(conn)
+            .map(|_| ())
+            .map_err(Into::into)
+    }
+
+    /// Delete a single step of the task.
+    ///
+    /// An error is returned if the step does not belong to the task, or if
+    /// any of the remaining steps depend on it.
+    pub(crate) fn delete_step(
+        &self,
+        step: &Step,
+        conn: &PgConnection,
+    ) -> Result<(), Box<dyn error::Error>> {
+        if step.task_id != self.id {
+            return Err("step does not belong to task".into());
+        }
+
+        conn.transaction(|| {
+            let _ = diesel::delete(step).execute(conn)?;
+
+            self.validate_steps(conn)?;
+            TaskVersion::record(self, conn).map(|_| ())
+        })
+    }
+
+    /// Change the order in which the steps of the task run.
+    ///
+    /// The provided step IDs have to contain all steps of the task exactly
+    /// once, ordered by their new position.
+    ///
+    /// Steps without any declared dependencies depend on the step preceding
+    /// them, so an error is returned if the new order results in circular
+    /// dependencies.
+    pub(crate) fn reorder_steps(
+        &self,
+        step_ids: &[i32],
+        conn: &PgConnection,
+    ) -> Result<(), Box<dyn error::Error>> {
+        let mut existing = self.steps(conn)?.iter().map(|s| s.id).collect::<Vec<_>>();
+        let mut provided = step_ids.to_vec();
+        existing.sort_unstable();
+        provided.sort_unstable();
+
+        if existing != provided {
+            return Err("step IDs must contain all steps of the task exactly once".into());
+        }
+
+        conn.transaction(|| {
+            let _ = conn.execute("SET CONSTRAINTS ALL DEFERRED")?;
+
+            step_ids
+                .iter()
+                .zip(0..)
+                .try_for_each(|(step_id, position)| {
+                    diesel::update(steps::table.find(step_id))
+                        .set(steps::position.eq(position))
+                        .execute(conn)
+                        .map(|_| ())
+                })?;
+
+            self.validate_steps(conn)?;
+            TaskVersion::record(self, conn).map(|_| ())
+        })
+    }
+
+    /// Validate that the dependencies between the stored steps of the task
+    /// are valid.
+    fn validate_steps(&self, conn: &PgConnection) -> Result<(), Box<dyn error::Error>> {
+        let steps = self.steps(conn)?;
+
+        // Cleanup steps run separately from the other steps, so they are not
+        // part of the dependency graph.
+        let dependencies = steps
+            .iter()
+            .filter(|step| !step.run_when.is_cleanup())
+            .map(|step| {
+                (
+                    step.name.as_str(),
+                    step.depends_on.as_ref().map(Vec::as_slice),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let _ = StepGraph::new(&dependencies)?;
+        Ok(())
+    }
+
+    /// Returns an error if any other task runs this task as a sub-job.
+    fn ensure_no_dependents(&self, conn: &PgConnection) -> Result<(), Box<dyn error::Error>> {
+        let dependents = self.dependents(conn)?;
+        if dependents.is_empty() {
+            return Ok(());
+        }
+
+        let names = dependents
+            .iter()
+            .map(|t| t.name.as_str())
+            .collect::<Vec<_>>();
+        Err(format!("task is run as a sub-job by: {}", names.join(", ")).into())
+    }
+
     /// Return the task variable matching the given key, if any.
     pub(crate) fn variable_with_key(
         &self,
@@ -145,6 +298,7 @@ pub(crate) struct NewTask<'a> {
     name: &'a str,
     description: Option<&'a str>,
     labels: Vec<&'a str>,
+    timeout_seconds: Option<i32>,
     variables: Vec<NewVariable<'a>>,
     steps: Vec<NewStep<'a>>,
 }
@@ -157,11 +311,18 @@ impl<'a> NewTask<'a> {
             name,
             description,
             labels,
+            timeout_seconds: None,
             variables: vec![],
             steps: vec![],
         }
     }
 
+    /// Limit the time jobs created from this task are allowed to run, before
+    /// they are considered to have failed.
+    pub(crate) fn with_timeout_seconds(&mut self, timeout_seconds: i32) {
+        self.timeout_seconds = Some(timeout_seconds)
+    }
+
     /// Attach variables to this task.
     ///
     /// `NewTask` takes ownership of the variables, but you are required to
@@ -182,8 +343,41 @@ impl<'a> NewTask<'a> {
         self.steps.append(&mut steps)
     }
 
+    /// Validate the task labels, and the configuration of the attached
+    /// steps.
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        validate_labels(&self.labels)?;
+
+        if self.steps.iter().any(|step| match step.processor() {
+            Processor::RunTask(processor) => processor.task == self.name,
+            _ => false,
+        }) {
+            return Err("Task cannot run itself as a sub-job.".to_owned());
+        }
+
+        if self
+            .steps
+            .iter()
+            .any(|step| step.run_when().is_cleanup() && step.depends_on().is_some())
+        {
+            return Err("Cleanup steps cannot depend on other steps.".to_owned());
+        }
+
+        // Cleanup steps run separately from the other steps, so they are not
+        // part of the dependency graph.
+        let dependencies = self
+            .steps
+            .iter()
+            .filter(|step| !step.run_when().is_cleanup())
+            .map(|step| (step.name, step.depends_on()))
+            .collect::<Vec<_>>();
+
+        let _ = StepGraph::new(&dependencies)?;
+        Ok(())
+    }
+
     /// Persist the task and any attached variables and steps into the
-    /// database.
+    /// database, and record the first version of the task.
     ///
     /// Persisting the data happens within a transaction that is rolled back if
     /// any data fails to persist.
@@ -196,11 +390,13 @@ impl<'a> NewTask<'a> {
                 name.eq(&self.name),
                 description.eq(&self.description),
                 labels.eq(&self.labels),
+                timeout_seconds.eq(self.timeout_seconds),
             );
 
             let task = diesel::insert_into(tasks).values(values).get_result(conn)?;
             self.create_or_update_associations(&task, conn)?;
 
+            let _ = TaskVersion::record(&task, conn)?;
             Ok(task)
         })
     }
@@ -209,7 +405,8 @@ impl<'a> NewTask<'a> {
     /// database.
     ///
     /// If the task already exists, it will be updated, as will any
-    /// associations, if they have changed.
+    /// associations, if they have changed. A new version of the task is
+    /// recorded if anything changed.
     pub(crate) fn create_or_update(
         self,
         conn: &PgConnection,
@@ -217,11 +414,12 @@ impl<'a> NewTask<'a> {
         use diesel::dsl::{any, not};
         use diesel::insert_into;
 
-        conn.build_transaction().deferrable().run(|| {
+        conn.transaction(|| {
             let values = (
                 tasks::name.eq(&self.name),
                 tasks::description.eq(&self.description),
                 tasks::labels.eq(&self.labels),
+                tasks::timeout_seconds.eq(self.timeout_seconds),
             );
 
             let task: Task = insert_into(tasks::table)
@@ -261,6 +459,7 @@ impl<'a> NewTask<'a> {
 
             self.create_or_update_associations(&task, conn)?;
 
+            let _ = TaskVersion::record(&task, conn)?;
             Ok(task)
         })
     }
@@ -282,6 +481,58 @@ impl<'a> NewTask<'a> {
     }
 }
 
+/// Contains the changes to apply to an existing task.
+///
+/// Fields that are `None` are left unchanged. Use
+/// [`TaskPatch#apply`] to update the task in the database.
+#[derive(Clone, Debug, Default, AsChangeset)]
+#[table_name = "tasks"]
+pub(crate) struct TaskPatch<'a> {
+    name: Option<&'a str>,
+    description: Option<Option<&'a str>>,
+    labels: Option<Vec<&'a str>>,
+    timeout_seconds: Option<Option<i32>>,
+}
+
+impl<'a> TaskPatch<'a> {
+    /// Apply the changes to the provided task, and return the updated task.
+    ///
+    /// A task can't be renamed while other tasks run it as a sub-job, as
+    /// those tasks refer to it by name.
+    pub(crate) fn apply(
+        self,
+        conn: &PgConnection,
+        task: &Task,
+    ) -> Result<Task, Box<dyn error::Error>> {
+        if let Some(name) = self.name.filter(|name| *name != task.name) {
+            task.ensure_no_dependents(conn)?;
+
+            if task.steps(conn)?.iter().any(|step| match step.processor() {
+                Ok(Processor::RunTask(processor)) => processor.task == name,
+                _ => false,
+            }) {
+                return Err("Task cannot run itself as a sub-job.".into());
+            }
+        }
+
+        let unchanged = self.name.is_none()
+            && self.description.is_none()
+            && self.labels.is_none()
+            && self.timeout_seconds.is_none();
+
+        if unchanged {
+            return tasks::table.find(task.id).first(conn).map_err(Into::into);
+        }
+
+        conn.transaction(|| {
+            let task = diesel::update(task).set(&self).get_result(conn)?;
+
+            let _ = TaskVersion::record(&task, conn)?;
+            Ok(task)
+        })
+    }
+}
+
 pub(crate) mod graphql {
     //! All GraphQL related functionality is encapsulated in this module. The
     //! relevant functions and structs are re-exported through
@@ -294,7 +545,7 @@ pub(crate) mod graphql {
     //! mutation, and type documentation.
 
     use super::*;
-    use crate::resources::{CreateStepInput, CreateVariableInput, Step, Variable};
+    use crate::resources::{CreateStepInput, CreateVariableInput, Schedule, Step, Variable};
     use juniper::{object, FieldResult, GraphQLInputObject, ID};
 
     /// Contains all the data needed to create a new `Task`.
@@ -337,6 +588,15 @@ pub(crate) mod graphql {
         /// version of this API.
         pub(crate) steps: Vec<CreateStepInput>,
 
+        /// An optional number of seconds jobs created from this task are
+        /// allowed to run.
+        ///
+        /// If a job runs longer than the provided timeout, the active step is
+        /// stopped, and the job fails.
+        ///
+        /// Individual steps can also have their own timeout.
+        pub(crate) timeout_seconds: Option<i32>,
+
         /// Define what to do when the task already exists.
         ///
         /// By default, updating an existing task is disallowed, to prevent
@@ -345,10 +605,42 @@ pub(crate) mod graphql {
         /// You can set this value to `UPDATE` to force the existing task to be
         /// updated to the newly provided value.
         ///
-        /// NOTE that a task name or ID cannot be updated at the moment.
+        /// NOTE that a task name or ID cannot be updated this way, use the
+        /// `updateTask` mutation to rename a task.
         pub(crate) on_conflict: Option<OnConflict>,
     }
 
+    /// Contains the changes to apply to an existing `Task`.
+    ///
+    /// Any field that is not provided is left unchanged. To change the steps
+    /// or variables of a task, use the `createTask` mutation with `onConflict`
+    /// set to `UPDATE`.
+    #[derive(Clone, Debug, Deserialize, Serialize, GraphQLInputObject)]
+    pub(crate) struct UpdateTaskInput {
+        /// The new name of the task.
+        ///
+        /// This name is required to be unique. Jobs that already ran keep the
+        /// name they were created with.
+        pub(crate) name: Option<String>,
+
+        /// The new description of the task.
+        ///
+        /// An empty description removes the existing description.
+        pub(crate) description: Option<String>,
+
+        /// The new set of labels attached to the task.
+        ///
+        /// Any labels existing before, but missing in this set will be
+        /// removed.
+        pub(crate) labels: Option<Vec<String>>,
+
+        /// The new number of seconds jobs created from this task are allowed
+        /// to run.
+        ///
+        /// A value of `0` removes the existing timeout.
+        pub(crate) timeout_seconds: Option<i32>,
+    }
+
     /// An optional set of input details to filter a set of `Task`s, based
     /// on either their name, or description.
     #[derive(Clone, Debug, Deserialize, Serialize, GraphQLInputObject)]
@@ -398,6 +690,12 @@ pub(crate) mod graphql {
             self.labels.iter().map(String::as_str).collect()
         }
 
+        /// The number of seconds jobs created from this task are allowed to
+        /// run, if any.
+        fn timeout_seconds() -> Option<i32> {
+            self.timeout_seconds
+        }
+
         /// The variables belonging to the task.
         ///
         /// This field can return `null`, but _only_ if a database error
@@ -437,7 +735,59 @@ pub(crate) mod graphql {
         fn steps(context: &RequestState) -> FieldResult<Option<Vec<Step>>> {
             self.steps(&context.conn).map(Some).map_err(Into::into)
         }
+
+        /// The recorded versions of the task, ordered from first to last.
+        ///
+        /// A new version is recorded every time the task is changed.
+        ///
+        /// This field can return `null`, but _only_ if a database error
+        /// prevents the data from being retrieved.
+        ///
+        /// If a `null` value is returned, it is up to the client to decide the
+        /// best course of action. The following actions are advised, sorted by
+        /// preference:
+        ///
+        /// 1. continue execution if the information is not critical to success,
+        /// 2. retry the request to try and get the relevant information,
+        /// 3. disable parts of the application reliant on the information,
+        /// 4. show a global error, and ask the user to retry.
+        fn versions(context: &RequestState) -> FieldResult<Option<Vec<TaskVersion>>> {
+            self.versions(&context.conn).map(Some).map_err(Into::into)
+        }
+
+        /// The schedules that trigger the task on a recurring basis.
+        ///
+        /// This field can return `null`, but _only_ if a database error
+        /// prevents the data from being retrieved.
+        ///
+        /// If no schedules are attached to a task, an empty array is returned
+        /// instead.
+        ///
+        /// If a `null` value is returned, it is up to the client to decide the
+        /// best course of action. The following actions are advised, sorted by
+        /// preference:
+        ///
+        /// 1. continue execution if the information is not critical to success,
+        /// 2. retry the request to try and get the relevant information,
+        /// 3. disable parts of the application reliant on the information,
+        /// 4. show a global error, and ask the user to retry.
+        fn schedules(context: &RequestState) -> FieldResult<Option<Vec<Schedule>>> {
+            self.schedules(&context.conn).map(Some).map_err(Into::into)
+        }
+    }
+}
+
+/// Validate that none of the task labels can be mistaken for a privilege
+/// that grants access to a query or mutation.
+fn validate_labels(labels: &[&str]) -> Result<(), String> {
+    if labels
+        .iter()
+        .any(|l| l.starts_with("mutation_") || l.starts_with("query_"))
+    {
+        return Err("Task labels cannot start with `mutation_` or `query_`.".to_owned());
     }
+
+    Ok(())
 }
 
 impl<'a> TryFrom<&'a graphql::CreateTaskInput> for NewTask<'a> {
@@ -449,13 +799,6 @@ impl<'a> TryFrom<&'a graphql::CreateTaskInput> for NewTask<'a> {
             .as_ref()
             .map_or(vec![], |l| l.iter().map(String::as_str).collect());
 
-        if labels
-            .iter()
-            .any(|l| l.starts_with("mutation_") || l.starts_with("query_"))
-        {
-            return Err("Task labels cannot start with `mutation_` or `query_`.".to_owned());
-        }
-
         let mut task = Self::new(
             &input.name,
             input.description.as_ref().map(String::as_ref),
@@ -477,8 +820,60 @@ impl<'a> TryFrom<&'a graphql::CreateTaskInput> for NewTask<'a> {
             .map(TryInto::try_into)
             .collect::<Result<Vec<_>, Self::Error>>()?;
 
+        if let Some(timeout) = input.timeout_seconds {
+            if timeout <= 0 {
+                return Err("Task timeout must be a positive number of seconds.".to_owned());
+            }
+
+            task.with_timeout_seconds(timeout);
+        }
+
         task.with_variables(variables);
         task.with_steps(steps);
+        task.validate()?;
+
         Ok(task)
     }
 }
+
+impl<'a> TryFrom<&'a graphql::UpdateTaskInput> for TaskPatch<'a> {
+    type Error = String;
+
+    fn try_from(input: &'a graphql::UpdateTaskInput) -> Result<Self, Self::Error> {
+        let labels = input
+            .labels
+            .as_ref()
+            .map(|l| l.iter().map(String::as_str).collect::<Vec<_>>());
+
+        if let Some(labels) = &labels {
+            validate_labels(labels)?;
+        }
+
+        if let Some(name) = &input.name {
+            if name.trim().is_empty() {
+                return Err("Task name cannot be empty.".to_owned());
+            }
+        }
+
+        let timeout_seconds = match input.timeout_seconds {
+            None => None,
+            Some(0) => Some(None),
+            Some(timeout) if timeout > 0 => Some(Some(timeout)),
+            Some(_) => {
+                return Err("Task timeout must be a positive number of seconds.".to_owned());
+            }
+        };
+
+        let description = input
+            .description
+            .as_ref()
+            .map(|d| Some(d.as_str()).filter(|d| !d.is_empty()));
+
+        Ok(Self {
+            name: input.name.as_ref().map(String::as_str),
+            description,
+            labels,
+            timeout_seconds,
+        })
+    }
+}
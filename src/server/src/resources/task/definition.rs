@@ -0,0 +1,456 @@
+//! A task [`Definition`] is a self-contained description of a [`Task`],
+//! including its variables and steps, but excluding any database identifiers.
+//!
+//! Definitions are stored as immutable task versions, and can be used to
+//! restore a task to an earlier version, or to compare two versions of the
+//! same task.
+//!
+//! [`Task`]: crate::resources::Task
+
+use crate::resources::{
+    NewStep, NewTask, NewVariable, RetryPolicy, StepRunWhen, Task, VariableConstraints,
+    VariableKind,
+};
+use crate::schema::variable_advertisements;
+use crate::Processor;
+use diesel::prelude::*;
+use juniper::GraphQLObject;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::error::Error;
+
+/// The definition of a task.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct Definition {
+    pub(crate) name: String,
+    pub(crate) description: Option<String>,
+
+    #[serde(default)]
+    pub(crate) labels: Vec<String>,
+    pub(crate) timeout_seconds: Option<i32>,
+
+    #[serde(default)]
+    pub(crate) variables: Vec<VariableDefinition>,
+
+    /// The steps of the task, ordered by their position.
+    #[serde(default)]
+    pub(crate) steps: Vec<StepDefinition>,
+}
+
+/// The definition of a single task variable.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct VariableDefinition {
+    pub(crate) key: String,
+    pub(crate) description: Option<String>,
+    pub(crate) selection_constraint: Option<Vec<String>>,
+    pub(crate) default_value: Option<String>,
+    pub(crate) example_value: Option<String>,
+
+    #[serde(default, rename = "type")]
+    pub(crate) kind: VariableKind,
+
+    #[serde(default)]
+    pub(crate) constraints: VariableConstraints,
+
+    #[serde(default)]
+    pub(crate) optional: bool,
+
+    #[serde(default)]
+    pub(crate) secret: bool,
+}
+
+/// The definition of a single task step.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct StepDefinition {
+    pub(crate) name: String,
+    pub(crate) description: Option<String>,
+    pub(crate) processor: Processor,
+    pub(crate) advertised_variable_key: Option<String>,
+    pub(crate) timeout_seconds: Option<i32>,
+    pub(crate) retry_policy: Option<RetryPolicy>,
+    pub(crate) depends_on: Option<Vec<String>>,
+    pub(crate) condition: Option<String>,
+
+    #[serde(default = "default_run_when")]
+    pub(crate) run_when: StepRunWhen,
+    pub(crate) for_each: Option<String>,
+}
+
+const fn default_run_when() -> StepRunWhen {
+    StepRunWhen::OnSuccess
+}
+
+/// A single difference between two task definitions.
+#[derive(Clone, Debug, PartialEq, GraphQLObject)]
+#[graphql(name = "TaskVersionChange")]
+pub(crate) struct Change {
+    /// The path of the changed value, such as `description`, or
+    /// `steps.Deploy.condition`.
+    ///
+    /// The `steps` path itself changes if the order of the steps changed.
+    pub(crate) path: String,
+
+    /// The JSON encoded value before the change.
+    ///
+    /// This is `null` if the value was added.
+    pub(crate) before: Option<String>,
+
+    /// The JSON encoded value after the change.
+    ///
+    /// This is `null` if the value was removed.
+    pub(crate) after: Option<String>,
+}
+
+impl Definition {
+    /// Load the current definition of the provided task from the database.
+    pub(crate) fn load(task: &Task, conn: &PgConnection) -> Result<Self, Box<dyn Error>> {
+        let variables = task
+            .variables(conn)?
+            .into_iter()
+            .map(|variable| {
+                Ok(VariableDefinition {
+                    constraints: variable.constraints()?,
+                    key: variable.key,
+                    description: variable.description,
+                    selection_constraint: variable.selection_constraint,
+                    default_value: variable.default_value,
+                    example_value: variable.example_value,
+                    kind: variable.kind,
+                    optional: variable.optional,
+                    secret: variable.secret,
+                })
+            })
+            .collect::<Result<_, serde_json::Error>>()?;
+
+        let steps = task
+            .steps(conn)?
+            .into_iter()
+            .map(|step| {
+                let advertised_variable_key = variable_advertisements::table
+                    .filter(variable_advertisements::step_id.eq(step.id))
+                    .select(variable_advertisements::key)
+                    .first(conn)
+                    .optional()?;
+
+                Ok(StepDefinition {
+                    processor: step.processor()?,
+                    retry_policy: step.retry_policy()?,
+                    name: step.name,
+                    description: step.description,
+                    advertised_variable_key,
+                    timeout_seconds: step.timeout_seconds,
+                    depends_on: step.depends_on,
+                    condition: step.condition,
+                    run_when: step.run_when,
+                    for_each: step.for_each,
+                })
+            })
+            .collect::<Result<_, Box<dyn Error>>>()?;
+
+        Ok(Self {
+            name: task.name.clone(),
+            description: task.description.clone(),
+            labels: task.labels.clone(),
+            timeout_seconds: task.timeout_seconds,
+            variables,
+            steps,
+        })
+    }
+
+    /// Returns all differences between this definition and a newer one.
+    ///
+    /// Steps are matched by name, and variables by key. A step or variable
+    /// that only exists in one of the definitions is reported as a single
+    /// change, with the entire step or variable as its value.
+    pub(crate) fn diff(&self, newer: &Self) -> Vec<Change> {
+        let mut changes = vec![];
+
+        let before = json!({
+            "name": self.name,
+            "description": self.description,
+            "labels": self.labels,
+            "timeout_seconds": self.timeout_seconds,
+        });
+
+        let after = json!({
+            "name": newer.name,
+            "description": newer.description,
+            "labels": newer.labels,
+            "timeout_seconds": newer.timeout_seconds,
+        });
+
+        diff_fields("", &before, &after, &mut changes);
+
+        let variables = |d: &Self| {
+            d.variables
+                .iter()
+                .map(|v| (v.key.clone(), json!(v)))
+                .collect::<Vec<_>>()
+        };
+
+        diff_collection("variables", variables(self), variables(newer), &mut changes);
+
+        let steps = |d: &Self| {
+            d.steps
+                .iter()
+                .map(|s| (s.name.clone(), json!(s)))
+                .collect::<Vec<_>>()
+        };
+
+        diff_collection("steps", steps(self), steps(newer), &mut changes);
+
+        // The position of a step is not part of the step itself, so a change
+        // in order is reported separately.
+        let order = |d: &Self| json!(d.steps.iter().map(|s| &s.name).collect::<Vec<_>>());
+        let (before, after) = (order(self), order(newer));
+        if before != after {
+            changes.push(change("steps", Some(&before), Some(&after)));
+        }
+
+        changes
+    }
+
+    /// Persist the definition as the task with the same name, creating the
+    /// task if it doesn't exist yet.
+    pub(crate) fn create_or_update(&self, conn: &PgConnection) -> Result<Task, Box<dyn Error>> {
+        NewTask::try_from(self)?.create_or_update(conn)
+    }
+}
+
+/// Report the changes between two collections of JSON objects, each
+/// identified by a name.
+fn diff_collection(
+    prefix: &str,
+    before: Vec<(String, Value)>,
+    after: Vec<(String, Value)>,
+    changes: &mut Vec<Change>,
+) {
+    let before = before.into_iter().collect::<BTreeMap<_, _>>();
+    let after = after.into_iter().collect::<BTreeMap<_, _>>();
+
+    let names = before.keys().chain(after.keys()).collect::<BTreeSet<_>>();
+
+    for name in names {
+        let path = format!("{}.{}", prefix, name);
+
+        match (before.get(name), after.get(name)) {
+            (Some(before), Some(after)) => diff_fields(&path, before, after, changes),
+            (before, after) => changes.push(change(&path, before, after)),
+        }
+    }
+}
+
+/// Report the changed fields between two JSON objects.
+fn diff_fields(prefix: &str, before: &Value, after: &Value, changes: &mut Vec<Change>) {
+    let empty = serde_json::Map::new();
+    let before = before.as_object().unwrap_or(&empty);
+    let after = after.as_object().unwrap_or(&empty);
+
+    let keys = before.keys().chain(after.keys()).collect::<BTreeSet<_>>();
+
+    for key in keys {
+        let (old, new) = (before.get(key), after.get(key));
+        if old == new {
+            continue;
+        }
+
+        let path = if prefix.is_empty() {
+            key.to_owned()
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+
+        changes.push(change(&path, old, new));
+    }
+}
+
+fn change(path: &str, before: Option<&Value>, after: Option<&Value>) -> Change {
+    let encode = |value: Option<&Value>| value.filter(|v| !v.is_null()).map(Value::to_string);
+
+    Change {
+        path: path.to_owned(),
+        before: encode(before),
+        after: encode(after),
+    }
+}
+
+impl<'a> TryFrom<&'a Definition> for NewTask<'a> {
+    type Error = String;
+
+    fn try_from(definition: &'a Definition) -> Result<Self, Self::Error> {
+        let mut task = Self::new(
+            &definition.name,
+            definition.description.as_ref().map(String::as_str),
+            definition.labels.iter().map(String::as_str).collect(),
+        );
+
+        if let Some(timeout) = definition.timeout_seconds {
+            if timeout <= 0 {
+                return Err("Task timeout must be a positive number of seconds.".to_owned());
+            }
+
+            task.with_timeout_seconds(timeout);
+        }
+
+        let variables = definition
+            .variables
+            .iter()
+            .map(|variable| {
+                let mut new = NewVariable::new(
+                    &variable.key,
+                    variable
+                        .selection_constraint
+                        .as_ref()
+                        .map(|v| v.iter().map(String::as_str).collect()),
+                    variable.default_value.as_ref().map(String::as_str),
+                    variable.example_value.as_ref().map(String::as_str),
+                    variable.description.as_ref().map(String::as_str),
+                )?;
+
+                new.with_constraints(variable.kind, variable.constraints.clone())?;
+                new.with_optional(variable.optional);
+                new.with_secret(variable.secret);
+                Ok(new)
+            })
+            .collect::<Result<_, String>>()?;
+
+        let steps = definition
+            .steps
+            .iter()
+            .zip(0..)
+            .map(|(step, position)| NewStep::try_from((position, step)))
+            .collect::<Result<_, _>>()?;
+
+        task.with_variables(variables);
+        task.with_steps(steps);
+        task.validate()?;
+
+        Ok(task)
+    }
+}
+
+impl<'a> TryFrom<(i32, &'a StepDefinition)> for NewStep<'a> {
+    type Error = String;
+
+    fn try_from((position, definition): (i32, &'a StepDefinition)) -> Result<Self, String> {
+        let mut step = Self::new(
+            &definition.name,
+            definition.description.as_ref().map(String::as_str),
+            definition.processor.clone(),
+            position,
+            definition
+                .advertised_variable_key
+                .as_ref()
+                .map(String::as_str),
+        );
+
+        if let Some(timeout) = definition.timeout_seconds {
+            if timeout <= 0 {
+                return Err("Step timeout must be a positive number of seconds.".to_owned());
+            }
+
+            step.with_timeout_seconds(timeout);
+        }
+
+        if let Some(policy) = &definition.retry_policy {
+            step.with_retry_policy(policy.clone());
+        }
+
+        if let Some(condition) = &definition.condition {
+            step.with_condition(condition)?;
+        }
+
+        if let Some(template) = &definition.for_each {
+            step.with_for_each(template)?;
+        }
+
+        if let Some(depends_on) = &definition.depends_on {
+            step.with_depends_on(depends_on.iter().map(String::as_str).collect());
+        }
+
+        step.with_run_when(definition.run_when);
+        Ok(step)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::RunTask;
+
+    fn step(name: &str) -> StepDefinition {
+        StepDefinition {
+            name: name.to_owned(),
+            description: None,
+            processor: Processor::RunTask(RunTask {
+                task: "Other".to_owned(),
+                variables: vec![],
+            }),
+            advertised_variable_key: None,
+            timeout_seconds: None,
+            retry_policy: None,
+            depends_on: None,
+            condition: None,
+            run_when: StepRunWhen::OnSuccess,
+            for_each: None,
+        }
+    }
+
+    fn definition(steps: Vec<StepDefinition>) -> Definition {
+        Definition {
+            name: "Task".to_owned(),
+            description: None,
+            labels: vec![],
+            timeout_seconds: None,
+            variables: vec![],
+            steps,
+        }
+    }
+
+    #[test]
+    fn test_diff_unchanged() {
+        let old = definition(vec![step("one")]);
+
+        assert!(old.diff(&old.clone()).is_empty());
+    }
+
+    #[test]
+    fn test_diff_fields() {
+        let old = definition(vec![step("one")]);
+        let mut new = old.clone();
+        new.description = Some("new".to_owned());
+        new.steps[0].condition = Some("true".to_owned());
+
+        assert_eq!(
+            old.diff(&new),
+            vec![
+                Change {
+                    path: "description".to_owned(),
+                    before: None,
+                    after: Some(r#""new""#.to_owned()),
+                },
+                Change {
+                    path: "steps.one.condition".to_owned(),
+                    before: None,
+                    after: Some(r#""true""#.to_owned()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_added_removed_and_reordered_steps() {
+        let old = definition(vec![step("one"), step("two")]);
+        let new = definition(vec![step("three"), step("one")]);
+
+        let paths = old
+            .diff(&new)
+            .into_iter()
+            .map(|change| change.path)
+            .collect::<Vec<_>>();
+
+        assert_eq!(paths, vec!["steps.three", "steps.two", "steps"]);
+    }
+}
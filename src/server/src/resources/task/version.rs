@@ -0,0 +1,196 @@
+//! A [`TaskVersion`] is an immutable snapshot of the [`Definition`] of a
+//! [`Task`].
+//!
+//! A new version is recorded every time a task is created or changed, and
+//! every job references the version of the task it was created from. This
+//! makes it possible to see which definition a past job ran, and to restore
+//! a task to an earlier version.
+
+use super::definition::{Change, Definition};
+use crate::processor::Processor;
+use crate::resources::Task;
+use crate::schema::{task_versions, tasks};
+use crate::server::RequestState;
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+
+/// The model representing a task version stored in the database.
+#[derive(Clone, Debug, Deserialize, Serialize, Associations, Identifiable, Queryable)]
+#[belongs_to(Task)]
+#[table_name = "task_versions"]
+pub(crate) struct TaskVersion {
+    pub(crate) id: i32,
+
+    /// The version number, starting at `1` for the first version of a task.
+    pub(crate) version: i32,
+    pub(crate) definition: serde_json::Value,
+    pub(crate) created_at: NaiveDateTime,
+    pub(crate) task_id: i32,
+}
+
+impl TaskVersion {
+    /// Record the current definition of the provided task as a new version,
+    /// and return that version.
+    ///
+    /// If the definition did not change since the latest version, no new
+    /// version is recorded, and the latest version is returned instead.
+    pub(crate) fn record(task: &Task, conn: &PgConnection) -> Result<Self, Box<dyn Error>> {
+        let definition = serde_json::to_value(Definition::load(task, conn)?)?;
+
+        let latest: Option<Self> = Self::belonging_to(task)
+            .order(task_versions::version.desc())
+            .first(conn)
+            .optional()?;
+
+        let version = match latest {
+            Some(latest) if latest.definition == definition => return Ok(latest),
+            Some(latest) => latest.version + 1,
+            None => 1,
+        };
+
+        diesel::insert_into(task_versions::table)
+            .values((
+                task_versions::version.eq(version),
+                task_versions::definition.eq(definition),
+                task_versions::created_at.eq(Utc::now().naive_utc()),
+                task_versions::task_id.eq(task.id),
+            ))
+            .get_result(conn)
+            .map_err(Into::into)
+    }
+
+    pub(crate) fn definition(&self) -> Result<Definition, serde_json::Error> {
+        serde_json::from_value(self.definition.clone())
+    }
+
+    pub(crate) fn task(&self, conn: &PgConnection) -> QueryResult<Task> {
+        tasks::table.find(self.task_id).first(conn)
+    }
+
+    /// Returns the version of the same task with the provided version number,
+    /// if any.
+    pub(crate) fn sibling(&self, version: i32, conn: &PgConnection) -> QueryResult<Option<Self>> {
+        task_versions::table
+            .filter(task_versions::task_id.eq(self.task_id))
+            .filter(task_versions::version.eq(version))
+            .first(conn)
+            .optional()
+    }
+
+    /// Returns the changes made in this version, compared to the provided
+    /// (older) version.
+    pub(crate) fn changes_since(&self, other: &Self) -> Result<Vec<Change>, serde_json::Error> {
+        Ok(other.definition()?.diff(&self.definition()?))
+    }
+
+    /// Restore the task to this version.
+    ///
+    /// The task is renamed first, if its name changed since this version.
+    /// Restoring a version records a new version, as with any other change to
+    /// the task.
+    ///
+    /// As with [`TaskPatch#apply`], a task can't be renamed while other tasks
+    /// run it as a sub-job, or to a name it runs as a sub-job itself. Nothing
+    /// is changed if the task can't be restored.
+    pub(crate) fn restore(&self, conn: &PgConnection) -> Result<Task, Box<dyn Error>> {
+        let definition = self.definition()?;
+        let task = self.task(conn)?;
+
+        conn.transaction(|| {
+            if task.name != definition.name {
+                task.ensure_no_dependents(conn)?;
+
+                if definition.steps.iter().any(|step| match &step.processor {
+                    Processor::RunTask(processor) => processor.task == definition.name,
+                    _ => false,
+                }) {
+                    return Err("Task cannot run itself as a sub-job.".into());
+                }
+
+                let _ = diesel::update(&task)
+                    .set(tasks::name.eq(&definition.name))
+                    .execute(conn)?;
+            }
+
+            definition.create_or_update(conn)
+        })
+    }
+}
+
+pub(crate) mod graphql {
+    //! All GraphQL related functionality is encapsulated in this module. The
+    //! relevant functions and structs are re-exported through
+    //! [`crate::graphql`].
+    //!
+    //! API documentation in this module is also used in the GraphQL API itself
+    //! as documentation for the clients.
+    //!
+    //! You can browse to `/graphql/playground` to see all relevant query,
+    //! mutation, and type documentation.
+
+    use super::*;
+    use chrono::DateTime;
+    use juniper::{object, FieldResult, ID};
+
+    #[object(Context = RequestState)]
+    impl TaskVersion {
+        /// The unique identifier for a specific task version.
+        fn id() -> ID {
+            ID::new(self.id.to_string())
+        }
+
+        /// The version number, starting at `1` for the first version of a
+        /// task.
+        fn version() -> i32 {
+            self.version
+        }
+
+        /// The moment at which the version was recorded.
+        fn created_at() -> DateTime<Utc> {
+            DateTime::from_utc(self.created_at, Utc)
+        }
+
+        /// The JSON encoded definition of the task at this version, including
+        /// its variables and steps.
+        fn definition() -> String {
+            self.definition.to_string()
+        }
+
+        /// The changes made in this version, compared to the version with the
+        /// provided version number (defaults to the previous version).
+        ///
+        /// If there is no version to compare with, for example because this is
+        /// the first version of the task, this field returns `null`.
+        ///
+        /// This field can also return `null` if a database error prevents the
+        /// data from being retrieved, in which case an `errors` object will be
+        /// attached to the result.
+        fn changes(context: &RequestState, since: Option<i32>) -> FieldResult<Option<Vec<Change>>> {
+            let since = since.unwrap_or(self.version - 1);
+
+            match self.sibling(since, &context.conn)? {
+                None => Ok(None),
+                Some(other) => self.changes_since(&other).map(Some).map_err(Into::into),
+            }
+        }
+
+        /// The task to which the version belongs.
+        ///
+        /// This field can return `null`, but _only_ if a database error
+        /// prevents the data from being retrieved.
+        ///
+        /// If a `null` value is returned, it is up to the client to decide the
+        /// best course of action. The following actions are advised, sorted by
+        /// preference:
+        ///
+        /// 1. continue execution if the information is not critical to success,
+        /// 2. retry the request to try and get the relevant information,
+        /// 3. disable parts of the application reliant on the information,
+        /// 4. show a global error, and ask the user to retry.
+        fn task(context: &RequestState) -> FieldResult<Option<Task>> {
+            self.task(&context.conn).map(Some).map_err(Into::into)
+        }
+    }
+}
@@ -26,9 +26,20 @@
 use crate::resources::Task;
 use crate::schema::variables;
 use crate::server::RequestState;
+use crate::Processor;
 use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::convert::{AsRef, TryFrom};
+use std::error::Error;
+use std::iter;
+use tera::{Context, Tera};
+
+pub(crate) mod constraints;
+pub(crate) mod options;
+
+use constraints::{Constraints, Kind};
+use options::Options;
 
 /// The model representing a variable definition (without an actual value)
 /// stored in the database.
@@ -39,13 +50,182 @@ pub(crate) struct Variable {
     pub(crate) id: i32,
     pub(crate) key: String,
     pub(crate) description: Option<String>,
-    // TODO: figure how to use Diesel's `embed` feature to move this into a
-    // `VariableConstraint` struct, which can also hold other constraints (such
-    // as `optional: bool`) in the future.
+    // TODO: figure how to use Diesel's `embed` feature to move this into the
+    // `Constraints` struct, which holds all other value constraints.
     pub(crate) selection_constraint: Option<Vec<String>>,
     pub(crate) default_value: Option<String>,
     pub(crate) example_value: Option<String>,
     pub(crate) task_id: i32,
+    pub(crate) kind: Kind,
+    pub(crate) constraints: Option<serde_json::Value>,
+
+    /// Whether a job can be created without providing a value for the
+    /// variable.
+    pub(crate) optional: bool,
+
+    /// Whether the value of the variable is masked in anything stored about
+    /// the jobs using it.
+    pub(crate) secret: bool,
+}
+
+impl Variable {
+    /// Returns `true` if a value has to be provided for the variable when
+    /// creating a job, because it is not optional, and has no default value.
+    pub(crate) fn is_required(&self) -> bool {
+        !self.optional && self.default_value.is_none()
+    }
+
+    /// Returns the value constraints of the variable, which are empty if none
+    /// are set.
+    pub(crate) fn constraints(&self) -> Result<Constraints, serde_json::Error> {
+        self.constraints
+            .clone()
+            .map_or_else(|| Ok(Constraints::default()), serde_json::from_value)
+    }
+
+    /// Returns the values the variable accepts, if it is limited to a set of
+    /// values.
+    ///
+    /// These are the values of the selection constraint, or the values
+    /// returned by the processor of the options constraint, which can be
+    /// cached.
+    pub(crate) fn options(
+        &self,
+        conn: &PgConnection,
+    ) -> Result<Option<Vec<String>>, Box<dyn Error>> {
+        if let Some(selection) = &self.selection_constraint {
+            return Ok(Some(selection.clone()));
+        }
+
+        let options = match self.constraints()?.options {
+            None => return Ok(None),
+            Some(options) => options,
+        };
+
+        let labels = crate::schema::tasks::table
+            .find(self.task_id)
+            .select(crate::schema::tasks::labels)
+            .first::<Vec<String>>(conn)?;
+
+        options.load(&labels, conn).map(Some)
+    }
+
+    /// Returns an error if the value does not match the kind and constraints
+    /// of the variable.
+    ///
+    /// The selection constraint is not validated by this method.
+    pub(crate) fn validate_value(&self, value: &str) -> Result<(), String> {
+        let constraints = self.constraints().map_err(|err| err.to_string())?;
+
+        self.kind.validate(value)?;
+        constraints.validate(value)
+    }
+
+    /// Render the default value of the variable, if any.
+    ///
+    /// The default value is a template, which can use the provided `var` and
+    /// `global` variable values, and any of the built-in template functions,
+    /// such as `now()`.
+    pub(crate) fn render_default_value(
+        &self,
+        var: &HashMap<&str, &str>,
+        global: &HashMap<&str, &str>,
+    ) -> Result<Option<String>, String> {
+        let template = match &self.default_value {
+            None => return Ok(None),
+            Some(template) => template,
+        };
+
+        let mut context = Context::new();
+        context.insert("var", var);
+        context.insert("global", global);
+
+        Tera::one_off(template, context, false)
+            .map(Some)
+            .map_err(|err| {
+                let err = err
+                    .source()
+                    .map_or_else(|| err.to_string(), ToString::to_string);
+                format!(r#"default value of variable "{}": {}"#, self.key, err)
+            })
+    }
+
+    /// Returns `true` if the default value of the variable refers to the
+    /// provided key of the `var` or `global` template variable, selected by
+    /// `object`.
+    ///
+    /// Only the expressions and statements of the template are inspected. Any
+    /// use of the object other than looking up a literal key, such as
+    /// iterating over it, is considered to refer to all of its keys.
+    pub(crate) fn default_value_refers_to(&self, object: &str, key: &str) -> bool {
+        self.default_value
+            .iter()
+            .any(|template| tags(template).any(|tag| refers_to(tag, object, key)))
+    }
+}
+
+/// Returns the contents of the expression (`{{ }}`) and statement (`{% %}`)
+/// tags of a template.
+fn tags(template: &str) -> impl Iterator<Item = &str> {
+    let mut rest = template;
+
+    iter::from_fn(move || loop {
+        let start = rest.find('{')?;
+        let close = match rest[start + 1..].chars().next() {
+            Some('{') => "}}",
+            Some('%') => "%}",
+            Some('#') => "#}",
+            _ => {
+                rest = &rest[start + 1..];
+                continue;
+            }
+        };
+
+        let body = &rest[start + 2..];
+        let end = body.find(close).unwrap_or(body.len());
+        rest = body.get(end + close.len()..).unwrap_or_default();
+
+        if close != "#}" {
+            return Some(&body[..end]);
+        }
+    })
+}
+
+/// Returns `true` if the template tag refers to the provided key of the
+/// provided object, as described in [`Variable::default_value_refers_to`].
+fn refers_to(tag: &str, object: &str, key: &str) -> bool {
+    let is_name = |c: char| c == '_' || c.is_ascii_alphanumeric();
+
+    tag.match_indices(object).any(|(start, _)| {
+        // Skip names that merely contain the object name, and attributes of
+        // other objects with the same name.
+        let before = tag[..start].chars().next_back();
+        let rest = &tag[start + object.len()..];
+        if matches!(before, Some(c) if is_name(c) || c == '.') || rest.starts_with(is_name) {
+            return false;
+        }
+
+        let rest = rest.trim_start();
+        if let Some(name) = rest.strip_prefix('.') {
+            let name = name.trim_start();
+            let end = name.find(|c| !is_name(c)).unwrap_or(name.len());
+
+            return &name[..end] == key;
+        }
+
+        if let Some(index) = rest.strip_prefix('[') {
+            let index = index.trim_start();
+
+            return match index.chars().next() {
+                Some(quote) if quote == '"' || quote == '\'' || quote == '`' => {
+                    index[1..].split(quote).next() == Some(key)
+                }
+                _ => true,
+            };
+        }
+
+        true
+    })
 }
 
 /// Contains all the details needed to store a variable in the database.
@@ -60,6 +240,13 @@ pub(crate) struct NewVariable<'a> {
     default_value: Option<&'a str>,
     example_value: Option<&'a str>,
     task_id: Option<i32>,
+    kind: Kind,
+
+    // Always set, so that updating a variable also removes any constraints
+    // that no longer apply.
+    constraints: serde_json::Value,
+    optional: bool,
+    secret: bool,
 }
 
 impl<'a> NewVariable<'a> {
@@ -67,7 +254,8 @@ impl<'a> NewVariable<'a> {
     /// database using the [`NewVariable#add_to_task`] method.
     ///
     /// Returns an error if the `default_value` value is provided, but is not a
-    /// subset of the values provided in `selection_constraint`.
+    /// subset of the values provided in `selection_constraint`. Default values
+    /// that are templates are validated once they are rendered.
     pub(crate) fn new(
         key: &'a str,
         selection_constraint: Option<Vec<&'a str>>,
@@ -76,8 +264,8 @@ impl<'a> NewVariable<'a> {
         description: Option<&'a str>,
     ) -> Result<Self, String> {
         if let Some(selection) = &selection_constraint {
-            if let Some(default) = &default_value {
-                if !selection.contains(default) {
+            if let Some(default) = default_value.filter(|v| !is_template(v)) {
+                if !selection.contains(&default) {
                     return Err(
                         "default value must be included in the selection constraint".to_owned()
                     );
@@ -92,9 +280,71 @@ impl<'a> NewVariable<'a> {
             default_value,
             example_value,
             task_id: None,
+            kind: Kind::default(),
+            constraints: serde_json::json!({}),
+            optional: false,
+            secret: false,
         })
     }
 
+    /// Allow jobs to be created without a value for the variable.
+    ///
+    /// A missing value of an optional variable is replaced by its default
+    /// value, or an empty string if the variable has no default value.
+    pub(crate) fn with_optional(&mut self, optional: bool) {
+        self.optional = optional
+    }
+
+    /// Mark the variable as secret, masking its value in anything stored
+    /// about the jobs using it, such as their processor configuration,
+    /// output, and logs.
+    pub(crate) fn with_secret(&mut self, secret: bool) {
+        self.secret = secret
+    }
+
+    /// Set the kind of values the variable accepts, and any constraints that
+    /// apply to them.
+    ///
+    /// Returns an error if the constraints don't apply to the kind, or if the
+    /// default value or any of the selection values don't match them.
+    pub(crate) fn with_constraints(
+        &mut self,
+        kind: Kind,
+        constraints: Constraints,
+    ) -> Result<(), String> {
+        constraints.validate_kind(kind)?;
+
+        if constraints.options.is_some() && self.selection_constraint.is_some() {
+            return Err(
+                "options constraint can't be combined with a selection constraint".to_owned(),
+            );
+        }
+
+        if kind == Kind::Enum
+            && self.selection_constraint.is_none()
+            && constraints.options.is_none()
+        {
+            return Err("enum variables require a selection or options constraint".to_owned());
+        }
+
+        let values = self
+            .selection_constraint
+            .iter()
+            .flatten()
+            .chain(self.default_value.iter().filter(|v| !is_template(v)));
+
+        for value in values {
+            kind.validate(value)
+                .and_then(|_| constraints.validate(value))
+                .map_err(|err| format!(r#"value "{}" {}"#, value, err))?;
+        }
+
+        self.kind = kind;
+        self.constraints = serde_json::to_value(constraints).map_err(|err| err.to_string())?;
+
+        Ok(())
+    }
+
     /// Add a variable to a [`Task`], by storing it in the database as an
     /// association.
     ///
@@ -120,6 +370,12 @@ impl<'a> NewVariable<'a> {
     }
 }
 
+/// Returns `true` if the (default) value contains template expressions, and
+/// can therefore only be validated once it is rendered.
+fn is_template(value: &str) -> bool {
+    value.contains("{{") || value.contains("{%")
+}
+
 pub(crate) mod graphql {
     //! All GraphQL related functionality is encapsulated in this module. The
     //! relevant functions and structs are re-exported through
@@ -133,6 +389,7 @@ pub(crate) mod graphql {
 
     use super::*;
     use crate::resources::Task;
+    use crate::ProcessorInput;
     use juniper::{object, FieldResult, GraphQLInputObject, GraphQLObject, ID};
 
     /// Contains all the data needed to create a new `Variable`.
@@ -152,6 +409,12 @@ pub(crate) mod graphql {
 
         /// An optional default value that can be used by clients to pre-fill
         /// the variable value before running a task.
+        ///
+        /// The default value is a template, which is rendered when a job is
+        /// created without a value for the variable. The template can use
+        /// global variables (`{{ global.key }}`), other variable values
+        /// (`{{ var.key }}`), and built-in functions, such as
+        /// `{{ now() | date(format="%Y-%m-%d") }}`.
         pub(crate) default_value: Option<String>,
 
         /// An optional example value that can be used by the clients to show
@@ -165,6 +428,13 @@ pub(crate) mod graphql {
         /// A set of optional constraints applied to future values attached to
         /// this variable.
         pub(crate) constraints: Option<VariableConstraintsInput>,
+
+        /// Whether the value of the variable is secret (defaults to `false`).
+        ///
+        /// A secret value is replaced by a placeholder in anything stored
+        /// about the jobs using it, such as their processor configuration,
+        /// output, and logs.
+        pub(crate) secret: Option<bool>,
     }
 
     #[derive(Debug, Clone, Deserialize, Serialize, GraphQLInputObject)]
@@ -174,6 +444,55 @@ pub(crate) mod graphql {
         /// A variable value has to match one of the provided selections in
         /// order to be considered a valid variable.
         pub(crate) selection: Option<Vec<String>>,
+
+        /// The kind of values the variable accepts (defaults to `TEXT`).
+        ///
+        /// An `ENUM` variable requires a selection constraint.
+        #[graphql(name = "type")]
+        pub(crate) kind: Option<Kind>,
+
+        /// The minimum value of a `NUMBER` variable.
+        pub(crate) min: Option<f64>,
+
+        /// The maximum value of a `NUMBER` variable.
+        pub(crate) max: Option<f64>,
+
+        /// A regular expression the entire value has to match.
+        pub(crate) pattern: Option<String>,
+
+        /// The minimum number of characters of the value.
+        pub(crate) min_length: Option<i32>,
+
+        /// The maximum number of characters of the value.
+        pub(crate) max_length: Option<i32>,
+
+        /// Whether a job can be created without a value for the variable
+        /// (defaults to `false`).
+        pub(crate) optional: Option<bool>,
+
+        /// An optional options constraint.
+        ///
+        /// A variable value has to match one of the values returned by the
+        /// provided processor. This can't be combined with a selection
+        /// constraint.
+        pub(crate) options: Option<VariableOptionsInput>,
+    }
+
+    #[derive(Debug, Clone, Deserialize, Serialize, GraphQLInputObject)]
+    pub(crate) struct VariableOptionsInput {
+        /// The processor returning the values the variable accepts.
+        ///
+        /// The processor has to be read-only, such as a `SqlQuery`, a
+        /// `RedisCommand` that reads data, or an `HttpRequest` using the `GET`
+        /// method. Each line of output is a single value, or each element if
+        /// the output is a JSON array.
+        ///
+        /// The processor configuration can use global variables as templates,
+        /// such as `{{ global.database_url }}`.
+        pub(crate) processor: ProcessorInput,
+
+        /// The number of seconds the values are cached (defaults to `60`).
+        pub(crate) ttl_seconds: Option<i32>,
     }
 
     /// The set of constraints that apply to a variable value.
@@ -188,6 +507,35 @@ pub(crate) mod graphql {
         /// Clients are encouraged to enforce this invariant, for example by
         /// changing the input field into a select box.
         pub(crate) selection: Option<Vec<String>>,
+
+        /// The kind of values the variable accepts.
+        ///
+        /// Clients are encouraged to render the matching input field, for
+        /// example a date picker for a `DATE` variable.
+        #[graphql(name = "type")]
+        pub(crate) kind: Kind,
+
+        /// The (optional) minimum value of a `NUMBER` variable.
+        pub(crate) min: Option<f64>,
+
+        /// The (optional) maximum value of a `NUMBER` variable.
+        pub(crate) max: Option<f64>,
+
+        /// An (optional) regular expression the entire value has to match.
+        pub(crate) pattern: Option<String>,
+
+        /// The (optional) minimum number of characters of the value.
+        pub(crate) min_length: Option<i32>,
+
+        /// The (optional) maximum number of characters of the value.
+        pub(crate) max_length: Option<i32>,
+
+        /// Whether the variable is optional.
+        ///
+        /// A job can be created without a value for an optional variable. The
+        /// server replaces any missing (or empty) value with the default
+        /// value of the variable, or an empty string if there is none.
+        pub(crate) optional: bool,
     }
 
     #[object(Context = RequestState)]
@@ -214,6 +562,10 @@ pub(crate) mod graphql {
         ///
         /// Clients can use this to pre-fill a value, or select the correct
         /// value if a selection constrained is defined for the variable.
+        ///
+        /// The default value is a template, which the server renders if no
+        /// value is provided for the variable. Clients should not pre-fill
+        /// default values containing template expressions (`{{` or `{%`).
         fn default_value() -> Option<&str> {
             self.default_value.as_ref().map(String::as_ref)
         }
@@ -227,17 +579,50 @@ pub(crate) mod graphql {
             self.example_value.as_ref().map(String::as_ref)
         }
 
+        /// Whether the value of the variable is secret.
+        ///
+        /// Clients are encouraged to hide secret values while they are
+        /// entered, for example by using a password input field.
+        fn secret() -> bool {
+            self.secret
+        }
+
+        /// The values the variable accepts, if it is limited to a set of
+        /// values.
+        ///
+        /// These are the values of the selection constraint, or the values
+        /// returned by the processor of the options constraint, which are
+        /// cached for a limited time.
+        ///
+        /// Clients are encouraged to enforce this invariant, for example by
+        /// changing the input field into a select box.
+        ///
+        /// This field returns `null` if the variable accepts any value, and
+        /// can fail if the options processor fails.
+        fn options(context: &RequestState) -> FieldResult<Option<Vec<String>>> {
+            self.options(&context.conn).map_err(Into::into)
+        }
+
         /// A set of value constraints for this variable.
         ///
         /// This object will always be defined, but it might be empty, if no
         /// constraints are actually set for this variable.
-        fn constraints() -> VariableConstraints {
-            VariableConstraints {
+        fn constraints() -> FieldResult<VariableConstraints> {
+            let constraints = self.constraints()?;
+
+            Ok(VariableConstraints {
                 selection: self
                     .selection_constraint
                     .as_ref()
                     .map(|v| v.iter().map(ToOwned::to_owned).collect()),
-            }
+                kind: self.kind,
+                min: constraints.min,
+                max: constraints.max,
+                pattern: constraints.pattern,
+                min_length: constraints.min_length,
+                max_length: constraints.max_length,
+                optional: self.optional,
+            })
         }
 
         /// The task to which the variable belongs.
@@ -309,12 +694,116 @@ impl<'a> TryFrom<&'a graphql::CreateVariableInput> for NewVariable<'a> {
                 .map(|v| v.iter().map(String::as_str).collect()),
         };
 
-        Self::new(
+        let mut variable = Self::new(
             &input.key,
             selection_constraint,
             input.default_value.as_ref().map(String::as_ref),
             input.example_value.as_ref().map(String::as_ref),
             input.description.as_ref().map(String::as_ref),
-        )
+        )?;
+
+        if let Some(input) = &input.constraints {
+            let constraints = Constraints {
+                min: input.min,
+                max: input.max,
+                pattern: input.pattern.clone(),
+                min_length: input.min_length,
+                max_length: input.max_length,
+                options: match &input.options {
+                    None => None,
+                    Some(options) => Some(Options {
+                        processor: Processor::try_from(options.processor.clone())?,
+                        ttl_seconds: options.ttl_seconds,
+                    }),
+                },
+            };
+
+            variable.with_constraints(input.kind.unwrap_or_default(), constraints)?;
+            variable.with_optional(input.optional.unwrap_or(false));
+        }
+
+        variable.with_secret(input.secret.unwrap_or(false));
+        Ok(variable)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variable(default_value: Option<&str>) -> Variable {
+        Variable {
+            id: 1,
+            key: "name".to_owned(),
+            description: None,
+            selection_constraint: None,
+            default_value: default_value.map(ToOwned::to_owned),
+            example_value: None,
+            task_id: 1,
+            kind: Kind::Text,
+            constraints: None,
+            optional: false,
+            secret: false,
+        }
+    }
+
+    #[test]
+    fn test_render_default_value() {
+        let var = vec![("greeting", "hello")].into_iter().collect();
+        let global = vec![("world", "Earth")].into_iter().collect();
+
+        let variable = variable(Some("{{ var.greeting }} {{ global.world }}"));
+        let value = variable.render_default_value(&var, &global).unwrap();
+
+        assert_eq!(value, Some("hello Earth".to_owned()));
+    }
+
+    #[test]
+    fn test_render_default_value_none() {
+        let empty = HashMap::new();
+
+        assert_eq!(
+            variable(None).render_default_value(&empty, &empty),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn test_default_value_refers_to() {
+        let variable = variable(Some(
+            "{# global.comment #}{{ global.token | upper }} {{ var['name'] }} \
+             {% if global.enabled %}global.text{% endif %}",
+        ));
+
+        assert!(variable.default_value_refers_to("global", "token"));
+        assert!(variable.default_value_refers_to("global", "enabled"));
+        assert!(variable.default_value_refers_to("var", "name"));
+        assert!(!variable.default_value_refers_to("global", "comment"));
+        assert!(!variable.default_value_refers_to("global", "text"));
+        assert!(!variable.default_value_refers_to("global", "tok"));
+        assert!(!variable.default_value_refers_to("var", "token"));
+    }
+
+    #[test]
+    fn test_default_value_refers_to_whole_object() {
+        let iterate = variable(Some(
+            "{% for key, value in global %}{{ value }}{% endfor %}",
+        ));
+        let index = variable(Some("{{ global[var.name] }}"));
+        let other = variable(Some("{{ var.global }} {{ globals.token }}"));
+
+        assert!(iterate.default_value_refers_to("global", "token"));
+        assert!(index.default_value_refers_to("global", "token"));
+        assert!(!other.default_value_refers_to("global", "token"));
+        assert!(!variable(None).default_value_refers_to("global", "token"));
+    }
+
+    #[test]
+    fn test_render_default_value_invalid() {
+        let empty = HashMap::new();
+
+        assert!(variable(Some("{{ var.missing }}"))
+            .render_default_value(&empty, &empty)
+            .is_err());
     }
 }
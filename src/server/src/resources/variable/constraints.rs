@@ -0,0 +1,260 @@
+//! The [`Kind`] of a variable, and the [`Constraints`] that apply to its
+//! value.
+//!
+//! By default, a variable accepts any text value. A variable can be given a
+//! more specific kind (such as `Number` or `Date`), in which case only values
+//! of that kind are accepted. Clients use the kind to render the matching
+//! input field.
+//!
+//! On top of that, a variable can limit its values further, using constraints
+//! such as the minimum value of a number, or a pattern the value has to match.
+
+use super::options::Options;
+use chrono::NaiveDate;
+use juniper::GraphQLEnum;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+
+/// The format in which date values are provided.
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// The kind of values a variable accepts.
+#[derive(Clone, Copy, Debug, PartialEq, DbEnum, GraphQLEnum, Serialize, Deserialize)]
+#[PgType = "VariableKind"]
+#[graphql(name = "VariableKind")]
+pub(crate) enum Kind {
+    /// A single line of free-form text.
+    Text,
+
+    /// Free-form text that can span multiple lines.
+    Multiline,
+
+    /// A (floating point) number, such as `42` or `-0.5`.
+    Number,
+
+    /// Either `true` or `false`.
+    Boolean,
+
+    /// A date, formatted as `YYYY-MM-DD`.
+    Date,
+
+    /// One of the values of the selection constraint of the variable.
+    Enum,
+
+    /// A valid regular expression.
+    Regex,
+}
+
+impl Default for Kind {
+    fn default() -> Self {
+        Self::Text
+    }
+}
+
+impl Kind {
+    /// Returns an error if the value is not of this kind.
+    pub(crate) fn validate(self, value: &str) -> Result<(), String> {
+        let (valid, expected) = match self {
+            Self::Text | Self::Multiline | Self::Enum => (true, ""),
+            Self::Number => (
+                value.parse::<f64>().map(f64::is_finite).unwrap_or(false),
+                "a number",
+            ),
+            Self::Boolean => (value == "true" || value == "false", "true or false"),
+            Self::Date => (
+                NaiveDate::parse_from_str(value, DATE_FORMAT).is_ok(),
+                "a date formatted as YYYY-MM-DD",
+            ),
+            Self::Regex => (Regex::new(value).is_ok(), "a valid regular expression"),
+        };
+
+        if valid {
+            Ok(())
+        } else {
+            Err(format!("must be {}", expected))
+        }
+    }
+}
+
+/// The constraints that apply to the value of a variable.
+///
+/// The selection constraint predates these constraints, and is stored
+/// separately.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub(crate) struct Constraints {
+    /// The minimum value of a number variable.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) min: Option<f64>,
+
+    /// The maximum value of a number variable.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) max: Option<f64>,
+
+    /// A regular expression the entire value has to match.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) pattern: Option<String>,
+
+    /// The minimum number of characters of the value.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) min_length: Option<i32>,
+
+    /// The maximum number of characters of the value.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) max_length: Option<i32>,
+
+    /// The processor providing the values the variable accepts, as an
+    /// alternative to the static selection constraint.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) options: Option<Options>,
+}
+
+impl Constraints {
+    /// Returns an error if the constraints are invalid, or can't be applied
+    /// to values of the provided kind.
+    pub(crate) fn validate_kind(&self, kind: Kind) -> Result<(), String> {
+        if (self.min.is_some() || self.max.is_some()) && kind != Kind::Number {
+            return Err("min and max constraints only apply to number variables".to_owned());
+        }
+
+        if let (Some(min), Some(max)) = (self.min, self.max) {
+            if min > max {
+                return Err("min constraint must not exceed max constraint".to_owned());
+            }
+        }
+
+        if self.min_length.map_or(false, i32::is_negative)
+            || self.max_length.map_or(false, i32::is_negative)
+        {
+            return Err("length constraints must not be negative".to_owned());
+        }
+
+        if let (Some(min), Some(max)) = (self.min_length, self.max_length) {
+            if min > max {
+                return Err(
+                    "min length constraint must not exceed max length constraint".to_owned(),
+                );
+            }
+        }
+
+        if let Some(pattern) = &self.pattern {
+            let _ = anchored(pattern).map_err(|err| format!("invalid pattern: {}", err))?;
+        }
+
+        if let Some(options) = &self.options {
+            options.validate()?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns an error if the value does not satisfy the constraints.
+    pub(crate) fn validate(&self, value: &str) -> Result<(), String> {
+        if self.min.is_some() || self.max.is_some() {
+            let number = value
+                .parse::<f64>()
+                .map_err(|_| "must be a number".to_owned())?;
+
+            if let Some(min) = self.min.filter(|min| number < *min) {
+                return Err(format!("must be at least {}", min));
+            }
+
+            if let Some(max) = self.max.filter(|max| number > *max) {
+                return Err(format!("must be at most {}", max));
+            }
+        }
+
+        let length = i32::try_from(value.chars().count()).unwrap_or(i32::max_value());
+
+        if let Some(min) = self.min_length.filter(|min| length < *min) {
+            return Err(format!("must be at least {} characters long", min));
+        }
+
+        if let Some(max) = self.max_length.filter(|max| length > *max) {
+            return Err(format!("must be at most {} characters long", max));
+        }
+
+        if let Some(pattern) = &self.pattern {
+            let regex = anchored(pattern).map_err(|err| err.to_string())?;
+
+            if !regex.is_match(value) {
+                return Err(format!("must match pattern {}", pattern));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns a regular expression that only matches if the provided pattern
+/// matches the entire value.
+fn anchored(pattern: &str) -> Result<Regex, regex::Error> {
+    Regex::new(&format!("^(?:{})$", pattern))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kind_validate() {
+        assert!(Kind::Text.validate("anything").is_ok());
+        assert!(Kind::Number.validate("-0.5").is_ok());
+        assert!(Kind::Number.validate("NaN").is_err());
+        assert!(Kind::Number.validate("five").is_err());
+        assert!(Kind::Boolean.validate("true").is_ok());
+        assert!(Kind::Boolean.validate("yes").is_err());
+        assert!(Kind::Date.validate("2019-09-15").is_ok());
+        assert!(Kind::Date.validate("15-09-2019").is_err());
+        assert!(Kind::Regex.validate("^[a-z]+$").is_ok());
+        assert!(Kind::Regex.validate("[a-z").is_err());
+    }
+
+    #[test]
+    fn test_constraints_validate_kind() {
+        let constraints = Constraints {
+            min: Some(1.0),
+            ..Constraints::default()
+        };
+
+        assert!(constraints.validate_kind(Kind::Number).is_ok());
+        assert!(constraints.validate_kind(Kind::Text).is_err());
+
+        let constraints = Constraints {
+            min_length: Some(5),
+            max_length: Some(2),
+            ..Constraints::default()
+        };
+
+        assert!(constraints.validate_kind(Kind::Text).is_err());
+    }
+
+    #[test]
+    fn test_constraints_validate() {
+        let constraints = Constraints {
+            min: Some(1.0),
+            max: Some(10.0),
+            ..Constraints::default()
+        };
+
+        assert!(constraints.validate("5").is_ok());
+        assert_eq!(
+            constraints.validate("0.5").unwrap_err(),
+            "must be at least 1"
+        );
+        assert_eq!(
+            constraints.validate("11").unwrap_err(),
+            "must be at most 10"
+        );
+
+        let constraints = Constraints {
+            pattern: Some("[a-z]+".to_owned()),
+            max_length: Some(3),
+            ..Constraints::default()
+        };
+
+        assert!(constraints.validate("abc").is_ok());
+        assert!(constraints.validate("abc1").is_err());
+        assert!(constraints.validate("abcd").is_err());
+    }
+}
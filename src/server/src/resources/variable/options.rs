@@ -0,0 +1,200 @@
+//! The [`Options`] of a variable define the values it accepts, by running a
+//! read-only processor, instead of listing them in a static selection
+//! constraint.
+//!
+//! For example, a `SqlQuery` processor can list all tenants, or a
+//! `RedisCommand` processor can return the members of a set using
+//! `SMEMBERS`. Each line of the processor output is a single value. If the
+//! output is a JSON array (as returned by the `SqlQuery` processor), each
+//! element is a single value, and objects have to contain a single field.
+//!
+//! The processor configuration can use global variables as templates, such as
+//! `{{ global.database_url }}`. Global variables scoped to task labels are only
+//! available if the task of the variable carries one of those labels.
+//!
+//! Loading the options runs the processor, so the values are cached for a
+//! limited time, to prevent running it every time a client loads a task.
+
+use crate::models::GlobalVariable;
+use crate::Processor;
+use automaat_core::Context as ProcessorContext;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tera::{Context, Tera};
+
+/// The number of seconds the options are cached, if no other duration is
+/// configured.
+const DEFAULT_TTL_SECONDS: i32 = 60;
+
+lazy_static::lazy_static! {
+    /// The most recently loaded options, by their serialized configuration
+    /// and task labels.
+    static ref CACHE: Mutex<HashMap<String, (Instant, Vec<String>)>> =
+        Mutex::new(HashMap::new());
+}
+
+/// The configuration of the processor that provides the values a variable
+/// accepts.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct Options {
+    /// The read-only processor returning the values.
+    pub(crate) processor: Processor,
+
+    /// The number of seconds the values are cached.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) ttl_seconds: Option<i32>,
+}
+
+impl Options {
+    /// Returns an error if the processor is not read-only, or the cache
+    /// duration is negative.
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if !self.processor.is_read_only() {
+            return Err("options processor must be read-only".to_owned());
+        }
+
+        if self.ttl_seconds.map_or(false, i32::is_negative) {
+            return Err("options ttl must not be negative".to_owned());
+        }
+
+        Ok(())
+    }
+
+    /// Returns the values the variable accepts.
+    ///
+    /// The provided labels are the labels of the task of the variable, which
+    /// determine the global variables available to the processor.
+    ///
+    /// The processor only runs if the values are not cached, or if the cached
+    /// values expired. Failures are not cached.
+    pub(crate) fn load(
+        &self,
+        labels: &[String],
+        conn: &PgConnection,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        let key = serde_json::to_string(&(self, labels))?;
+        let ttl = u64::try_from(self.ttl_seconds.unwrap_or(DEFAULT_TTL_SECONDS))
+            .map(Duration::from_secs)
+            .unwrap_or_default();
+
+        let cached = CACHE.lock().ok().and_then(|cache| {
+            cache
+                .get(&key)
+                .filter(|(loaded_at, _)| loaded_at.elapsed() < ttl)
+                .map(|(_, values)| values.clone())
+        });
+
+        if let Some(values) = cached {
+            return Ok(values);
+        }
+
+        let values = self.run(labels, conn)?;
+        if let Ok(mut cache) = CACHE.lock() {
+            let _ = cache.insert(key, (Instant::now(), values.clone()));
+        }
+
+        Ok(values)
+    }
+
+    /// Run the processor, after rendering its configuration using the global
+    /// variables available to a task with the provided labels, and parse its
+    /// output.
+    fn run(&self, labels: &[String], conn: &PgConnection) -> Result<Vec<String>, Box<dyn Error>> {
+        let global_variables: Vec<GlobalVariable> = GlobalVariable::all().load(conn)?;
+        let global = global_variables
+            .iter()
+            .filter(|v| v.is_available_to(labels))
+            .map(|v| (v.key.as_str(), v.value.as_str()))
+            .collect::<HashMap<_, _>>();
+
+        let mut context = Context::new();
+        context.insert("global", &global);
+
+        let mut processor = serde_json::to_value(&self.processor)?;
+        render(&mut processor, &context)?;
+
+        let processor: Processor = serde_json::from_value(processor)?;
+        let output = processor
+            .run(&ProcessorContext::new()?)
+            .map_err(|err| format!("options processor failed: {}", err))?;
+
+        output.map_or_else(|| Ok(vec![]), |output| parse(&output).map_err(Into::into))
+    }
+}
+
+/// Render all string values in the provided processor configuration as
+/// templates.
+fn render(value: &mut Value, context: &Context) -> Result<(), String> {
+    match value {
+        Value::String(string) => {
+            *string = Tera::one_off(string, context.clone(), false)
+                .map_err(|err| format!("options processor template error: {}", err))?;
+        }
+        Value::Array(values) => values.iter_mut().try_for_each(|v| render(v, context))?,
+        Value::Object(map) => map.values_mut().try_for_each(|v| render(v, context))?,
+        _ => {}
+    };
+
+    Ok(())
+}
+
+/// Parse the output of the processor into a list of values.
+fn parse(output: &str) -> Result<Vec<String>, String> {
+    let values = match serde_json::from_str(output) {
+        Ok(Value::Array(values)) => values,
+        _ => {
+            return Ok(output
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(ToOwned::to_owned)
+                .collect())
+        }
+    };
+
+    let mut options = vec![];
+    for value in values {
+        let value = match value {
+            Value::Object(map) if map.len() == 1 => map.into_iter().map(|(_, v)| v).next(),
+            Value::Object(_) => return Err("options rows must contain a single field".to_owned()),
+            value => Some(value),
+        };
+
+        match value {
+            None | Some(Value::Null) => {}
+            Some(Value::String(string)) => options.push(string),
+            Some(value) => options.push(value.to_string()),
+        }
+    }
+
+    Ok(options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_lines() {
+        let options = parse("acme\n  globex \n\ninitech\n").unwrap();
+
+        assert_eq!(options, vec!["acme", "globex", "initech"]);
+    }
+
+    #[test]
+    fn test_parse_json() {
+        let options = parse(r#"[{"name":"acme"},{"name":null},{"name":42}]"#).unwrap();
+        assert_eq!(options, vec!["acme", "42"]);
+
+        let options = parse(r#"["acme", true]"#).unwrap();
+        assert_eq!(options, vec!["acme", "true"]);
+
+        assert!(parse(r#"[{"id":1,"name":"acme"}]"#).is_err());
+    }
+}
@@ -6,6 +6,17 @@ table! {
         name -> Text,
         description -> Nullable<Text>,
         labels -> Array<Text>,
+        timeout_seconds -> Nullable<Integer>,
+    }
+}
+
+table! {
+    task_versions (id) {
+        id -> Integer,
+        version -> Integer,
+        definition -> Jsonb,
+        created_at -> Timestamp,
+        task_id -> Integer,
     }
 }
 
@@ -17,6 +28,12 @@ table! {
         processor -> Jsonb,
         position -> Integer,
         task_id -> Integer,
+        timeout_seconds -> Nullable<Integer>,
+        retry_policy -> Nullable<Jsonb>,
+        depends_on -> Nullable<Array<Text>>,
+        condition -> Nullable<Text>,
+        run_when -> crate::resources::StepRunWhenMapping,
+        for_each -> Nullable<Text>,
     }
 }
 
@@ -32,6 +49,52 @@ table! {
         status -> crate::resources::JobStepStatusMapping,
         output -> Nullable<Text>,
         job_id -> Integer,
+        timeout_seconds -> Nullable<Integer>,
+        retry_policy -> Nullable<Jsonb>,
+        depends_on -> Nullable<Array<Text>>,
+        condition -> Nullable<Text>,
+        run_when -> crate::resources::StepRunWhenMapping,
+        for_each -> Nullable<Text>,
+        parent_id -> Nullable<Integer>,
+        item -> Nullable<Jsonb>,
+        formalized_processor -> Nullable<Jsonb>,
+    }
+}
+
+table! {
+    job_step_attempts (id) {
+        id -> Integer,
+        attempt -> Integer,
+        started_at -> Timestamp,
+        finished_at -> Timestamp,
+        status -> crate::resources::JobStepStatusMapping,
+        output -> Nullable<Text>,
+        job_step_id -> Integer,
+    }
+}
+
+table! {
+    job_step_approvals (id) {
+        id -> Integer,
+        job_step_id -> Integer,
+        privilege -> Text,
+        message -> Nullable<Text>,
+        requested_by -> Nullable<Integer>,
+        requested_at -> Timestamp,
+        approved -> Nullable<Bool>,
+        decided_by -> Nullable<Integer>,
+        decided_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    job_step_logs (id) {
+        id -> Integer,
+        position -> Integer,
+        stream -> crate::resources::JobStepLogStreamMapping,
+        line -> Text,
+        created_at -> Timestamp,
+        job_step_id -> Integer,
     }
 }
 
@@ -41,6 +104,8 @@ table! {
         key -> Text,
         value -> Bytea,
         job_id -> Integer,
+        secret -> Bool,
+        key_id -> Text,
     }
 }
 
@@ -51,6 +116,16 @@ table! {
         description -> Nullable<Text>,
         status -> crate::resources::JobStatusMapping,
         task_reference -> Nullable<Integer>,
+        run_at -> Nullable<Timestamp>,
+        timeout_seconds -> Nullable<Integer>,
+        worker_id -> Nullable<Text>,
+        heartbeat_at -> Nullable<Timestamp>,
+        status_reason -> Nullable<Text>,
+        parent_id -> Nullable<Integer>,
+        requested_by -> Nullable<Integer>,
+        task_version_id -> Nullable<Integer>,
+        resolve_variables -> Bool,
+        provides_variable -> Nullable<Text>,
     }
 }
 
@@ -63,6 +138,29 @@ table! {
         default_value -> Nullable<Text>,
         example_value -> Nullable<Text>,
         task_id -> Integer,
+        kind -> crate::resources::VariableKindMapping,
+        constraints -> Nullable<Jsonb>,
+        optional -> Bool,
+        secret -> Bool,
+    }
+}
+
+table! {
+    schedules (id) {
+        id -> Integer,
+        expression -> Text,
+        next_run_at -> Timestamp,
+        task_id -> Integer,
+    }
+}
+
+table! {
+    schedule_variables (id) {
+        id -> Integer,
+        key -> Text,
+        value -> Bytea,
+        schedule_id -> Integer,
+        key_id -> Text,
     }
 }
 
@@ -79,6 +177,12 @@ table! {
         id -> Integer,
         key -> Text,
         value -> Bytea,
+        secret -> Bool,
+        description -> Nullable<Text>,
+        labels -> Array<Text>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        key_id -> Text,
     }
 }
 
@@ -92,18 +196,31 @@ table! {
 
 joinable!(steps -> tasks (task_id));
 joinable!(job_steps -> jobs (job_id));
+joinable!(job_step_attempts -> job_steps (job_step_id));
+joinable!(job_step_approvals -> job_steps (job_step_id));
+joinable!(job_step_logs -> job_steps (job_step_id));
 joinable!(job_variables -> jobs (job_id));
 joinable!(jobs -> tasks (task_reference));
+joinable!(jobs -> task_versions (task_version_id));
+joinable!(task_versions -> tasks (task_id));
 joinable!(variables -> tasks (task_id));
+joinable!(schedules -> tasks (task_id));
+joinable!(schedule_variables -> schedules (schedule_id));
 joinable!(variable_advertisements -> steps (step_id));
 
 allow_tables_to_appear_in_same_query!(
     tasks,
+    task_versions,
     steps,
     job_steps,
+    job_step_attempts,
+    job_step_approvals,
+    job_step_logs,
     job_variables,
     jobs,
     variables,
+    schedules,
+    schedule_variables,
     variable_advertisements,
     global_variables,
     sessions,
@@ -1,7 +1,10 @@
-use crate::graphql::{MutationRoot, QueryRoot, Schema};
+use crate::graphql::{
+    MutationRoot, QueryRoot, Schema, SubscriptionMutation, SubscriptionRoot, SubscriptionSchema,
+};
 use crate::handlers;
 use crate::middleware::RemoveContentLengthHeader;
 use crate::models::Session;
+use crate::subscription::Broker;
 use actix_files::Files;
 use actix_web::error::BlockingError;
 use actix_web::{
@@ -44,15 +47,25 @@ pub(crate) enum ServerError {
     Internal(String),
 }
 
-impl fmt::Display for ServerError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let message = match self {
+impl ServerError {
+    /// The human-readable error message, without any surrounding GraphQL
+    /// error structure.
+    pub(crate) fn message(&self) -> String {
+        match self {
             ServerError::Authentication => "Unauthorized".to_owned(),
             ServerError::Json(err) => err.to_string(),
             ServerError::Internal(string) => string.to_owned(),
-        };
+        }
+    }
+}
 
-        write!(f, r#"{{ "errors": [{{ "message": "{}" }}] }}"#, message)
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            r#"{{ "errors": [{{ "message": "{}" }}] }}"#,
+            self.message()
+        )
     }
 }
 
@@ -106,24 +119,31 @@ pub(crate) struct State {
 
 pub(crate) struct Server {
     state: State,
+    broker: Broker,
 }
 
 impl Server {
     pub(crate) fn from_environment() -> Result<Self, Box<dyn Error>> {
         let database_url = env::var("DATABASE_URL")?;
-        let pool = Pool::new(ConnectionManager::new(database_url))?;
+        let pool = Pool::new(ConnectionManager::new(database_url.as_str()))?;
 
         crate::embedded_migrations::run(&pool.get()?)?;
 
         Ok(Self {
             state: State { pool },
+            broker: Broker::start(database_url),
         })
     }
 
     pub(crate) fn run_to_completion(self) -> Result<(), Box<dyn Error>> {
         let bind = env::var("SERVER_BIND").unwrap_or_else(|_| "0.0.0.0:8000".to_owned());
         let schema = Arc::new(Schema::new(QueryRoot, MutationRoot));
+        let subscription_schema = Arc::new(SubscriptionSchema::new(
+            SubscriptionRoot,
+            SubscriptionMutation,
+        ));
         let state = Arc::new(self.state);
+        let broker = self.broker;
 
         let server = HttpServer::new(move || {
             let root = env::var("SERVER_ROOT").unwrap_or_else(|_| "/public".to_owned());
@@ -139,8 +159,14 @@ impl Server {
                 .wrap(RemoveContentLengthHeader)
                 .data(state.clone())
                 .data(schema.clone())
+                .data(subscription_schema.clone())
+                .data(broker.clone())
                 .route("/graphql/playground", web::get().to(handlers::playground))
                 .route("/graphql/graphiql", web::get().to(handlers::graphiql))
+                .route(
+                    "/graphql/subscriptions",
+                    web::get().to(handlers::subscriptions),
+                )
                 .route("/graphql", web::get().to_async(handlers::graphql))
                 .route("/graphql", web::post().to_async(handlers::graphql))
                 .route("/health", web::get().to(handlers::health))
@@ -0,0 +1,535 @@
+//! GraphQL subscriptions, served over a WebSocket connection.
+//!
+//! The server implements the `graphql-ws` protocol, as used by GraphQL
+//! Playground and most GraphQL clients. A client starts a subscription by
+//! sending a `start` message containing a subscription document, after which
+//! the server sends a `data` message every time the subscribed data changes.
+//!
+//! Juniper does not support subscriptions, so instead each subscription is
+//! resolved as a query against the [`SubscriptionSchema`]. The [`Broker`]
+//! listens for database notifications (see [`crate::notification`]), and
+//! forwards them to all connected sessions. A session then resolves the
+//! subscriptions that apply to the changed job again, and only sends the
+//! result to the client if it differs from the previously sent result.
+
+use crate::graphql::SubscriptionSchema;
+use crate::handlers::authenticate;
+use crate::notification::{Listener, Notification};
+use crate::server::{RequestState, ServerError, State};
+use actix::fut::{self, wrap_future};
+use actix::prelude::*;
+use actix_web::web::block;
+use actix_web_actors::ws;
+use juniper::http::GraphQLRequest;
+use juniper::InputValue;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// The WebSocket sub-protocol implemented by the server.
+pub(crate) const PROTOCOL: &str = "graphql-ws";
+
+/// The interval at which keep-alive messages are sent to the client.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The maximum time the broker waits for a single notification.
+const LISTEN_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The time the broker waits before reconnecting to the database, after its
+/// connection failed.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+impl Message for Notification {
+    type Result = ();
+}
+
+/// Forwards database notifications to all connected subscription sessions.
+#[derive(Clone)]
+pub(crate) struct Broker {
+    sessions: Arc<Mutex<Vec<Recipient<Notification>>>>,
+}
+
+impl Broker {
+    /// Start listening for database notifications in a background thread.
+    ///
+    /// If the database connection fails, the broker reports the error, and
+    /// reconnects after a short delay.
+    pub(crate) fn start(database_url: String) -> Self {
+        let sessions = Arc::new(Mutex::new(vec![]));
+        let broker = Self {
+            sessions: sessions.clone(),
+        };
+
+        let _ = thread::spawn(move || loop {
+            if let Err(err) = forward(&database_url, &sessions) {
+                println!("subscription broker failed: {}", err);
+            }
+
+            thread::sleep(RECONNECT_INTERVAL);
+        });
+
+        broker
+    }
+
+    /// Start forwarding notifications to the provided session.
+    ///
+    /// The session is removed from the broker once it stops.
+    fn subscribe(&self, session: Recipient<Notification>) {
+        if let Ok(mut sessions) = self.sessions.lock() {
+            sessions.push(session)
+        }
+    }
+}
+
+/// Listen for notifications, and forward them to all sessions, dropping any
+/// session that stopped in the meantime.
+///
+/// This function only returns if an error occurred.
+fn forward(
+    database_url: &str,
+    sessions: &Mutex<Vec<Recipient<Notification>>>,
+) -> Result<(), Box<dyn Error>> {
+    let listener = Listener::new(database_url)?;
+
+    loop {
+        if let Some(notification) = listener.wait_for(LISTEN_TIMEOUT, |_| true)? {
+            let mut sessions = sessions.lock().map_err(|_| "poisoned session lock")?;
+            sessions.retain(|session| session.do_send(notification).is_ok());
+        }
+    }
+}
+
+/// A message sent by the client.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    ConnectionInit { payload: Option<ConnectionParams> },
+    Start { id: String, payload: Operation },
+    Stop { id: String },
+    ConnectionTerminate,
+}
+
+/// The parameters a client can provide when initializing the connection.
+#[derive(Debug, Deserialize)]
+struct ConnectionParams {
+    /// The session token, used instead of the `Authorization` header.
+    authorization: Option<String>,
+}
+
+/// The GraphQL operation to subscribe to.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Operation {
+    query: String,
+    operation_name: Option<String>,
+    variables: Option<InputValue>,
+}
+
+/// A message sent by the server.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage<'a> {
+    ConnectionAck,
+    ConnectionError {
+        payload: ErrorPayload,
+    },
+    Data {
+        id: &'a str,
+        payload: &'a Value,
+    },
+    Error {
+        id: &'a str,
+        payload: Vec<ErrorPayload>,
+    },
+    Complete {
+        id: &'a str,
+    },
+    Ka,
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorPayload {
+    message: String,
+}
+
+/// The context in which subscriptions are resolved.
+///
+/// Next to the regular request state, it records the IDs of the jobs that are
+/// resolved, so that a subscription is only resolved again once one of those
+/// jobs changes.
+pub(crate) struct SubscriptionState {
+    pub(crate) request: RequestState,
+    job_ids: RefCell<HashSet<i32>>,
+}
+
+impl SubscriptionState {
+    fn new(request: RequestState) -> Self {
+        Self {
+            request,
+            job_ids: RefCell::new(HashSet::new()),
+        }
+    }
+
+    /// Record that the subscription being resolved applies to the job with
+    /// the provided ID.
+    pub(crate) fn watch_job(&self, id: i32) {
+        let _ = self.job_ids.borrow_mut().insert(id);
+    }
+}
+
+/// An active subscription of a session.
+struct Subscription {
+    request: Arc<GraphQLRequest>,
+
+    /// The IDs of the jobs to which the subscription applied the last time it
+    /// was resolved.
+    job_ids: HashSet<i32>,
+
+    /// The last result sent to the client, if any.
+    last: Option<Value>,
+}
+
+/// A single WebSocket connection, which can have multiple active
+/// subscriptions.
+pub(crate) struct Session {
+    state: Arc<State>,
+    schema: Arc<SubscriptionSchema>,
+    broker: Broker,
+    token: Option<String>,
+    subscriptions: HashMap<String, Subscription>,
+}
+
+impl Session {
+    pub(crate) fn new(
+        state: Arc<State>,
+        schema: Arc<SubscriptionSchema>,
+        broker: Broker,
+        token: Option<String>,
+    ) -> Self {
+        Self {
+            state,
+            schema,
+            broker,
+            token,
+            subscriptions: HashMap::new(),
+        }
+    }
+
+    fn handle_message(&mut self, message: ClientMessage, ctx: &mut ws::WebsocketContext<Self>) {
+        use ClientMessage::*;
+
+        match message {
+            ConnectionInit { payload } => {
+                if let Some(token) = payload.and_then(|p| p.authorization) {
+                    self.token = Some(token);
+                }
+
+                send(ctx, &ServerMessage::ConnectionAck)
+            }
+            Start { id, payload } => {
+                let request = GraphQLRequest::new(
+                    as_query(&payload.query),
+                    payload.operation_name,
+                    payload.variables,
+                );
+
+                let subscription = Subscription {
+                    request: Arc::new(request),
+                    job_ids: HashSet::new(),
+                    last: None,
+                };
+
+                let _ = self.subscriptions.insert(id.clone(), subscription);
+                self.resolve(id, ctx)
+            }
+            Stop { id } => {
+                if self.subscriptions.remove(&id).is_some() {
+                    send(ctx, &ServerMessage::Complete { id: &id })
+                }
+            }
+            ConnectionTerminate => ctx.stop(),
+        }
+    }
+
+    /// Resolve the subscription in a background thread, and publish the
+    /// result to the client.
+    fn resolve(&self, id: String, ctx: &mut ws::WebsocketContext<Self>) {
+        let request = match self.subscriptions.get(&id) {
+            None => return,
+            Some(subscription) => subscription.request.clone(),
+        };
+
+        let state = self.state.clone();
+        let schema = self.schema.clone();
+        let token = self.token.clone();
+
+        let response = block(move || -> Result<_, ServerError> {
+            let conn = state.pool.get()?;
+            let session = match token {
+                None => None,
+                Some(token) => Some(authenticate(&token, &conn)?),
+            };
+
+            let state = SubscriptionState::new(RequestState::new(conn, session));
+            let payload = serde_json::to_value(&request.execute(&schema, &state))?;
+
+            Ok((payload, state.job_ids.into_inner()))
+        });
+
+        let _ = ctx.spawn(
+            wrap_future(response).then(move |result, session: &mut Self, ctx| {
+                session.publish(&id, result.map_err(Into::into), ctx);
+                fut::ok(())
+            }),
+        );
+    }
+
+    /// Send the result of a subscription to the client, unless the result is
+    /// unchanged since the last time it was sent.
+    ///
+    /// The subscription is updated to apply to the jobs resolved as part of
+    /// the result.
+    ///
+    /// If the subscription could not be resolved, an error is sent, and the
+    /// subscription is removed.
+    fn publish(
+        &mut self,
+        id: &str,
+        result: Result<(Value, HashSet<i32>), ServerError>,
+        ctx: &mut ws::WebsocketContext<Self>,
+    ) {
+        let subscription = match self.subscriptions.get_mut(id) {
+            None => return,
+            Some(subscription) => subscription,
+        };
+
+        match result {
+            Ok((payload, job_ids)) => {
+                subscription.job_ids = job_ids;
+                if subscription.last.as_ref() == Some(&payload) {
+                    return;
+                }
+
+                send(
+                    ctx,
+                    &ServerMessage::Data {
+                        id,
+                        payload: &payload,
+                    },
+                );
+                subscription.last = Some(payload);
+            }
+            Err(err) => {
+                let payload = vec![ErrorPayload {
+                    message: err.message(),
+                }];
+
+                send(ctx, &ServerMessage::Error { id, payload });
+                let _ = self.subscriptions.remove(id);
+            }
+        }
+    }
+}
+
+impl Actor for Session {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.broker.subscribe(ctx.address().recipient());
+
+        let _ = ctx.run_interval(KEEP_ALIVE_INTERVAL, |_, ctx| send(ctx, &ServerMessage::Ka));
+    }
+}
+
+impl Handler<Notification> for Session {
+    type Result = ();
+
+    fn handle(&mut self, notification: Notification, ctx: &mut Self::Context) {
+        let job_id = notification.job_id();
+        let ids = self
+            .subscriptions
+            .iter()
+            .filter(|(_, subscription)| subscription.job_ids.contains(&job_id))
+            .map(|(id, _)| id.clone())
+            .collect::<Vec<_>>();
+
+        ids.into_iter().for_each(|id| self.resolve(id, ctx));
+    }
+}
+
+impl StreamHandler<ws::Message, ws::ProtocolError> for Session {
+    fn handle(&mut self, message: ws::Message, ctx: &mut Self::Context) {
+        match message {
+            ws::Message::Text(text) => match serde_json::from_str(&text) {
+                Ok(message) => self.handle_message(message, ctx),
+                Err(err) => {
+                    let payload = ErrorPayload {
+                        message: err.to_string(),
+                    };
+
+                    send(ctx, &ServerMessage::ConnectionError { payload })
+                }
+            },
+            ws::Message::Ping(message) => ctx.pong(&message),
+            ws::Message::Close(_) => ctx.stop(),
+            ws::Message::Binary(_) | ws::Message::Pong(_) | ws::Message::Nop => {}
+        }
+    }
+}
+
+/// Serialize the message, and send it to the client.
+fn send(ctx: &mut ws::WebsocketContext<Session>, message: &ServerMessage<'_>) {
+    if let Ok(text) = serde_json::to_string(message) {
+        ctx.text(text)
+    }
+}
+
+/// Turn a subscription document into a query document, so that it can be
+/// resolved by Juniper, which does not know about subscription operations.
+///
+/// The document is tokenized, and the `subscription` keyword of each
+/// operation definition is replaced by `query`. Names, strings and comments
+/// that happen to read "subscription" are left untouched.
+fn as_query(document: &str) -> String {
+    let bytes = document.as_bytes();
+    let mut query = String::with_capacity(document.len());
+    let mut copied = 0;
+
+    // The nesting level of braces, parentheses and brackets, and whether the
+    // next token starts a new definition.
+    let mut depth = 0_usize;
+    let mut definition_start = true;
+
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'#' => {
+                while i < bytes.len() && bytes[i] != b'\n' && bytes[i] != b'\r' {
+                    i += 1;
+                }
+            }
+            b'"' if bytes[i..].starts_with(b"\"\"\"") => {
+                i += 3;
+                while i < bytes.len() && !bytes[i..].starts_with(b"\"\"\"") {
+                    let escaped = bytes[i..].starts_with(b"\\\"\"\"");
+                    i += if escaped { 4 } else { 1 };
+                }
+
+                i += 3;
+                continue;
+            }
+            b'"' => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != b'"' && bytes[i] != b'\n' {
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                }
+            }
+            b'{' | b'(' | b'[' => {
+                depth += 1;
+                definition_start = false;
+            }
+            b'}' => {
+                depth = depth.saturating_sub(1);
+                definition_start = depth == 0;
+            }
+            b')' | b']' => {
+                depth = depth.saturating_sub(1);
+                definition_start = false;
+            }
+            byte if byte == b'_' || byte.is_ascii_alphabetic() => {
+                let start = i;
+                while i < bytes.len() && (bytes[i] == b'_' || bytes[i].is_ascii_alphanumeric()) {
+                    i += 1;
+                }
+
+                if definition_start && &document[start..i] == "subscription" {
+                    query.push_str(&document[copied..start]);
+                    query.push_str("query");
+                    copied = i;
+                }
+
+                definition_start = false;
+                continue;
+            }
+            byte if byte.is_ascii_whitespace() || byte == b',' => {}
+            _ => definition_start = false,
+        }
+
+        i += 1;
+    }
+
+    query.push_str(&document[copied..]);
+    query
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_as_query_subscription() {
+        let document = "\n  subscription JobUpdated($id: ID!) { jobUpdated(id: $id) { id } }";
+
+        assert_eq!(
+            as_query(document),
+            "\n  query JobUpdated($id: ID!) { jobUpdated(id: $id) { id } }"
+        )
+    }
+
+    #[test]
+    fn test_as_query_comments_and_fragments() {
+        let document = r#"
+            # subscription to a job
+            fragment Job on Job { id }
+            subscription { jobUpdated(id: "subscription") { ...Job } }
+        "#;
+
+        assert_eq!(
+            as_query(document),
+            r#"
+            # subscription to a job
+            fragment Job on Job { id }
+            query { jobUpdated(id: "subscription") { ...Job } }
+        "#
+        )
+    }
+
+    #[test]
+    fn test_as_query_multiple_operations() {
+        let document = "subscription A { a: jobUpdated(id: 1) { id } }\n\
+                        subscription B($subscription: ID!) { jobUpdated(id: $subscription) { id } }";
+
+        assert_eq!(
+            as_query(document),
+            "query A { a: jobUpdated(id: 1) { id } }\n\
+             query B($subscription: ID!) { jobUpdated(id: $subscription) { id } }"
+        )
+    }
+
+    #[test]
+    fn test_as_query_operation_named_subscription() {
+        let document = "query subscription { jobUpdated(id: 1) { id } }";
+
+        assert_eq!(as_query(document), document)
+    }
+
+    #[test]
+    fn test_as_query_anonymous_subscription() {
+        assert_eq!(
+            as_query("subscription { jobUpdated(id: 1) { id } }"),
+            "query { jobUpdated(id: 1) { id } }"
+        )
+    }
+
+    #[test]
+    fn test_as_query_query() {
+        let document = "query { jobUpdated(id: 1) { id } }";
+
+        assert_eq!(as_query(document), document)
+    }
+}
@@ -0,0 +1,293 @@
+//! Declarative task management, using task definitions stored as files.
+//!
+//! `automaat export DIR` writes the [`Definition`] of every task to a separate
+//! YAML file in the provided directory.
+//!
+//! `automaat apply DIR` reads all YAML (`.yaml`, `.yml`) and TOML (`.toml`)
+//! files in the provided directory, and creates or updates the tasks they
+//! define. Tasks that are no longer defined are kept, unless pruning is
+//! enabled. A dry run prints the changes, without applying them.
+
+use crate::resources::{Change, Definition, NewTask, Task};
+use crate::schema::tasks;
+use diesel::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::path::Path;
+use std::{env, error::Error, fs};
+
+/// The options used when applying task definitions.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct ApplyOptions {
+    /// Print the changes, without applying them.
+    pub(crate) dry_run: bool,
+
+    /// Delete any task that is not defined in the applied files.
+    pub(crate) prune: bool,
+}
+
+/// A single change to the tasks in the database, needed to match the applied
+/// definitions.
+enum Action<'a> {
+    Create(&'a Definition),
+    Update(&'a Definition, Vec<Change>),
+    Delete(Task),
+}
+
+/// Write the definition of every task to a YAML file in the provided
+/// directory.
+///
+/// The directory is created if it doesn't exist yet. Existing files with the
+/// same name are overwritten.
+pub(crate) fn export(dir: &Path) -> Result<(), Box<dyn Error>> {
+    let conn = connect()?;
+    let tasks: Vec<Task> = tasks::table.order(tasks::name.asc()).load(&conn)?;
+
+    fs::create_dir_all(dir)?;
+
+    let mut paths = HashSet::new();
+    for task in tasks {
+        let definition = Definition::load(&task, &conn)?;
+
+        // Task names are unique, but their file names might not be.
+        let stem = file_stem(&task.name);
+        let mut path = dir.join(format!("{}.yaml", stem));
+        if paths.contains(&path) {
+            path = dir.join(format!("{}-{}.yaml", stem, task.id));
+        }
+
+        fs::write(&path, serde_yaml::to_string(&definition)?)?;
+        println!("exported {} to {}", task.name, path.display());
+
+        let _ = paths.insert(path);
+    }
+
+    Ok(())
+}
+
+/// Create or update the tasks defined in the files in the provided directory.
+///
+/// All definitions are validated before any change is made. Each task is
+/// persisted in its own transaction, so a database error can leave the
+/// already applied tasks in place. Applying the same files again is safe.
+pub(crate) fn apply(dir: &Path, options: ApplyOptions) -> Result<(), Box<dyn Error>> {
+    let definitions = load_definitions(dir)?;
+    let conn = connect()?;
+    let tasks: Vec<Task> = tasks::table.order(tasks::name.asc()).load(&conn)?;
+
+    let mut existing = tasks
+        .into_iter()
+        .map(|task| (task.name.clone(), task))
+        .collect::<HashMap<_, _>>();
+
+    let mut actions = vec![];
+    let (mut created, mut updated, mut unchanged) = (0, 0, 0);
+    for definition in &definitions {
+        match existing.remove(&definition.name) {
+            None => {
+                created += 1;
+                actions.push(Action::Create(definition));
+            }
+            Some(task) => {
+                let changes = Definition::load(&task, &conn)?.diff(definition);
+                if changes.is_empty() {
+                    unchanged += 1;
+                } else {
+                    updated += 1;
+                    actions.push(Action::Update(definition, changes));
+                }
+            }
+        }
+    }
+
+    if options.prune {
+        let mut pruned = existing
+            .into_iter()
+            .map(|(_, task)| task)
+            .collect::<Vec<_>>();
+        pruned.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+
+        actions.extend(pruned.into_iter().map(Action::Delete));
+    }
+
+    for action in &actions {
+        print_action(action);
+    }
+
+    let deleted = actions.len() - created - updated;
+    println!(
+        "{} to create, {} to update, {} to delete, {} unchanged",
+        created, updated, deleted, unchanged
+    );
+
+    if options.dry_run {
+        println!("dry run, no changes applied");
+        return Ok(());
+    }
+
+    let mut deleted = vec![];
+    for action in actions {
+        match action {
+            Action::Create(definition) | Action::Update(definition, _) => {
+                let _ = definition.create_or_update(&conn)?;
+            }
+            Action::Delete(task) => deleted.push(task),
+        }
+    }
+
+    delete_tasks(deleted, &conn)
+}
+
+/// Delete the provided tasks.
+///
+/// A task can't be deleted while another task runs it as a sub-job, so tasks
+/// are deleted once all tasks depending on them are deleted.
+fn delete_tasks(mut tasks: Vec<Task>, conn: &PgConnection) -> Result<(), Box<dyn Error>> {
+    while !tasks.is_empty() {
+        let count = tasks.len();
+        let mut error = None;
+
+        tasks.retain(|task| match task.delete(conn) {
+            Ok(()) => false,
+            Err(err) => {
+                error = Some(format!("unable to delete {}: {}", task.name, err));
+                true
+            }
+        });
+
+        // Stop if no task could be deleted in this round.
+        if tasks.len() == count {
+            if let Some(error) = error {
+                return Err(error.into());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn print_action(action: &Action<'_>) {
+    let format = |value: &Option<String>| value.clone().unwrap_or_else(|| "null".to_owned());
+
+    match action {
+        Action::Create(definition) => println!("+ {}", definition.name),
+        Action::Delete(task) => println!("- {}", task.name),
+        Action::Update(definition, changes) => {
+            println!("~ {}", definition.name);
+
+            for change in changes {
+                println!(
+                    "    {}: {} -> {}",
+                    change.path,
+                    format(&change.before),
+                    format(&change.after)
+                );
+            }
+        }
+    }
+}
+
+/// Read and validate all task definitions in the provided directory, sorted
+/// by file name.
+///
+/// Files with an unknown extension are ignored. An error is returned if any
+/// of the files is invalid, or if multiple files define the same task.
+fn load_definitions(dir: &Path) -> Result<Vec<Definition>, Box<dyn Error>> {
+    let mut paths = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    paths.sort_unstable();
+
+    let mut definitions = vec![];
+    let mut names = HashMap::new();
+    for path in paths {
+        let definition = match load_definition(&path) {
+            Ok(Some(definition)) => definition,
+            Ok(None) => continue,
+            Err(err) => return Err(format!("{}: {}", path.display(), err).into()),
+        };
+
+        if let Some(other) = names.insert(definition.name.clone(), path.clone()) {
+            return Err(format!(
+                "task {} is defined in both {} and {}",
+                definition.name,
+                other.display(),
+                path.display()
+            )
+            .into());
+        }
+
+        definitions.push(definition);
+    }
+
+    Ok(definitions)
+}
+
+/// Read and validate the task definition in the provided file, if it is a
+/// YAML or TOML file.
+fn load_definition(path: &Path) -> Result<Option<Definition>, Box<dyn Error>> {
+    let extension = path.extension().and_then(|ext| ext.to_str());
+
+    let definition: Definition = match extension {
+        Some("yaml") | Some("yml") => serde_yaml::from_str(&fs::read_to_string(path)?)?,
+        Some("toml") => toml::from_str(&fs::read_to_string(path)?)?,
+        _ => return Ok(None),
+    };
+
+    let _ = NewTask::try_from(&definition)?;
+    Ok(Some(definition))
+}
+
+fn connect() -> Result<PgConnection, Box<dyn Error>> {
+    let conn = PgConnection::establish(&env::var("DATABASE_URL")?)?;
+    crate::embedded_migrations::run(&conn)?;
+
+    Ok(conn)
+}
+
+/// Returns a file name (without extension) for the task with the provided
+/// name.
+fn file_stem(name: &str) -> String {
+    let stem = name
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .map(str::to_ascii_lowercase)
+        .collect::<Vec<_>>()
+        .join("-");
+
+    if stem.is_empty() {
+        "task".to_owned()
+    } else {
+        stem
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_stem() {
+        assert_eq!(file_stem("Deploy Service"), "deploy-service");
+        assert_eq!(file_stem("  Run: tests (v2) "), "run-tests-v2");
+        assert_eq!(file_stem("🚀"), "task");
+    }
+
+    #[test]
+    fn test_load_definition_yaml() {
+        let dir = env::temp_dir().join(format!("automaat-sync-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let path = dir.join("task.yaml");
+        fs::write(&path, "name: Task\nlabels: [ops]\n").unwrap();
+
+        let definition = load_definition(&path).unwrap().unwrap();
+        assert_eq!(definition.name, "Task");
+        assert_eq!(definition.labels, vec!["ops".to_owned()]);
+        assert!(definition.steps.is_empty());
+
+        assert!(load_definition(&dir.join("README.md")).unwrap().is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
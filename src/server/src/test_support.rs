@@ -0,0 +1,69 @@
+//! Helpers shared by the tests that need a database.
+//!
+//! These tests expect the database referenced by `DATABASE_URL` to be
+//! migrated, and run within a transaction that is never committed.
+
+use crate::resources::{NewStep, NewTask, NewVariable, Task};
+use crate::server::RequestState;
+use crate::Processor;
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use serde_json::Value;
+use std::convert::TryFrom;
+
+/// The URL of the test database.
+pub(crate) fn database_url() -> String {
+    std::env::var("DATABASE_URL").unwrap_or_else(|_| "postgres://postgres@localhost".to_owned())
+}
+
+/// Connect to the test database.
+pub(crate) fn connection() -> PgConnection {
+    PgConnection::establish(&database_url()).unwrap()
+}
+
+/// Returns the state of an unauthenticated request, of which the database
+/// connection runs within a transaction that is never committed.
+pub(crate) fn request_state() -> RequestState {
+    let pool = Pool::builder()
+        .max_size(1)
+        .build(ConnectionManager::<PgConnection>::new(database_url()))
+        .unwrap();
+
+    let conn = pool.get().unwrap();
+    conn.begin_test_transaction().unwrap();
+
+    RequestState::new(conn, None)
+}
+
+/// Create a task with the provided required variables, and steps with the
+/// provided names and processor configurations.
+pub(crate) fn create_task(
+    conn: &PgConnection,
+    name: &str,
+    variables: &[&str],
+    steps: &[(&str, Value)],
+) -> Task {
+    let mut task = NewTask::new(name, None, vec![]);
+
+    task.with_variables(
+        variables
+            .iter()
+            .map(|key| NewVariable::new(key, None, None, None, None).unwrap())
+            .collect(),
+    );
+
+    task.with_steps(
+        steps
+            .iter()
+            .enumerate()
+            .map(|(position, (name, processor))| {
+                let processor: Processor = serde_json::from_value(processor.clone()).unwrap();
+                let position = i32::try_from(position).unwrap();
+
+                NewStep::new(name, None, processor, position, None)
+            })
+            .collect(),
+    );
+
+    task.create(conn).unwrap()
+}
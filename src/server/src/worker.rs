@@ -1,11 +1,41 @@
-use crate::resources::Job;
+use crate::notification::{Listener, Notification};
+use crate::resources::{Job, JobRecovery, JobStatus, LogWriter, Schedule};
+use crate::server::DatabasePool;
+use automaat_core::{Canceller, Context};
 use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use std::convert::TryFrom;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
 use std::sync::Arc;
 use std::{env, error::Error, thread, time};
+use uuid::Uuid;
+
+/// The interval at which a running job is checked for cancellation.
+const CANCELLATION_POLL_INTERVAL: time::Duration = time::Duration::from_millis(500);
+
+/// The maximum time a worker waits for a notification about a new pending job,
+/// before checking the database for pending jobs.
+///
+/// Workers are notified of new jobs as soon as they are created, so this
+/// interval acts as a safety net for missed notifications. It also determines
+/// how often scheduled jobs are checked.
+const PENDING_JOB_POLL_INTERVAL: time::Duration = time::Duration::from_secs(1);
+
+/// The interval at which a worker reports that it is still running a job.
+const HEARTBEAT_INTERVAL: time::Duration = time::Duration::from_secs(10);
+
+/// The time after which a running job without a heartbeat is considered
+/// orphaned, and recovered by any of the workers.
+const HEARTBEAT_TIMEOUT: time::Duration = time::Duration::from_secs(60);
 
 pub(crate) struct Worker {
-    conn: PgConnection,
+    /// A unique ID of the worker, stored on the jobs it claims.
+    id: String,
+    pool: DatabasePool,
+    database_url: String,
+    concurrency: usize,
+    recovery: JobRecovery,
 }
 
 pub(crate) enum Event {
@@ -16,29 +46,130 @@ pub(crate) enum Event {
 
 impl Worker {
     /// Create a new worker instance.
-    pub(crate) fn from_environment() -> Result<Self, Box<dyn Error>> {
+    ///
+    /// The worker runs at most `concurrency` jobs in parallel. If no
+    /// concurrency is provided, the `WORKER_CONCURRENCY` environment variable
+    /// is used, which defaults to a single job at a time.
+    ///
+    /// Jobs orphaned by other workers are failed, unless the
+    /// `WORKER_ORPHAN_RECOVERY` environment variable is set to `requeue`.
+    pub(crate) fn from_environment(concurrency: Option<usize>) -> Result<Self, Box<dyn Error>> {
         let database_url = env::var("DATABASE_URL")?;
-        let conn = PgConnection::establish(&database_url)?;
+        let concurrency = match concurrency {
+            Some(concurrency) => concurrency,
+            None => match env::var("WORKER_CONCURRENCY") {
+                Ok(value) => parse_concurrency(&value)?,
+                Err(_) => 1,
+            },
+        };
+
+        let recovery = match env::var("WORKER_ORPHAN_RECOVERY") {
+            Ok(value) => value.parse()?,
+            Err(_) => JobRecovery::Fail,
+        };
+
+        // Each job runner holds on to a single connection at a time.
+        let pool = Pool::builder()
+            .max_size(u32::try_from(concurrency)?)
+            .build(ConnectionManager::new(database_url.as_str()))?;
 
-        crate::embedded_migrations::run(&conn)?;
+        crate::embedded_migrations::run(&pool.get()?)?;
 
-        Ok(Self { conn })
+        Ok(Self {
+            id: Uuid::new_v4().to_string(),
+            pool,
+            database_url,
+            concurrency,
+            recovery,
+        })
     }
 
-    /// Start polling for pending jobs and run them to completion.
+    /// Start waiting for pending jobs and run them to completion.
+    ///
+    /// The worker starts a job runner thread for each job it is allowed to run
+    /// in parallel. Pending jobs are locked while they are claimed, and
+    /// skipped by the other runners, so each job runs exactly once.
     ///
     /// This method blocks until a Unix `SIGINT` or `SIGTERM` signal is
-    /// received. When any of these signals are received, any running job runs
+    /// received. When any of these signals are received, all running jobs run
     /// to completion, before the method returns.
+    ///
+    /// If any of the runners fails, the other runners stop as well, once their
+    /// running job (if any) completes.
     pub(crate) fn run_to_completion(self) -> Result<(), Box<dyn Error>> {
         let running = Arc::new(AtomicBool::new(true));
         let closer = running.clone();
         ctrlc::set_handler(move || closer.store(false, Ordering::SeqCst))?;
 
+        let worker = Arc::new(self);
+        let runners = (0..worker.concurrency)
+            .map(|_| {
+                let worker = worker.clone();
+                let running = running.clone();
+
+                thread::spawn(move || {
+                    let result = worker.run_jobs(&running).map_err(|err| err.to_string());
+                    if result.is_err() {
+                        running.store(false, Ordering::SeqCst);
+                    }
+
+                    result
+                })
+            })
+            .collect::<Vec<_>>();
+
+        // Wait for all runners to stop, before reporting any errors.
+        let results = runners
+            .into_iter()
+            .map(|runner| {
+                runner
+                    .join()
+                    .unwrap_or_else(|_| Err("job runner panicked".to_owned()))
+            })
+            .collect::<Vec<_>>();
+
+        results
+            .into_iter()
+            .collect::<Result<(), _>>()
+            .map_err(Into::into)
+    }
+
+    /// Run jobs one by one, until the worker stops running.
+    ///
+    /// If no job is pending, the runner waits until it is notified of a new
+    /// pending job, or until the poll interval passes.
+    ///
+    /// Before checking for pending jobs, any orphaned jobs are recovered, and
+    /// any scheduled jobs or schedules that are due to run are turned into
+    /// pending jobs. If either fails, for example because the database is
+    /// temporarily unavailable, the error is reported, and the worker keeps
+    /// running.
+    fn run_jobs(&self, running: &AtomicBool) -> Result<(), Box<dyn Error>> {
+        let listener = Listener::new(&self.database_url)?;
+        let is_pending = |notification: &Notification| match notification {
+            Notification::JobStatus { status, .. } => *status == JobStatus::Pending,
+            _ => false,
+        };
+
         while running.load(Ordering::SeqCst) {
             use Event::*;
-            match self.run_single_job() {
-                NoPendingJob => thread::sleep(time::Duration::from_millis(100)),
+
+            let conn = self.pool.get()?;
+
+            if let Err(err) = self.recover_orphaned_jobs(&conn) {
+                println!("failed to recover orphaned jobs: {}", err);
+            }
+
+            if let Err(err) = self.enqueue_scheduled_jobs(&conn) {
+                println!("failed to enqueue scheduled jobs: {}", err);
+            }
+
+            match self.run_single_job(&conn) {
+                NoPendingJob => {
+                    // Return the connection to the pool while waiting.
+                    drop(conn);
+                    let _ = listener.wait_for(PENDING_JOB_POLL_INTERVAL, is_pending)?;
+                }
                 Done => {}
                 DatabaseError(err) => return Err(err.into()),
             };
@@ -47,26 +178,150 @@ impl Worker {
         Ok(())
     }
 
+    /// Recover any running jobs of which the worker stopped sending
+    /// heartbeats, for example because it crashed.
+    pub(crate) fn recover_orphaned_jobs(&self, conn: &PgConnection) -> Result<(), Box<dyn Error>> {
+        let recovered = Job::recover_orphaned(conn, HEARTBEAT_TIMEOUT, self.recovery)?;
+        if recovered > 0 {
+            println!("recovered {} orphaned job(s)", recovered);
+        }
+
+        Ok(())
+    }
+
+    /// Mark any scheduled jobs that are due as pending, and create new jobs
+    /// for any schedules that are due.
+    ///
+    /// If a job can't be created for a schedule, the error is reported, but
+    /// the worker keeps running.
+    pub(crate) fn enqueue_scheduled_jobs(&self, conn: &PgConnection) -> QueryResult<()> {
+        let _ = Job::enqueue_due_scheduled(conn)?;
+
+        conn.transaction(|| {
+            while let Some(mut schedule) = Schedule::find_next_unlocked_due(conn)? {
+                if let Err(err) = schedule.enqueue(conn) {
+                    println!("failed to enqueue schedule {}: {}", schedule.id, err);
+                }
+            }
+
+            Ok(())
+        })
+    }
+
     /// Find a pending job in the database, and run it to completion.
-    pub(crate) fn run_single_job(&self) -> Event {
+    pub(crate) fn run_single_job(&self, conn: &PgConnection) -> Event {
         use Event::*;
 
-        let result = self.conn.transaction(|| {
-            let mut job = match Job::find_next_unlocked_pending(&self.conn) {
-                Ok(Some(job)) => job,
-                Ok(None) => return Ok(NoPendingJob),
-                Err(err) => return Err(err),
-            };
-
-            job.as_running(&self.conn)?
-                .run(&self.conn)
-                .or_else(|_| job.as_failed(&self.conn).map(|_| ()))
-                .map(|_| Done)
+        // The job is claimed in its own transaction, so that the running state
+        // of the job and its steps is visible to others while the job runs.
+        let claim = conn.transaction(|| match Job::find_next_unlocked_pending(conn)? {
+            None => Ok(None),
+            Some(mut job) => job.as_running(conn, &self.id).map(Some),
         });
 
-        match result {
-            Ok(event) => event,
+        let mut job = match claim {
+            Ok(Some(job)) => job,
+            Ok(None) => return NoPendingJob,
+            Err(err) => return DatabaseError(err),
+        };
+
+        let result = Context::new()
+            .map_err(Into::into)
+            .and_then(|context| self.run_job(conn, &job, &context));
+
+        match result.or_else(|_| job.as_failed(conn).map(|_| ())) {
+            Ok(_) => Done,
             Err(err) => DatabaseError(err),
         }
     }
+
+    /// Run the job, while watching for the job to be cancelled, and sending
+    /// heartbeats.
+    ///
+    /// The output written by the job steps while running is stored using a
+    /// separate database connection, as the runner connection is in use while
+    /// the job runs.
+    fn run_job(
+        &self,
+        conn: &PgConnection,
+        job: &Job,
+        context: &Context,
+    ) -> Result<(), Box<dyn Error>> {
+        let (stop, watcher) = self.watch_job(job.id, context.canceller())?;
+        let log = LogWriter::start(PgConnection::establish(&self.database_url)?);
+        let result = job.run(conn, &self.database_url, context, &log.handle());
+
+        log.finish();
+        drop(stop);
+        let _ = watcher.join();
+
+        result
+    }
+
+    /// Spawn a thread that periodically checks if a job is cancelled, and if
+    /// so, cancels the job context, to interrupt any running processor.
+    ///
+    /// The thread also sends heartbeats for the job, so that other workers
+    /// don't consider the job orphaned.
+    ///
+    /// The thread uses its own database connection, as the runner connection
+    /// is in use while the job runs.
+    ///
+    /// The thread stops as soon as the returned sender is dropped.
+    fn watch_job(
+        &self,
+        job_id: i32,
+        canceller: Canceller,
+    ) -> Result<(mpsc::Sender<()>, thread::JoinHandle<()>), ConnectionError> {
+        let conn = PgConnection::establish(&self.database_url)?;
+        let worker_id = self.id.clone();
+        let (stop, stopped) = mpsc::channel();
+
+        let watcher = thread::spawn(move || {
+            let mut heartbeat = time::Instant::now();
+
+            while let Err(RecvTimeoutError::Timeout) =
+                stopped.recv_timeout(CANCELLATION_POLL_INTERVAL)
+            {
+                if Job::is_cancelled(job_id, &conn).unwrap_or(false) {
+                    return canceller.cancel();
+                }
+
+                if heartbeat.elapsed() >= HEARTBEAT_INTERVAL {
+                    if let Err(err) = Job::heartbeat(job_id, &worker_id, &conn) {
+                        println!("failed to send heartbeat for job {}: {}", job_id, err);
+                    }
+
+                    heartbeat = time::Instant::now();
+                }
+            }
+        });
+
+        Ok((stop, watcher))
+    }
+}
+
+/// Parse the number of jobs a worker is allowed to run in parallel.
+pub(crate) fn parse_concurrency(value: &str) -> Result<usize, Box<dyn Error>> {
+    match value.parse::<usize>() {
+        Ok(concurrency) if concurrency > 0 => Ok(concurrency),
+        _ => Err(format!("invalid concurrency: {}", value).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_concurrency() {
+        assert_eq!(parse_concurrency("4").unwrap(), 4);
+    }
+
+    #[test]
+    fn test_parse_concurrency_invalid() {
+        assert!(parse_concurrency("0").is_err());
+        assert!(parse_concurrency("-1").is_err());
+        assert!(parse_concurrency("many").is_err());
+    }
 }
@@ -34,7 +34,7 @@ impl<'a> Render for Header<'a> {
             .attr("class", "au-header")
             .children([
                 Statistic::new("tasks", self.stats.total_tasks).render(cx),
-                Statistic::disabled("scheduled").render(cx),
+                Statistic::new("waiting", self.stats.waiting_jobs).render(cx),
                 div(&cx).child(logo).finish(),
                 Statistic::new("running", self.stats.running_jobs).render(cx),
                 Statistic::new("failed", self.stats.failed_jobs).render(cx),
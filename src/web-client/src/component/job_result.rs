@@ -2,7 +2,7 @@
 
 use crate::model::job::{
     Job,
-    Status::{Failed, Succeeded},
+    Status::{Cancelled, Failed, Succeeded},
 };
 use crate::utils;
 use dodrio::bumpalo::collections::string::String as BString;
@@ -55,6 +55,7 @@ impl<'a, 'b, C> Views<'b> for JobResult<'a, C> {
         let title = match &self.job.status {
             Succeeded(_) => "Success!",
             Failed(_) => "Failed!",
+            Cancelled(_) => "Cancelled!",
             _ => unreachable!(),
         };
 
@@ -75,7 +76,7 @@ impl<'a, 'b, C> Views<'b> for JobResult<'a, C> {
         use dodrio::builder::*;
 
         let output = match &self.job.status {
-            Succeeded(output) | Failed(output) if output.text.is_some() => {
+            Succeeded(output) | Failed(output) | Cancelled(output) if output.text.is_some() => {
                 output.text.as_ref().unwrap_throw().clone()
             }
             _ => return div(&cx).finish(),
@@ -105,7 +106,7 @@ impl<'a, 'b, C> Views<'b> for JobResult<'a, C> {
         use dodrio::builder::*;
 
         let body = match &self.job.status {
-            Succeeded(string) | Failed(string) => string,
+            Succeeded(string) | Failed(string) | Cancelled(string) => string,
             _ => unreachable!(),
         };
 
@@ -126,6 +127,7 @@ impl<'a, C> Render for JobResult<'a, C> {
         let class = match &self.job.status {
             Succeeded(_) => "job-result success",
             Failed(_) => "job-result failed",
+            Cancelled(_) => "job-result cancelled",
             _ => unreachable!(),
         };
 
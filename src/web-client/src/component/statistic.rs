@@ -29,15 +29,6 @@ impl Statistic {
             enabled: true,
         }
     }
-
-    /// Create a new Statistic component that is disabled.
-    pub(crate) const fn disabled(name: &'static str) -> Self {
-        Self {
-            name,
-            value: None,
-            enabled: false,
-        }
-    }
 }
 
 impl Render for Statistic {
@@ -76,6 +76,9 @@ trait Views<'b> {
     /// The run button to start running a task.
     fn btn_run(&self, cx: &mut RenderContext<'b>) -> Node<'b>;
 
+    /// The abort button to cancel the active job while it is running.
+    fn btn_abort(&self, cx: &mut RenderContext<'b>, id: job::RemoteId) -> Node<'b>;
+
     /// The (disabled) "missing authorization" button.
     fn btn_unauthorized(&self, cx: &mut RenderContext<'b>) -> Node<'b>;
 
@@ -112,6 +115,15 @@ where
             if job.is_completed() {
                 let result = component::JobResult::<C>::new(job);
                 body = body.child(result.render(cx));
+            } else if job.status == job::Status::Waiting {
+                body = body.child(
+                    div(&cx)
+                        .attr("class", "waiting")
+                        .child(text(
+                            "This task is waiting for approval, before it continues running.",
+                        ))
+                        .finish(),
+                );
             }
         } else if !self.task.finished_jobs().is_empty() {
             let id = self.task.id();
@@ -166,8 +178,17 @@ where
         let action = if self.task.show_login {
             self.field_login(cx)
         } else {
+            let running_job = self
+                .task
+                .active_job()
+                .filter(|job| job.is_running())
+                .and_then(|job| job.remote_id.clone());
+
             match self.access_mode {
-                AccessMode::Ok => self.btn_run(cx),
+                AccessMode::Ok => match running_job {
+                    Some(id) => self.btn_abort(cx, id),
+                    None => self.btn_run(cx),
+                },
                 AccessMode::Unauthorized => self.btn_unauthorized(cx),
                 AccessMode::Unauthenticated => self.btn_authenticate(cx),
             }
@@ -250,6 +271,20 @@ where
             .finish()
     }
 
+    fn btn_abort(&self, cx: &mut RenderContext<'b>, id: job::RemoteId) -> Node<'b> {
+        use dodrio::builder::*;
+
+        button(&cx)
+            .attr("type", "button")
+            .attr("class", "abort")
+            .child(span(&cx).child(text("Abort Task ")).finish())
+            .child(span(&cx).child(i(&cx).finish()).finish())
+            .on("click", move |root, vdom, _event| {
+                C::abort(root, vdom, id.clone())
+            })
+            .finish()
+    }
+
     fn btn_unauthorized(&self, cx: &mut RenderContext<'b>) -> Node<'b> {
         use dodrio::builder::*;
 
@@ -294,7 +329,7 @@ where
                 let vdom2 = vdom.clone();
                 spawn_local({
                     C::run(root, vdom.clone(), id.clone(), map)
-                        .and_then(move |job_id| C::poll_result(tasks, vdom, job_id, id, client))
+                        .and_then(move |job_id| C::watch_result(tasks, vdom, job_id, id, client))
                         .and_then(move |_| C::render_task_details(vdom2))
                 });
 
@@ -2,8 +2,9 @@
 //!
 //! This component shows the name of a variable, along with the appropriate
 //! input field, depending on the variable properties (such as if it's required,
-//! if the types of values are constraint, etc.).
+//! if the types of values are constraint, the kind of the variable, etc.).
 
+use crate::graphql::fetch_task_details::VariableKind;
 use crate::model::variable::{self, ValueAdvertiser};
 use crate::router::Route;
 use crate::utils;
@@ -85,9 +86,19 @@ trait Views<'b> {
     /// A select field for when the variable can contain three or more values.
     fn select(&self, cx: &mut RenderContext<'b>, selection: &[&str]) -> Node<'b>;
 
-    /// A free-form text input field for when there are no value constraints
-    /// imposed on a variable.
-    fn input(&self, cx: &mut RenderContext<'b>) -> Node<'b>;
+    /// An input field for when there is no selection constraint imposed on a
+    /// variable.
+    ///
+    /// The type of the input field (such as `text`, `number` or `date`)
+    /// matches the kind of the variable, and any other constraints of the
+    /// variable are added as validation attributes.
+    ///
+    /// The value of a `password` input field is not stored in the location
+    /// query, so that secret values don't end up in the browser history.
+    fn input(&self, cx: &mut RenderContext<'b>, kind: &'static str) -> Node<'b>;
+
+    /// A free-form text area for multiline variables.
+    fn textarea(&self, cx: &mut RenderContext<'b>) -> Node<'b>;
 
     /// A variable field, which contains a label, and one of the defined field
     /// types.
@@ -110,7 +121,13 @@ impl<'a, 'b> Views<'b> for Variable<'a> {
         use dodrio::builder::*;
 
         let key = String::from_str_in(self.variable.key(), cx.bump).into_bump_str();
-        let label = label(&cx).child(text(key)).finish();
+        let mut label = label(&cx).child(text(key));
+
+        if self.variable.is_optional() {
+            label = label.child(span(&cx).child(text(" (optional)")).finish());
+        }
+
+        let label = label.finish();
 
         div(&cx)
             .attr("class", "variable-label")
@@ -227,12 +244,12 @@ impl<'a, 'b> Views<'b> for Variable<'a> {
             .finish()
     }
 
-    fn input(&self, cx: &mut RenderContext<'b>) -> Node<'b> {
+    fn input(&self, cx: &mut RenderContext<'b>, kind: &'static str) -> Node<'b> {
         use dodrio::builder::*;
 
         let key = String::from_str_in(self.variable.key(), cx.bump).into_bump_str();
         let mut attributes = vec![
-            attr("type", "text"),
+            attr("type", kind),
             attr("name", key),
             attr("aria-label", key),
             attr("value", self.value(cx.bump)),
@@ -242,9 +259,42 @@ impl<'a, 'b> Views<'b> for Variable<'a> {
             attributes.push(attr("placeholder", value))
         };
 
+        if kind == "number" {
+            attributes.push(attr("step", "any"));
+        }
+
+        let (min, max) = self.variable.range();
+        if let Some(min) = min {
+            attributes.push(attr("min", format!(in cx.bump, "{}", min).into_bump_str()));
+        }
+
+        if let Some(max) = max {
+            attributes.push(attr("max", format!(in cx.bump, "{}", max).into_bump_str()));
+        }
+
+        let (min_length, max_length) = self.variable.length();
+        if let Some(length) = min_length {
+            let length = format!(in cx.bump, "{}", length).into_bump_str();
+            attributes.push(attr("minlength", length));
+        }
+
+        if let Some(length) = max_length {
+            let length = format!(in cx.bump, "{}", length).into_bump_str();
+            attributes.push(attr("maxlength", length));
+        }
+
+        if let Some(pattern) = self.variable.pattern() {
+            let pattern = String::from_str_in(pattern, cx.bump).into_bump_str();
+            attributes.push(attr("pattern", pattern));
+        }
+
         let input = input(&cx)
             .attributes(attributes)
             .on("input", move |_root, _vdom, event| {
+                if kind == "password" {
+                    return;
+                }
+
                 let target = event.target().unwrap_throw();
                 utils::input_to_location_query(target).unwrap_throw();
             })
@@ -253,6 +303,42 @@ impl<'a, 'b> Views<'b> for Variable<'a> {
         div(&cx).child(input).finish()
     }
 
+    fn textarea(&self, cx: &mut RenderContext<'b>) -> Node<'b> {
+        use dodrio::builder::*;
+
+        let key = String::from_str_in(self.variable.key(), cx.bump).into_bump_str();
+        let mut attributes = vec![attr("name", key), attr("aria-label", key)];
+
+        if let Some(value) = self.placeholder(cx.bump) {
+            attributes.push(attr("placeholder", value))
+        };
+
+        let (min_length, max_length) = self.variable.length();
+        if let Some(length) = min_length {
+            let length = format!(in cx.bump, "{}", length).into_bump_str();
+            attributes.push(attr("minlength", length));
+        }
+
+        if let Some(length) = max_length {
+            let length = format!(in cx.bump, "{}", length).into_bump_str();
+            attributes.push(attr("maxlength", length));
+        }
+
+        let textarea = textarea(&cx)
+            .attributes(attributes)
+            .child(text(self.value(cx.bump)))
+            .on("input", move |_root, _vdom, event| {
+                let target = event.target().unwrap_throw();
+                utils::input_to_location_query(target).unwrap_throw();
+            })
+            .finish();
+
+        div(&cx)
+            .attr("class", "variable-textarea")
+            .child(textarea)
+            .finish()
+    }
+
     fn field(&self, cx: &mut RenderContext<'b>) -> Node<'b> {
         use dodrio::builder::*;
 
@@ -260,7 +346,14 @@ impl<'a, 'b> Views<'b> for Variable<'a> {
             Some(selection) if selection.len() == 1 => self.checkbox(cx, selection),
             Some(selection) if selection.len() <= 2 => self.radio(cx, selection),
             Some(selection) => self.select(cx, selection),
-            None => self.input(cx),
+            None if self.variable.is_secret() => self.input(cx, "password"),
+            None => match self.variable.kind() {
+                VariableKind::BOOLEAN => self.radio(cx, &["true", "false"]),
+                VariableKind::MULTILINE => self.textarea(cx),
+                VariableKind::NUMBER => self.input(cx, "number"),
+                VariableKind::DATE => self.input(cx, "date"),
+                _ => self.input(cx, "text"),
+            },
         };
 
         div(&cx)
@@ -322,7 +415,7 @@ impl<'a, 'b> Views<'b> for Variable<'a> {
             content.extend_from_slice(&[
                 text("The"),
                 a(&cx).attr("href", url).child(text(name)).finish(),
-                text("task can provide this value."),
+                text("task can provide this value, and runs first if you leave it empty."),
             ]);
         } else {
             let mut items = vec![];
@@ -357,7 +450,7 @@ impl<'a, 'b> Views<'b> for Variable<'a> {
             content.extend_from_slice(&[
                 text("There are"),
                 dropdown,
-                text("that can provide this value."),
+                text("that can provide this value, one of which runs first if you leave it empty."),
             ]);
         };
 
@@ -11,10 +11,8 @@ use futures::{future, prelude::*};
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
-use std::time::Duration;
 use wasm_bindgen::UnwrapThrowExt;
 use wasm_bindgen_futures::spawn_local;
-use wasm_timer::{Delay, Instant};
 use web_sys::HtmlElement;
 
 /// The main application controller.
@@ -187,6 +185,10 @@ impl task::Actions for Controller {
                     Some(JobVariableInput { key, value })
                 })
                 .collect(),
+            run_at: None,
+
+            // Empty variables are provided by the tasks advertising them.
+            resolve_variables: Some(true),
         };
 
         let lock = app.cloned_tasks();
@@ -294,135 +296,140 @@ impl task::Actions for Controller {
 
 impl job::Actions for Controller {
     #[allow(clippy::wildcard_enum_match_arm)]
-    fn poll_result(
+    fn watch_result(
         lock: Rc<RefCell<tasks::Tasks>>,
         vdom: VdomWeak,
         id: job::RemoteId,
         task_id: task::Id,
         client: GraphqlService,
     ) -> Box<dyn Future<Item = (), Error = ()> + 'static> {
-        use crate::graphql::{fetch_job_result::*, FetchJobResult};
-        use futures::future::{loop_fn, Loop};
+        use crate::graphql::{job_updated::*, JobUpdated};
         use graphql_client::Response;
+        use job::Status;
 
-        let tries = 0;
-        let future = loop_fn(
-            (tries, client, lock, id, task_id, vdom),
-            |(tries, client, lock, id, task_id, vdom)| {
-                let variables = Variables { id: id.to_string() };
-
-                // After the first request to check if the job finished, each
-                // subsequent request will be done after a small delay, to
-                // prevent flooding the server with requests.
-                let delay = move |response| {
-                    let timeout = if tries == 0 { 0 } else { 500 };
-
-                    Delay::new(Instant::now() + Duration::from_millis(timeout))
-                        .map(|_| response)
-                        .map_err(|_| vec![])
-                };
+        let variables = Variables { id: id.to_string() };
 
-                // Check the response of the server and either return any
-                // errors returned by the server, or pass along the request
-                // body.
-                let handle_response = |response: Response<ResponseData>| {
-                    if let Some(err) = response.errors {
-                        Err(err.iter().map(|e| e.message.to_owned()).collect())
-                    } else if let Some(data) = response.data {
-                        match data.job {
-                            None => Err(vec!["no job data returned".to_owned()]),
-                            Some(job) => Ok(job),
-                        }
-                    } else {
-                        Err(vec!["unknown server error".to_owned()])
-                    }
-                };
+        // Find the job that is being watched, and update its status.
+        let set_status = move |lock: &RefCell<tasks::Tasks>, f: &dyn Fn(&Status) -> Status| {
+            let mut tasks = lock.try_borrow_mut().unwrap_throw();
+            let task = tasks.get_mut(&task_id).unwrap_throw();
+            let job = task
+                .jobs
+                .iter_mut()
+                .find(|j| j.remote_id.as_ref() == Some(&id))
+                .unwrap_throw();
+
+            job.status = f(&job.status);
+            job.status.clone()
+        };
+
+        // Check the response of the server and either return any errors
+        // returned by the server, or pass along the job details.
+        let handle_response = |response: Response<ResponseData>| {
+            if let Some(err) = response.errors {
+                Err(err.iter().map(|e| e.message.to_owned()).collect())
+            } else if let Some(data) = response.data {
+                match data.job_updated {
+                    None => Err(vec!["no job data returned".to_owned()]),
+                    Some(job) => Ok(job),
+                }
+            } else {
+                Err(vec!["unknown server error".to_owned()])
+            }
+        };
 
-                // Update the job status, including the possible error or
-                // success message, based on the server response.
-                let update_state = move |result: Result<FetchJobResultJob, Vec<String>>| {
-                    use job::Status;
-                    use JobStatus::*;
-                    use JobStepStatus as S;
-
-                    let mut tasks = lock.try_borrow_mut().unwrap_throw();
-                    let task = tasks.get_mut(&task_id).unwrap_throw();
-                    let job = task
-                        .jobs
-                        .iter_mut()
-                        .find(|j| j.remote_id.as_ref() == Some(&id))
-                        .unwrap_throw();
-
-                    job.status = match result {
-                        Err(err) => Status::Failed(Some(err.join("\n")).into()),
-                        Ok(result) => match result.status {
-                            SCHEDULED | PENDING | RUNNING => Status::Delivered,
-                            FAILED | CANCELLED | OK => match result.steps.as_ref() {
-                                None => Status::Succeeded(Some("task has no steps").into()),
-                                Some(steps) => {
-                                    let step = match steps
-                                        .iter()
-                                        .find(|step| step.status == JobStepStatus::FAILED)
-                                    {
-                                        Some(s) => s,
-                                        None => steps.last().unwrap_throw(),
-                                    };
-
-                                    match &step.status {
-                                        S::OK => Status::Succeeded((&step.output).into()),
-                                        _ => Status::Failed((&step.output).into()),
+        // Update the job status, including the possible error or success
+        // message, based on the server response.
+        let update_state = {
+            let lock = lock.clone();
+            let set_status = set_status.clone();
+            let vdom = vdom.clone();
+
+            move |result: Result<JobUpdatedJobUpdated, Vec<String>>| -> Result<Status, ()> {
+                use JobStatus::*;
+                use JobStepStatus as S;
+
+                let status = match result {
+                    Err(err) => Status::Failed(Some(err.join("\n")).into()),
+                    Ok(result) => match result.status {
+                        SCHEDULED | PENDING | RUNNING => Status::Delivered,
+                        WAITING => Status::Waiting,
+                        CANCELLED => Status::Cancelled(Some("job was cancelled").into()),
+                        FAILED | OK => match result.steps.as_ref() {
+                            None => Status::Succeeded(Some("task has no steps").into()),
+                            Some(steps) => {
+                                // Show the output of the failed step, or else
+                                // the output of the last step that wasn't
+                                // skipped.
+                                let step =
+                                    steps.iter().find(|step| step.status == S::FAILED).or_else(
+                                        || steps.iter().rev().find(|s| s.status != S::SKIPPED),
+                                    );
+
+                                match step {
+                                    None => {
+                                        Status::Succeeded(Some("all steps were skipped").into())
+                                    }
+                                    Some(step) if step.status == S::OK => {
+                                        Status::Succeeded((&step.output).into())
                                     }
+                                    Some(step) => Status::Failed((&step.output).into()),
                                 }
-                            },
-                            _unknown => unreachable!(),
+                            }
                         },
-                    };
+                        _unknown => unreachable!(),
+                    },
+                };
 
-                    if tries > 120 && job.is_running() {
-                        job.status =
-                            Status::Failed(Some("timeout waiting for job to complete").into());
-                    }
+                let status = set_status(&lock, &|_| status.clone());
+                vdom.schedule_render();
 
-                    let status = job.status.clone();
-                    drop(tasks);
+                Ok(status)
+            }
+        };
 
-                    Ok((lock, id, task_id, status))
-                };
+        // If the subscription ended before the job finished running (for
+        // example, because the connection was lost), mark the job as failed.
+        let finish = move |_| {
+            let _ = set_status(&lock, &|status| match status {
+                Status::Delivered | Status::Waiting => {
+                    Status::Failed(Some("connection lost before the job completed").into())
+                }
+                status => status.clone(),
+            });
 
-                // Depending on the new job status, either keep polling the
-                // server for the final status, or break out of the loop.
-                let new_client = client.clone();
-                let retry_or_break = move |(lock, id, task_id, status)| {
-                    vdom.schedule_render();
-
-                    match status {
-                        job::Status::Delivered => Ok(Loop::Continue((
-                            tries + 1,
-                            new_client,
-                            lock,
-                            id,
-                            task_id,
-                            vdom,
-                        ))),
-                        job::Status::Created => unreachable!(),
-                        _ => Ok(Loop::Break(())),
-                    }
-                };
+            vdom.schedule_render();
+            Ok(())
+        };
 
-                client
-                    .request(FetchJobResult, variables)
-                    .map_err(|err| vec![err.to_string()])
-                    .and_then(delay)
-                    .and_then(handle_response)
-                    .then(update_state)
-                    .and_then(retry_or_break)
-            },
-        );
+        // Keep updating the job status, until the job is no longer running.
+        let future = future::result(client.subscribe(JobUpdated, variables))
+            .flatten_stream()
+            .map_err(|err| vec![err.to_string()])
+            .and_then(handle_response)
+            .then(update_state)
+            .take_while(|status| Ok(status == &Status::Delivered || status == &Status::Waiting))
+            .for_each(|_| Ok(()))
+            .then(finish);
 
         Box::new(future)
     }
 
-    fn abort(_root: &mut dyn RootRender, _vdom: VdomWeak, _id: job::RemoteId) {}
+    fn abort(root: &mut dyn RootRender, _vdom: VdomWeak, id: job::RemoteId) {
+        use crate::graphql::{cancel_job::Variables, CancelJob};
+
+        let app = root.unwrap_mut::<App>();
+        let variables = Variables { id: id.to_string() };
+
+        // The server response is ignored, the job status is updated by the
+        // active `watch_result` subscription once the job is cancelled.
+        spawn_local(
+            app.client
+                .request(CancelJob, variables)
+                .map(|_| ())
+                .map_err(|_| ()),
+        );
+    }
 }
 
 impl statistics::Actions for Controller {
@@ -453,12 +460,17 @@ impl statistics::Actions for Controller {
                     .filter(|j| j.status == JobStatus::RUNNING)
                     .count();
 
+                let waiting = jobs
+                    .iter()
+                    .filter(|j| j.status == JobStatus::WAITING)
+                    .count();
+
                 let failed = jobs
                     .iter()
                     .filter(|j| j.status == JobStatus::FAILED)
                     .count();
 
-                stats.update(tasks.len(), running, failed);
+                stats.update(tasks.len(), running, waiting, failed);
                 vdom.render().map_err(|_| ())
             });
 
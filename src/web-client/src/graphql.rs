@@ -1,7 +1,10 @@
-//! The derived GraphQL query and mutation structures.
+//! The derived GraphQL query, mutation, and subscription structures.
 
 use graphql_client::GraphQLQuery;
 
+/// The `DateTimeUtc` scalar, serialized as an RFC 3339 formatted string.
+type DateTimeUtc = String;
+
 /// Fetch the global application statistics from the server.
 #[derive(GraphQLQuery)]
 #[graphql(
@@ -38,14 +41,23 @@ pub(crate) struct FetchTaskDetails;
 )]
 pub(crate) struct CreateJob;
 
-/// Fetch the details of a job.
+/// Cancel a job that hasn't finished running yet.
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "schema.graphql",
+    query_path = "queries/cancel_job.graphql",
+    response_derives = "Debug, Clone"
+)]
+pub(crate) struct CancelJob;
+
+/// Subscribe to changes of a job, until the job finished running.
 #[derive(GraphQLQuery)]
 #[graphql(
     schema_path = "schema.graphql",
-    query_path = "queries/fetch_job_result.graphql",
+    query_path = "queries/job_updated.graphql",
     response_derives = "Debug, Clone"
 )]
-pub(crate) struct FetchJobResult;
+pub(crate) struct JobUpdated;
 
 /// Fetch the details of the active session (if any).
 #[derive(GraphQLQuery)]
@@ -1,7 +1,7 @@
 //! A `Job` is an instance of a `Task` that is either scheduled to run, is
 //! actively running on the server, or ran in the past.
 
-use crate::graphql::fetch_job_result::FetchJobResultJobStepsOutput;
+use crate::graphql::job_updated::JobUpdatedJobUpdatedStepsOutput;
 use crate::model::{task, tasks};
 use crate::service::GraphqlService;
 use dodrio::{RootRender, VdomWeak};
@@ -39,8 +39,8 @@ impl Job {
         use Status::*;
 
         match self.status {
-            Created | Delivered => false,
-            Succeeded(_) | Failed(_) => true,
+            Created | Delivered | Waiting => false,
+            Succeeded(_) | Failed(_) | Cancelled(_) => true,
         }
     }
 
@@ -99,8 +99,8 @@ where
     }
 }
 
-impl From<&FetchJobResultJobStepsOutput> for Output {
-    fn from(input: &FetchJobResultJobStepsOutput) -> Self {
+impl From<&JobUpdatedJobUpdatedStepsOutput> for Output {
+    fn from(input: &JobUpdatedJobUpdatedStepsOutput) -> Self {
         Self {
             html: input.html.clone(),
             text: input.text.clone(),
@@ -117,11 +117,18 @@ pub(crate) enum Status {
     /// The job was successfully delivered to the server.
     Delivered,
 
+    /// The job is paused on the server, until someone approves or rejects
+    /// one of its steps.
+    Waiting,
+
     /// The server reported a successful run of the job.
     Succeeded(Output),
 
     /// The server either rejected the job, or the job failed while running.
     Failed(Output),
+
+    /// The job was cancelled before it finished running.
+    Cancelled(Output),
 }
 
 impl Default for Status {
@@ -137,8 +144,10 @@ impl fmt::Display for Status {
         match self {
             Created => f.write_str("status-created"),
             Delivered => f.write_str("status-delivered"),
+            Waiting => f.write_str("status-waiting"),
             Succeeded(_) => f.write_str("status-succeeded"),
             Failed(_) => f.write_str("status-failed"),
+            Cancelled(_) => f.write_str("status-cancelled"),
         }
     }
 }
@@ -163,14 +172,13 @@ impl fmt::Display for RemoteId {
 /// The actions a controller has to implement to bridge between the UI and the
 /// model.
 pub(crate) trait Actions {
-    /// Asks the server for the result of a job.
+    /// Subscribes to updates of a job on the server.
     ///
-    /// If the job is still pending or running, this function should block until
-    /// the job either failed, or succeeded.
+    /// The job is updated every time the server reports a change, until the
+    /// job either failed, succeeded, or was cancelled.
     ///
-    /// Once a final state is reached, the job must be updated with the new
-    /// details.
-    fn poll_result(
+    /// The returned future resolves once a final state is reached.
+    fn watch_result(
         tasks: Rc<RefCell<tasks::Tasks>>,
         vdom: VdomWeak,
         id: RemoteId,
@@ -182,5 +190,8 @@ pub(crate) trait Actions {
     ///
     /// This function can be used to stop a running job if the results of the
     /// job are no longer relevant.
+    ///
+    /// The job status is updated by [`Actions::watch_result`], once the server
+    /// reports the job as cancelled.
     fn abort(root: &mut dyn RootRender, vdom: VdomWeak, id: RemoteId);
 }
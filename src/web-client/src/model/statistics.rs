@@ -16,6 +16,11 @@ pub(crate) struct Statistics {
     /// The value is optional to allow for lazy-loading of the value.
     pub(crate) running_jobs: Option<u32>,
 
+    /// The number of jobs on the server waiting for approval.
+    ///
+    /// The value is optional to allow for lazy-loading of the value.
+    pub(crate) waiting_jobs: Option<u32>,
+
     /// The number of failed jobs on the server.
     ///
     /// The value is optional to allow for lazy-loading of the value.
@@ -25,9 +30,10 @@ pub(crate) struct Statistics {
 impl Statistics {
     /// Update the model to contain up-to-date metrics.
     #[allow(clippy::cast_possible_truncation)]
-    pub(crate) fn update(&mut self, total: usize, running: usize, failed: usize) {
+    pub(crate) fn update(&mut self, total: usize, running: usize, waiting: usize, failed: usize) {
         self.total_tasks = Some(total as u32);
         self.running_jobs = Some(running as u32);
+        self.waiting_jobs = Some(waiting as u32);
         self.failed_jobs = Some(failed as u32);
     }
 }
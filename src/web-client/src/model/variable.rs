@@ -1,7 +1,7 @@
 //! A variable belonging to a task.
 
 use crate::graphql::fetch_task_details::{
-    FetchTaskDetailsTaskVariables, FetchTaskDetailsTaskVariablesValueAdvertisers,
+    FetchTaskDetailsTaskVariables, FetchTaskDetailsTaskVariablesValueAdvertisers, VariableKind,
 };
 use crate::model::task::Id as TaskId;
 
@@ -42,8 +42,25 @@ impl<'a> Variable<'a> {
     }
 
     /// An optional default value set by the server for the variable.
+    ///
+    /// Default values containing template expressions are rendered by the
+    /// server when no value is provided, so they are not returned here.
     pub(crate) fn default_value(&self) -> Option<&str> {
-        self.inner.default_value.as_ref().map(String::as_str)
+        self.inner
+            .default_value
+            .as_ref()
+            .map(String::as_str)
+            .filter(|v| !v.contains("{{") && !v.contains("{%"))
+    }
+
+    /// Whether the variable can be left empty when running the task.
+    pub(crate) fn is_optional(&self) -> bool {
+        self.inner.constraints.optional
+    }
+
+    /// Whether the value of the variable should be hidden while entered.
+    pub(crate) fn is_secret(&self) -> bool {
+        self.inner.secret
     }
 
     /// An optional example value set by the server for the variable.
@@ -52,14 +69,39 @@ impl<'a> Variable<'a> {
     }
 
     /// An optional constraint on the set of values the variable can have.
+    ///
+    /// These values are either defined on the task itself, or provided by a
+    /// processor on the server.
     pub(crate) fn selection_constraint(&self) -> Option<Vec<&str>> {
         self.inner
-            .constraints
-            .selection
+            .options
             .as_ref()
             .map(|v| v.iter().map(String::as_str).collect())
     }
 
+    /// The kind of values the variable accepts.
+    pub(crate) fn kind(&self) -> &VariableKind {
+        &self.inner.constraints.kind
+    }
+
+    /// The optional minimum and maximum value of a number variable.
+    pub(crate) fn range(&self) -> (Option<f64>, Option<f64>) {
+        (self.inner.constraints.min, self.inner.constraints.max)
+    }
+
+    /// An optional regular expression the entire value has to match.
+    pub(crate) fn pattern(&self) -> Option<&str> {
+        self.inner.constraints.pattern.as_ref().map(String::as_str)
+    }
+
+    /// The optional minimum and maximum number of characters of the value.
+    pub(crate) fn length(&self) -> (Option<i64>, Option<i64>) {
+        (
+            self.inner.constraints.min_length,
+            self.inner.constraints.max_length,
+        )
+    }
+
     /// Return a list of task details that advertise their capability of
     /// providing a value for this variable.
     pub(crate) fn value_advertisers(&self) -> Vec<ValueAdvertiser<'a>> {
@@ -1,10 +1,31 @@
 //! The GraphQL service is a thin wrapper around a GraphQL-capable HTTP client.
+//!
+//! Subscriptions are served over a WebSocket connection, using the
+//! `graphql-ws` protocol.
 
+use crate::utils;
 use crate::CookieService;
 use failure::{Compat, Fail};
 use futures::future::Future;
-use graphql_client::{web, GraphQLQuery, Response};
+use futures::stream::Stream;
+use futures::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::{Async, Poll};
+use graphql_client::{web, Error as GraphqlError, GraphQLQuery, QueryBody, Response};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::rc::Rc;
 use std::{error, fmt};
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue, UnwrapThrowExt};
+use web_sys::{MessageEvent, WebSocket};
+
+/// The WebSocket sub-protocol used to communicate with the server.
+const SUBSCRIPTION_PROTOCOL: &str = "graphql-ws";
+
+/// The ID of the operation started on a subscription connection.
+///
+/// Each subscription uses its own connection, so the ID is always the same.
+const SUBSCRIPTION_ID: &str = "1";
 
 /// The GraphQL service.
 #[derive(Clone)]
@@ -25,6 +46,9 @@ pub(crate) enum Error {
 
     /// Authentication error.
     Authentication,
+
+    /// Subscription connection error.
+    Subscription(String),
 }
 
 impl fmt::Display for Error {
@@ -32,6 +56,7 @@ impl fmt::Display for Error {
         match self {
             Error::Client(err) => write!(f, "{}", err),
             Error::Authentication => f.write_str("authentication"),
+            Error::Subscription(err) => write!(f, "subscription: {}", err),
         }
     }
 }
@@ -40,7 +65,7 @@ impl error::Error for Error {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match self {
             Error::Client(err) => Some(err),
-            Error::Authentication => None,
+            Error::Authentication | Error::Subscription(_) => None,
         }
     }
 }
@@ -71,15 +96,240 @@ impl Service {
         client
             .call(query, variables)
             .map_err(|err| Error::Client(err.compat()))
-            .and_then(move |response| {
-                if let Some(errors) = &response.errors {
-                    if errors.iter().any(|e| e.message == "Unauthorized") {
-                        cookie.remove("session");
-                        return futures::future::err(Error::Authentication);
-                    }
+            .and_then(move |response| authorize(&cookie, response))
+    }
+
+    /// Subscribe to updates from the GraphQL server.
+    ///
+    /// A new WebSocket connection is opened for the subscription. The returned
+    /// stream yields a response every time the subscribed data changes, and
+    /// ends once the server completes the subscription, or the connection is
+    /// closed.
+    ///
+    /// The connection is closed once the subscription is dropped.
+    pub(crate) fn subscribe<Q: GraphQLQuery + 'static>(
+        &self,
+        _query: Q,
+        variables: Q::Variables,
+    ) -> Result<Subscription<Q::ResponseData>, Error> {
+        let socket = WebSocket::new_with_str(&self.subscription_endpoint(), SUBSCRIPTION_PROTOCOL)
+            .map_err(|_| Error::Subscription("unable to connect".to_owned()))?;
+
+        let (sender, receiver) = mpsc::unbounded();
+        let sender = Rc::new(RefCell::new(Some(sender)));
+
+        // Once connected, authenticate the connection (if a session is
+        // active), and start the subscription.
+        let authorization = self.cookie.get("session");
+        let body = Q::build_query(variables);
+        let ws = socket.clone();
+        let on_open: Closure<dyn FnMut()> = Closure::wrap(Box::new(move || {
+            let params = ConnectionParams {
+                authorization: authorization.clone(),
+            };
+
+            send(
+                &ws,
+                &ClientMessage::<Q::Variables>::ConnectionInit { payload: params },
+            );
+            send(
+                &ws,
+                &ClientMessage::Start {
+                    id: SUBSCRIPTION_ID,
+                    payload: &body,
+                },
+            );
+        }));
+
+        // Forward all subscription results to the stream.
+        let cookie = self.cookie.clone();
+        let tx = sender.clone();
+        let handle_message = move |event: MessageEvent| {
+            let message = event
+                .data()
+                .as_string()
+                .and_then(|text| js_sys::JSON::parse(&text).ok())
+                .and_then(|value| value.into_serde().ok());
+
+            let result = match message {
+                Some(ServerMessage::Data { payload }) => authorize(&cookie, payload),
+                Some(ServerMessage::Error { payload }) => authorize(
+                    &cookie,
+                    Response {
+                        data: None,
+                        errors: Some(payload),
+                    },
+                ),
+                Some(ServerMessage::ConnectionError { payload }) => {
+                    Err(Error::Subscription(payload.message))
+                }
+                Some(ServerMessage::Complete) => {
+                    drop(tx.borrow_mut().take());
+                    return;
                 }
+                Some(ServerMessage::ConnectionAck) | Some(ServerMessage::Ka) | None => return,
+            };
 
-                futures::future::ok(response)
-            })
+            if let Some(tx) = tx.borrow().as_ref() {
+                let _ = tx.unbounded_send(result);
+            }
+        };
+        let on_message: Closure<dyn FnMut(_)> = Closure::wrap(Box::new(handle_message));
+
+        // End the stream once the connection is closed.
+        let tx = sender;
+        let on_close: Closure<dyn FnMut()> = Closure::wrap(Box::new(move || {
+            drop(tx.borrow_mut().take());
+        }));
+
+        socket.set_onopen(Some(on_open.as_ref().unchecked_ref()));
+        socket.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+        socket.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+
+        Ok(Subscription {
+            socket,
+            receiver,
+            _callbacks: (on_open, on_message, on_close),
+        })
     }
+
+    /// The (absolute) WebSocket URL of the subscription endpoint.
+    fn subscription_endpoint(&self) -> String {
+        let location = utils::window().location();
+        let protocol = match location.protocol().unwrap_throw().as_str() {
+            "https:" => "wss:",
+            _ => "ws:",
+        };
+
+        format!(
+            "{}//{}{}/subscriptions",
+            protocol,
+            location.host().unwrap_throw(),
+            self.endpoint
+        )
+    }
+}
+
+/// An active GraphQL subscription.
+///
+/// The subscription is a stream of responses, sent by the server every time
+/// the subscribed data changes.
+#[derive(Debug)]
+pub(crate) struct Subscription<T> {
+    /// The WebSocket connection over which the responses are received.
+    socket: WebSocket,
+
+    /// The receiving end of the responses forwarded by the socket callbacks.
+    receiver: UnboundedReceiver<Result<Response<T>, Error>>,
+
+    /// The socket callbacks, which have to stay alive for as long as the
+    /// socket is in use.
+    _callbacks: (
+        Closure<dyn FnMut()>,
+        Closure<dyn FnMut(MessageEvent)>,
+        Closure<dyn FnMut()>,
+    ),
+}
+
+impl<T> Stream for Subscription<T> {
+    type Item = Response<T>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        match self.receiver.poll() {
+            Ok(Async::Ready(Some(result))) => result.map(|response| Async::Ready(Some(response))),
+            Ok(Async::Ready(None)) | Err(()) => Ok(Async::Ready(None)),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+        }
+    }
+}
+
+impl<T> Drop for Subscription<T> {
+    fn drop(&mut self) {
+        let _ = self.socket.close();
+    }
+}
+
+/// A message sent to the server over a subscription connection.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage<'a, T> {
+    /// Initialize the connection.
+    ConnectionInit {
+        /// The connection parameters.
+        payload: ConnectionParams,
+    },
+
+    /// Start a new subscription.
+    Start {
+        /// The ID of the subscription.
+        id: &'a str,
+
+        /// The subscription document and its variables.
+        payload: &'a QueryBody<T>,
+    },
+}
+
+/// The parameters sent to the server when initializing a connection.
+#[derive(Serialize)]
+struct ConnectionParams {
+    /// The session token, if any.
+    authorization: Option<String>,
+}
+
+/// A message received from the server over a subscription connection.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage<T> {
+    /// The server accepted the connection.
+    ConnectionAck,
+
+    /// The server rejected the connection.
+    ConnectionError {
+        /// The reason the connection was rejected.
+        payload: GraphqlError,
+    },
+
+    /// A new subscription result.
+    Data {
+        /// The GraphQL response.
+        payload: Response<T>,
+    },
+
+    /// The subscription failed.
+    Error {
+        /// The errors returned by the server.
+        payload: Vec<GraphqlError>,
+    },
+
+    /// The server completed the subscription.
+    Complete,
+
+    /// A keep-alive message.
+    Ka,
+}
+
+/// Serialize the message, and send it over the socket.
+fn send<T: Serialize>(socket: &WebSocket, message: &ClientMessage<'_, T>) {
+    let text = JsValue::from_serde(message)
+        .ok()
+        .and_then(|value| js_sys::JSON::stringify(&value).ok())
+        .and_then(|text| text.as_string());
+
+    if let Some(text) = text {
+        let _ = socket.send_with_str(&text);
+    }
+}
+
+/// Return an authentication error if the response reports the request as
+/// unauthorized, removing the (invalid) session cookie.
+fn authorize<T>(cookie: &CookieService, response: Response<T>) -> Result<Response<T>, Error> {
+    if let Some(errors) = &response.errors {
+        if errors.iter().any(|e| e.message == "Unauthorized") {
+            cookie.remove("session");
+            return Err(Error::Authentication);
+        }
+    }
+
+    Ok(response)
 }
@@ -3,7 +3,7 @@
 use js_sys::Array;
 use std::collections::HashMap;
 use wasm_bindgen::{JsCast, UnwrapThrowExt};
-use web_sys::{HtmlInputElement, HtmlSelectElement, Url};
+use web_sys::{HtmlInputElement, HtmlSelectElement, HtmlTextAreaElement, Url};
 
 /// Get the current location hash, if any.
 pub(crate) fn hash() -> Option<String> {
@@ -39,6 +39,9 @@ where
     } else if element.has_type::<HtmlSelectElement>() {
         let el = element.unchecked_into::<HtmlSelectElement>();
         (el.name(), el.value())
+    } else if element.has_type::<HtmlTextAreaElement>() {
+        let el = element.unchecked_into::<HtmlTextAreaElement>();
+        (el.name(), el.value())
     } else {
         return Err(());
     };